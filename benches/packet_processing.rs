@@ -1,123 +1,251 @@
-use std::sync::Arc;
-
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-
-use laminar::{net::VirtualConnection, DeliveryMethod, NetworkConfig, ProtocolVersion};
+//! Measures how fast packets are processed on their way to and from the socket.
+//!
+//! The benchmarks use internals of the crate, run them with `cargo bench --features bench`.
+
+use std::{
+    net::SocketAddr,
+    sync::mpsc::{Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use laminar::{
+    config::SocketConfig,
+    net::{BufferPool, LaminarSocket, VirtualConnection},
+    Packet, SocketEvent,
+};
 
 use criterion::{criterion_group, criterion_main, Criterion};
 
 const SERVER_ADDR: &str = "127.0.0.1:12345";
-const CLIENT_ADDR: &str = "127.0.0.1:12346";
-
-fn process_packet_before_send(
-    connection: &mut VirtualConnection,
-    _config: &NetworkConfig,
-    delivery_method: DeliveryMethod,
-) {
-    let payload = vec![1, 2, 3, 4, 5];
+/// Large enough to be split into 3 fragments with the default config.
+const FRAGMENTED_PAYLOAD_SIZE: usize = 4000;
+
+/// A client and a server socket on the loopback interface, which are polled by the benchmark
+/// instead of by their own threads.
+struct Endpoints {
+    client: LaminarSocket,
+    client_packets: Sender<Packet>,
+    client_events: Receiver<SocketEvent>,
+    server: LaminarSocket,
+    server_events: Receiver<SocketEvent>,
+    server_address: SocketAddr,
+}
 
-    let _packet_data = connection
-        .process_outgoing(&payload, delivery_method)
-        .unwrap();
+impl Endpoints {
+    fn new() -> Self {
+        // Acknowledge every packet right away, so reliable packets are never sent again, and don't
+        // let the congestion control hold packets back.
+        let config = SocketConfig::builder()
+            .ack_delay(Duration::from_millis(0))
+            .good_send_rate(10_000_000)
+            .bad_send_rate(10_000_000)
+            .build()
+            .unwrap();
+        let (client, client_packets, client_events) =
+            LaminarSocket::bind("127.0.0.1:0", config.clone()).unwrap();
+        let (server, _, server_events) = LaminarSocket::bind("127.0.0.1:0", config).unwrap();
+        let server_address = server.local_addr().unwrap();
+
+        let mut endpoints = Self {
+            client,
+            client_packets,
+            client_events,
+            server,
+            server_events,
+            server_address,
+        };
+        // Get the connection going before we start measuring.
+        endpoints.send(Packet::unreliable(server_address, vec![0]));
+        endpoints
+    }
+
+    /// Sends a packet from the client to the server and lets the server process it.
+    fn send(&mut self, packet: Packet) {
+        self.client_packets.send(packet).unwrap();
+
+        let now = Instant::now();
+        self.client.manual_poll(now);
+        self.server.manual_poll(now);
+
+        while self.server_events.try_recv().is_ok() {}
+        while self.client_events.try_recv().is_ok() {}
+    }
 }
 
-fn send_unreliable_benchmark(c: &mut Criterion) {
-    let config = NetworkConfig::default();
-    let mut connection = VirtualConnection::new(
-        SERVER_ADDR.parse().unwrap(),
-        Arc::new(NetworkConfig::default()),
+/// Creates a connection that receives packets into its own pool of buffers.
+fn create_connection(address: SocketAddr, config: &SocketConfig) -> VirtualConnection {
+    let buffer_pool = BufferPool::new(
+        config.payload_pool_size(),
+        config.receive_buffer_size_bytes(),
     );
+    VirtualConnection::new(address, config, buffer_pool)
+}
 
-    c.bench_function("process unreliable before send", move |b| {
+/// Measures the processing of an outgoing packet up to the moment its datagrams are handed to the
+/// socket, which are written into the same buffer over and over again like the socket does.
+fn process_benchmark(
+    c: &mut Criterion,
+    name: &str,
+    payload_size: usize,
+    create_packet: fn(SocketAddr, Vec<u8>) -> Packet,
+) {
+    let config = SocketConfig::default();
+    let address: SocketAddr = SERVER_ADDR.parse().unwrap();
+    let mut connection = create_connection(address, &config);
+    let mut buffer = Vec::with_capacity(config.max_mtu() as usize);
+
+    c.bench_function(name, move |b| {
         b.iter(|| {
-            process_packet_before_send(
-                &mut connection,
-                &config,
-                DeliveryMethod::UnreliableUnordered,
-            )
+            let packet = create_packet(address, vec![1; payload_size]);
+            let processed = connection.process_outgoing(packet).unwrap();
+            let fragment_size = connection.fragment_size();
+            let fragment_count = processed
+                .fragment_count(fragment_size, config.max_fragments())
+                .unwrap();
+            for fragment_id in 0..fragment_count {
+                processed
+                    .write_fragment(fragment_id, fragment_size, &mut buffer)
+                    .unwrap();
+            }
         })
     });
 }
 
-fn send_reliable_benchmark(c: &mut Criterion) {
-    let config = NetworkConfig::default();
-    let mut connection = VirtualConnection::new(
-        SERVER_ADDR.parse().unwrap(),
-        Arc::new(NetworkConfig::default()),
+fn process_unreliable_benchmark(c: &mut Criterion) {
+    process_benchmark(c, "process unreliable before send", 5, Packet::unreliable);
+}
+
+fn process_reliable_benchmark(c: &mut Criterion) {
+    process_benchmark(
+        c,
+        "process reliable before send",
+        5,
+        Packet::reliable_unordered,
     );
+}
 
-    c.bench_function("process reliable before send", move |b| {
-        b.iter(|| {
-            process_packet_before_send(&mut connection, &config, DeliveryMethod::ReliableUnordered)
-        })
-    });
+fn process_fragmented_benchmark(c: &mut Criterion) {
+    process_benchmark(
+        c,
+        "process fragmented reliable before send",
+        FRAGMENTED_PAYLOAD_SIZE,
+        Packet::reliable_unordered,
+    );
 }
 
-fn process_packet_when_received(connection: &mut VirtualConnection, data: &[u8]) {
-    connection.process_incoming(&data).unwrap().unwrap();
+/// Measures the processing of the datagrams of a received packet, up to the moment the packet is
+/// handed to the application. The datagrams are sent by another connection beforehand, so every
+/// packet has a new sequence number.
+fn receive_benchmark(
+    c: &mut Criterion,
+    name: &str,
+    payload_size: usize,
+    create_packet: fn(SocketAddr, Vec<u8>) -> Packet,
+) {
+    let config = SocketConfig::default();
+    let address: SocketAddr = SERVER_ADDR.parse().unwrap();
+    let mut sender = create_connection(address, &config);
+    let mut receiver = create_connection(address, &config);
+
+    c.bench_function(name, move |b| {
+        b.iter_with_setup(
+            || {
+                let packet = create_packet(address, vec![1; payload_size]);
+                let processed = sender.process_outgoing(packet).unwrap();
+                let fragment_size = sender.fragment_size();
+                let fragment_count = processed
+                    .fragment_count(fragment_size, config.max_fragments())
+                    .unwrap();
+                (0..fragment_count)
+                    .map(|fragment_id| {
+                        let mut datagram = Vec::new();
+                        processed
+                            .write_fragment(fragment_id, fragment_size, &mut datagram)
+                            .unwrap();
+                        datagram
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |datagrams| {
+                // The packets are dropped right away, like an application that is done with them.
+                for datagram in &datagrams {
+                    receiver.process_incoming(datagram).unwrap();
+                }
+                datagrams
+            },
+        )
+    });
 }
 
-/// This is mimicking the `HeaderParser for StandardHeader` implementation which is no longer
-/// visible externally
-fn standard_header_bytes(delivery_method: DeliveryMethod) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    buffer.write_u32::<BigEndian>(ProtocolVersion::get_crc32());
-    // Represents a standard `Packet`
-    buffer.write_u8(0);
-    buffer.write_u8(delivery_method as u8);
-    buffer
+fn receive_unreliable_benchmark(c: &mut Criterion) {
+    receive_benchmark(
+        c,
+        "process unreliable packet on receive",
+        500,
+        Packet::unreliable,
+    );
 }
 
-/// This is mimicking the `HeaderParser for AckedPacketHeader` implementation which is no longer
-/// visible externally
-fn acked_header_bytes(
-    delivery_method: DeliveryMethod,
-    seq: u16,
-    ack_seq: u16,
-    ack_field: u32,
-) -> Vec<u8> {
-    let mut buffer = standard_header_bytes(delivery_method);
-    buffer.write_u16::<BigEndian>(seq);
-    buffer.write_u16::<BigEndian>(ack_seq);
-    buffer.write_u32::<BigEndian>(ack_field);
-    buffer
+fn receive_reliable_benchmark(c: &mut Criterion) {
+    receive_benchmark(
+        c,
+        "process reliable packet on receive",
+        500,
+        Packet::reliable_unordered,
+    );
 }
 
-fn receive_unreliable_benchmark(c: &mut Criterion) {
-    let mut connection = VirtualConnection::new(
-        SERVER_ADDR.parse().unwrap(),
-        Arc::new(NetworkConfig::default()),
+fn receive_fragmented_benchmark(c: &mut Criterion) {
+    receive_benchmark(
+        c,
+        "process fragmented reliable packet on receive",
+        FRAGMENTED_PAYLOAD_SIZE,
+        Packet::reliable_unordered,
     );
+}
 
-    // setup fake received bytes.
-    let mut buffer = standard_header_bytes(DeliveryMethod::UnreliableUnordered);
-    buffer.append(&mut vec![1; 500]);
+/// Measures sending a packet from one socket to another on the loopback interface.
+fn send_benchmark(
+    c: &mut Criterion,
+    name: &str,
+    payload_size: usize,
+    create_packet: fn(SocketAddr, Vec<u8>) -> Packet,
+) {
+    let mut endpoints = Endpoints::new();
+    let server_address = endpoints.server_address;
 
-    c.bench_function("process unreliable packet on receive", move |b| {
-        b.iter(|| process_packet_when_received(&mut connection, &buffer))
+    c.bench_function(name, move |b| {
+        b.iter(|| endpoints.send(create_packet(server_address, vec![1; payload_size])))
     });
 }
 
-fn receive_reliable_benchmark(c: &mut Criterion) {
-    let mut connection = VirtualConnection::new(
-        SERVER_ADDR.parse().unwrap(),
-        Arc::new(NetworkConfig::default()),
-    );
+fn send_unreliable_benchmark(c: &mut Criterion) {
+    send_benchmark(c, "send unreliable packet", 5, Packet::unreliable);
+}
 
-    // setup fake received bytes.
-    let mut buffer = acked_header_bytes(DeliveryMethod::ReliableUnordered, 0, 1, 2);
-    buffer.append(&mut vec![1; 500]);
+fn send_reliable_benchmark(c: &mut Criterion) {
+    send_benchmark(c, "send reliable packet", 5, Packet::reliable_unordered);
+}
 
-    c.bench_function("process reliable packet on receive", move |b| {
-        b.iter(|| process_packet_when_received(&mut connection, &buffer))
-    });
+fn send_fragmented_benchmark(c: &mut Criterion) {
+    send_benchmark(
+        c,
+        "send fragmented reliable packet",
+        FRAGMENTED_PAYLOAD_SIZE,
+        Packet::reliable_unordered,
+    );
 }
 
 criterion_group!(
     benches,
-    send_reliable_benchmark,
-    send_unreliable_benchmark,
+    process_unreliable_benchmark,
+    process_reliable_benchmark,
+    process_fragmented_benchmark,
     receive_unreliable_benchmark,
-    receive_reliable_benchmark
+    receive_reliable_benchmark,
+    receive_fragmented_benchmark,
+    send_unreliable_benchmark,
+    send_reliable_benchmark,
+    send_fragmented_benchmark
 );
 criterion_main!(benches);
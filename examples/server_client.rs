@@ -8,14 +8,18 @@ use laminar::{
     net::{LaminarSocket, SocketEvent},
     Packet,
 };
-use std::thread;
+use std::{thread, time::Duration};
 
 const SERVER: &str = "127.0.0.1:12351";
 
 fn server() -> Result<(), io::Error> {
-    let (mut socket, packet_sender, event_receiver) =
-        LaminarSocket::bind(SERVER, SocketConfig::default())?;
-    let _thread = thread::spawn(move || socket.start_polling());
+    // Chat clients can be quiet for a long time, so don't forget about them too soon.
+    let config = SocketConfig::builder()
+        .idle_connection_timeout(Duration::from_secs(60))
+        .build()?;
+    let (mut socket, packet_sender, event_receiver) = LaminarSocket::bind(SERVER, config)?;
+    let handle = socket.handle();
+    let thread = thread::spawn(move || socket.start_polling());
 
     println!("Listening for connections to {}", SERVER);
 
@@ -40,20 +44,35 @@ fn server() -> Result<(), io::Error> {
                     ))
                     .unwrap();
             }
+            SocketEvent::Connect(address) => {
+                println!("Client connected: {}", address);
+            }
+            SocketEvent::Disconnect(address) => {
+                println!("Client disconnected: {}", address);
+            }
             SocketEvent::TimeOut(address) => {
                 println!("Client timed out: {}", address);
             }
-            _ => {}
+            // We don't send packets with a ticket, see `SocketHandle::send`.
+            SocketEvent::Acked(_) | SocketEvent::Lost(_) | SocketEvent::QualityChanged(..) => {}
+            // The client doesn't send large messages, see `SocketHandle::send_transfer`.
+            SocketEvent::TransferData(..)
+            | SocketEvent::TransferProgress(..)
+            | SocketEvent::TransferCompleted(..)
+            | SocketEvent::TransferCancelled(..) => {}
         }
     }
 
-    Ok(())
+    // Let the connected clients know we are going away and wait for the socket to stop.
+    handle.shutdown()?;
+    thread.join().expect("The socket thread panicked")
 }
 
 fn client() -> Result<(), io::Error> {
     let (mut socket, packet_sender, event_receiver) =
         LaminarSocket::bind("127.0.0.1:12352", SocketConfig::default())?;
     println!("Connected on {}", socket.local_addr()?);
+    let handle = socket.handle();
     let _thread = thread::spawn(move || socket.start_polling());
 
     let server = SERVER.parse().unwrap();
@@ -76,19 +95,34 @@ fn client() -> Result<(), io::Error> {
             .unwrap();
 
         if line == "Bye!" {
+            handle.disconnect(server)?;
             break;
         }
 
-        match event_receiver.recv().unwrap() {
-            SocketEvent::Packet(packet) => {
-                if packet.address() == server {
-                    println!("Server sent: {}", String::from_utf8_lossy(packet.payload()));
-                } else {
-                    println!("Unknown sender.");
+        loop {
+            match event_receiver.recv().unwrap() {
+                SocketEvent::Connect(address) => {
+                    println!("Connected to {}", address);
+                    continue;
                 }
+                SocketEvent::Packet(packet) => {
+                    if packet.address() == server {
+                        println!("Server sent: {}", String::from_utf8_lossy(packet.payload()));
+                    } else {
+                        println!("Unknown sender.");
+                    }
+                }
+                SocketEvent::TimeOut(_) => {}
+                _ => println!("Silence.."),
             }
-            SocketEvent::TimeOut(_) => {}
-            _ => println!("Silence.."),
+            break;
+        }
+    }
+
+    // Wait until the socket has let the server know that we are leaving.
+    for event in event_receiver.iter() {
+        if let SocketEvent::Disconnect(_) = event {
+            break;
         }
     }
 
@@ -1,13 +1,109 @@
+use crate::{
+    errors::ConfigError,
+    net::MIN_MTU,
+    packet::headers::{max_header_size, MAX_ACK_WINDOW_SIZE},
+    sequence_buffer::MAX_FRAGMENTS_DEFAULT,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::{default::Default, time::Duration};
 
+#[cfg(feature = "serde")]
+mod loader;
+
+/// Configuration options for a `LaminarSocket` and the connections it manages.
+///
+/// Use `SocketConfig::builder` to change any of the settings. With the `serde` feature enabled,
+/// the settings can also be loaded from a file, see `SocketConfig::load`.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct SocketConfig {
+    /// The maximal amount of time we wait for an outgoing packet to add our acknowledgements to,
+    /// after which they are sent in a packet of their own. Keeping this well below the round
+    /// trip time prevents the remote endpoint from sending packets again that did arrive.
+    ///
+    /// Recommended value: 20 milliseconds
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    ack_delay: Duration,
+    /// This is the number of packets, before the most recent one, we tell the other side about in
+    /// every acknowledgement. A reliable packet that falls outside of the window of the
+    /// acknowledgements we receive without being acknowledged is considered dropped and is sent
+    /// again. A wider window gives the acknowledgements more time to arrive on jittery links at
+    /// high send rates, at the cost of one extra byte per 8 packets in every reliable header.
+    ///
+    /// The ack field is sent with its length, so both sides of a connection don't need to use
+    /// the same window. It is rounded up to a multiple of 8 and can be at most 128.
+    ///
+    /// Recommended value: 64
+    ack_window_size: u8,
+    /// The number of packets per second we send to a connection while its network quality is
+    /// bad. Packets the application sends faster than this are held back until they can go out.
+    ///
+    /// Recommended value: a third of `good_send_rate`
+    bad_send_rate: u32,
+    /// The fraction of packets that can be lost (smoothed over recent packets) before we consider
+    /// the network quality of a connection to be bad.
+    ///
+    /// Recommended value: 0.1
+    /// Value is a ratio (0 = 0% and 1 = 100%)
+    congestion_loss_threshold: f32,
+    /// The variance of the round trip time (jitter) above which we consider the network quality
+    /// of a connection to be bad.
+    ///
+    /// Recommended value: 100 milliseconds
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    congestion_jitter_threshold: Duration,
+    /// The round trip time above which we consider the network quality of a connection to be
+    /// bad.
+    ///
+    /// Recommended value: 250 milliseconds
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    congestion_rtt_threshold: Duration,
     /// This is the size of a fragment.
     /// If a packet is too large it needs to be split in fragments.
+    /// With path MTU discovery enabled, this is only the size we start out with for every
+    /// connection, see `mtu_discovery_enabled`.
     ///
     /// Recommended value: +- 1450 (1500 is the default MTU)
     fragment_size_bytes: u16,
+    /// This is the number of packets we can be reassembling fragments for at the same time.
+    /// Fragments of packets that fall outside of this window will overwrite the oldest ones.
+    ///
+    /// Recommended value: 64
+    fragment_reassembly_buffer_size: u16,
+    /// The maximal amount of time to wait for the missing fragments of a packet before the
+    /// already received fragments are thrown away.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    fragment_reassembly_timeout: Duration,
+    /// The number of packets per second we send to a connection while its network quality is
+    /// good. A connection can send up to a second worth of packets at once, as long as it stays
+    /// within this rate on average.
+    ///
+    /// Recommended value: the rate at which the application sends its updates
+    good_send_rate: u32,
+    /// Whether remote endpoints have to complete a challenge/response handshake before we create
+    /// a connection for them. Packets from endpoints that did not complete the handshake are
+    /// dropped without allocating any state, which protects servers against spoofed traffic.
+    ///
+    /// Both sides of a connection should use the same setting.
+    handshake_enabled: bool,
+    /// The interval at which unanswered handshake packets are sent again.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    handshake_resend_interval: Duration,
+    /// The maximal amount of time a handshake may take. This is also how long a challenge handed
+    /// out by a server stays valid.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    handshake_timeout: Duration,
+    /// The interval at which a heartbeat is sent to connections we haven't sent anything to.
+    /// Heartbeats keep idle connections from timing out on the remote end and carry our
+    /// acknowledgements. `None` disables heartbeats.
+    ///
+    /// Recommended value: well below `idle_connection_timeout`.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    heartbeat_interval: Option<Duration>,
     /// The maximal amount of time to keep `VirtualConnection`s around before cleaning them up.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     idle_connection_timeout: Duration,
     /// These are the maximal fragments a packet could be divided into.
     ///
@@ -19,84 +115,688 @@ pub struct SocketConfig {
     ///
     /// Recommended value: 16 but keep in mind that lower is better.
     max_fragments: u8,
+    /// The largest datagram, headers included, we send to any connection. Path MTU discovery
+    /// never probes beyond this size, so it should not be larger than the receive buffer of the
+    /// remote endpoint.
+    ///
+    /// Recommended value: 1500 bytes on the internet, more on a local network with jumbo frames
+    max_mtu: u16,
+    /// This is the number of packets for a connection that wait for the congestion control to let
+    /// them go out. Once that many are waiting, the oldest unreliable packet is dropped to make
+    /// room, and reliable packets are refused when there is no unreliable packet left to drop.
+    /// No more than this many packets wait for a handshake either.
+    ///
+    /// Recommended value: 1024
+    max_paced_packets: usize,
+    /// This is the number of times a reliable packet is sent again when it isn't acknowledged in
+    /// time. If it still isn't acknowledged after that, the connection is considered broken.
+    ///
+    /// Recommended value: 10
+    max_retransmissions: u8,
+    /// Whether we look for the largest datagram that makes it to each connection, by sending it
+    /// padded probe packets. Packets are split into fragments of the discovered size, instead of
+    /// `fragment_size_bytes`, which is only where the search starts.
+    ///
+    /// Probes are only sent once the peer proved it is reachable, by completing the handshake or
+    /// acknowledging one of our packets. Both sides of a connection should use the same setting.
+    /// This is off by default.
+    mtu_discovery_enabled: bool,
+    /// This is the number of packets of an ordered stream we can hold back while waiting for an
+    /// earlier packet to arrive. Packets that are further ahead are not acknowledged, so they
+    /// will be sent again. Reliable unordered packets are treated the same way: a stream releases
+    /// packets up to this far ahead of the oldest packet that is missing.
+    ///
+    /// Recommended value: 256
+    ordering_buffer_size: u16,
+    /// This is the number of payload buffers of received packets that are kept around once the
+    /// application drops the packets, so the payloads of the next ones can be received without
+    /// allocating. Zero disables reusing them.
+    ///
+    /// Recommended value: a little more than the number of packets the application holds on to
+    payload_pool_size: usize,
     /// This is the size of the buffer the underlying UDP socket reads data into.
     /// Default: Max MTU - 1500 bytes
     receive_buffer_size_bytes: usize,
-    /// This is the maximal round trip time (rtt) for packet.
+    /// This is the maximal round trip time (rtt) for packet. Until a packet of a connection is
+    /// acknowledged, this is how long we wait before sending a reliable packet again.
     ///
     /// Recommend value: 250 ms
     /// Value is represented in milliseconds.
     rtt_max_value: u16,
     /// This is the factor which will smooth out network jitter. So that if one packet is not arrived fast we don't wan't to directly transform to an bad network.
+    /// Every new rtt measurement moves the smoothed rtt by this much towards it.
     ///
     /// Recommended value: 10% of the rtt time.
     /// Value is a ratio (0 = 0% and 1 = 100%)
     rtt_smoothing_factor: f32,
+    /// This is how long a shutdown waits for the reliable packets that were already sent to be
+    /// acknowledged, before the connections are disconnected anyway.
+    ///
+    /// Recommended value: 1 second
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    shutdown_timeout: Duration,
     // This is the size of the event buffer we read socket events (from `mio::Poll`) into.
     socket_event_buffer_size: usize,
     /// Optional duration specifying how long we should block polling for socket events.
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     socket_polling_timeout: Option<Duration>,
+    /// This is the number of chunks of a large message transfer that may be waiting for an
+    /// acknowledgement at the same time. A larger window makes transfers faster on connections
+    /// with a high round trip time, but the receiver may have to hold on to this many chunks while
+    /// it waits for one that got dropped.
+    ///
+    /// Recommended value: 32
+    transfer_window_size: u16,
 }
 
 impl SocketConfig {
+    /// Creates a builder which starts out with the default settings.
+    pub fn builder() -> SocketConfigBuilder {
+        SocketConfigBuilder::default()
+    }
+
+    /// Checks that the settings make sense, both on their own and together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let non_zero_values = [
+            ("ack_window_size", self.ack_window_size as usize),
+            ("bad_send_rate", self.bad_send_rate as usize),
+            ("fragment_size_bytes", self.fragment_size_bytes as usize),
+            (
+                "fragment_reassembly_buffer_size",
+                self.fragment_reassembly_buffer_size as usize,
+            ),
+            ("good_send_rate", self.good_send_rate as usize),
+            ("max_fragments", self.max_fragments as usize),
+            ("max_paced_packets", self.max_paced_packets),
+            ("ordering_buffer_size", self.ordering_buffer_size as usize),
+            ("socket_event_buffer_size", self.socket_event_buffer_size),
+            ("transfer_window_size", self.transfer_window_size as usize),
+        ];
+        if let Some((name, _)) = non_zero_values.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::ZeroValue(name));
+        }
+
+        let ratios = [
+            ("congestion_loss_threshold", self.congestion_loss_threshold),
+            ("rtt_smoothing_factor", self.rtt_smoothing_factor),
+        ];
+        if let Some((name, value)) = ratios
+            .iter()
+            .find(|(_, value)| !(0.0..=1.0).contains(value))
+        {
+            return Err(ConfigError::InvalidRatio(name, *value));
+        }
+
+        if self.ack_window_size > MAX_ACK_WINDOW_SIZE {
+            return Err(ConfigError::AckWindowTooLarge {
+                ack_window_size: self.ack_window_size,
+                limit: MAX_ACK_WINDOW_SIZE,
+            });
+        }
+
+        if self.bad_send_rate > self.good_send_rate {
+            return Err(ConfigError::BadSendRateTooHigh {
+                bad_send_rate: self.bad_send_rate,
+                good_send_rate: self.good_send_rate,
+            });
+        }
+
+        if u16::from(self.max_fragments) > MAX_FRAGMENTS_DEFAULT {
+            return Err(ConfigError::TooManyFragments {
+                max_fragments: self.max_fragments,
+                limit: MAX_FRAGMENTS_DEFAULT,
+            });
+        }
+
+        let max_datagram_size = self.fragment_size_bytes as usize + max_header_size();
+        if self.receive_buffer_size_bytes < max_datagram_size {
+            return Err(ConfigError::ReceiveBufferTooSmall {
+                receive_buffer_size_bytes: self.receive_buffer_size_bytes,
+                required: max_datagram_size,
+            });
+        }
+
+        if self.max_mtu < MIN_MTU {
+            return Err(ConfigError::MaxMtuTooSmall {
+                max_mtu: self.max_mtu,
+                limit: MIN_MTU,
+            });
+        }
+
+        if self.receive_buffer_size_bytes < self.max_mtu as usize {
+            return Err(ConfigError::ReceiveBufferTooSmall {
+                receive_buffer_size_bytes: self.receive_buffer_size_bytes,
+                required: self.max_mtu as usize,
+            });
+        }
+
+        if let Some(heartbeat_interval) = self.heartbeat_interval {
+            if heartbeat_interval >= self.idle_connection_timeout {
+                return Err(ConfigError::HeartbeatIntervalTooLong {
+                    heartbeat_interval,
+                    idle_connection_timeout: self.idle_connection_timeout,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the maximal amount of time acknowledgements wait for an outgoing packet to be added to.
+    #[inline]
+    pub const fn ack_delay(&self) -> Duration {
+        self.ack_delay
+    }
+
+    /// Get the number of packets before the most recent one that are acknowledged in every
+    /// reliable header.
+    #[inline]
+    pub const fn ack_window_size(&self) -> u8 {
+        self.ack_window_size
+    }
+
+    /// Get the size of a single fragment.
     #[inline]
     pub const fn fragment_size_bytes(&self) -> u16 {
         self.fragment_size_bytes
     }
 
+    /// Get the number of packets that can be reassembled at the same time.
+    #[inline]
+    pub const fn fragment_reassembly_buffer_size(&self) -> u16 {
+        self.fragment_reassembly_buffer_size
+    }
+
+    /// Get the time after which an incomplete packet is thrown away.
+    #[inline]
+    pub const fn fragment_reassembly_timeout(&self) -> Duration {
+        self.fragment_reassembly_timeout
+    }
+
+    /// Get whether remote endpoints have to complete a handshake before they are connected.
+    #[inline]
+    pub const fn handshake_enabled(&self) -> bool {
+        self.handshake_enabled
+    }
+
+    /// Get the interval at which unanswered handshake packets are sent again.
+    #[inline]
+    pub const fn handshake_resend_interval(&self) -> Duration {
+        self.handshake_resend_interval
+    }
+
+    /// Get the time after which a handshake is given up.
+    #[inline]
+    pub const fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    /// Get the interval at which heartbeats are sent to quiet connections.
+    #[inline]
+    pub const fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval
+    }
+
+    /// Get the time after which an idle connection is cleaned up.
     #[inline]
     pub const fn idle_connection_timeout(&self) -> Duration {
         self.idle_connection_timeout
     }
 
+    /// Get the maximal number of fragments a packet can be split into.
     #[inline]
     pub const fn max_fragments(&self) -> u8 {
         self.max_fragments
     }
 
+    /// Get the largest datagram we send to any connection.
+    #[inline]
+    pub const fn max_mtu(&self) -> u16 {
+        self.max_mtu
+    }
+
+    /// Get whether the largest datagram that makes it to each connection is discovered.
+    #[inline]
+    pub const fn mtu_discovery_enabled(&self) -> bool {
+        self.mtu_discovery_enabled
+    }
+
+    /// Get the number of packets for a connection that can wait for the congestion control.
+    #[inline]
+    pub const fn max_paced_packets(&self) -> usize {
+        self.max_paced_packets
+    }
+
+    /// Get the number of times an unacknowledged reliable packet is sent again.
+    #[inline]
+    pub const fn max_retransmissions(&self) -> u8 {
+        self.max_retransmissions
+    }
+
     /// Calculated value based on the maximum number of fragments and the fragment size.
+    ///
+    /// With path MTU discovery enabled, the limit for a connection can be lower while it uses
+    /// smaller fragments.
     #[inline]
     pub const fn max_packet_size_bytes(&self) -> usize {
-        self.max_fragments as usize + self.fragment_size_bytes as usize
+        self.max_fragments as usize * self.fragment_size_bytes as usize
+    }
+
+    /// Get the number of packets an ordered stream can hold back.
+    #[inline]
+    pub const fn ordering_buffer_size(&self) -> u16 {
+        self.ordering_buffer_size
+    }
+
+    /// Get the number of payload buffers of received packets that are kept around to be reused.
+    #[inline]
+    pub const fn payload_pool_size(&self) -> usize {
+        self.payload_pool_size
+    }
+
+    /// Get the number of packets per second we send while the network quality is bad.
+    #[inline]
+    pub const fn bad_send_rate(&self) -> u32 {
+        self.bad_send_rate
+    }
+
+    /// Get the number of packets per second we send while the network quality is good.
+    #[inline]
+    pub const fn good_send_rate(&self) -> u32 {
+        self.good_send_rate
+    }
+
+    /// Get the fraction of lost packets above which the network quality is considered bad.
+    #[inline]
+    pub const fn congestion_loss_threshold(&self) -> f32 {
+        self.congestion_loss_threshold
     }
 
+    /// Get the variance of the round trip time above which the network quality is considered bad.
+    #[inline]
+    pub const fn congestion_jitter_threshold(&self) -> Duration {
+        self.congestion_jitter_threshold
+    }
+
+    /// Get the round trip time above which the network quality is considered bad.
+    #[inline]
+    pub const fn congestion_rtt_threshold(&self) -> Duration {
+        self.congestion_rtt_threshold
+    }
+
+    /// Get the size of the buffer the underlying UDP socket reads data into.
     #[inline]
     pub const fn receive_buffer_size_bytes(&self) -> usize {
         self.receive_buffer_size_bytes
     }
 
+    /// Get how long a shutdown waits for reliable packets to be acknowledged.
+    #[inline]
+    pub const fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    /// Get the size of the buffer socket events are read into.
     #[inline]
     pub const fn socket_event_buffer_size(&self) -> usize {
         self.socket_event_buffer_size
     }
 
+    /// Get the maximal round trip time (rtt) in milliseconds.
     #[inline]
     pub const fn rtt_max_value(&self) -> u16 {
         self.rtt_max_value
     }
 
+    /// Get the factor used for smoothing out network jitter.
     #[inline]
     pub const fn rtt_smoothing_factor(&self) -> f32 {
         self.rtt_smoothing_factor
     }
 
+    /// Get the duration we block while polling for socket events.
     #[inline]
     pub const fn socket_polling_timeout(&self) -> Option<Duration> {
         self.socket_polling_timeout
     }
+
+    /// Get the number of chunks of a transfer that may be waiting for an acknowledgement.
+    #[inline]
+    pub const fn transfer_window_size(&self) -> u16 {
+        self.transfer_window_size
+    }
 }
 
 impl Default for SocketConfig {
     fn default() -> Self {
         Self {
+            ack_delay: Duration::from_millis(20),
+            ack_window_size: 64,
+            bad_send_rate: 20,
+            congestion_jitter_threshold: Duration::from_millis(100),
+            congestion_loss_threshold: 0.1,
+            congestion_rtt_threshold: Duration::from_millis(250),
             fragment_size_bytes: 1450,
+            fragment_reassembly_buffer_size: 64,
+            fragment_reassembly_timeout: Duration::from_secs(1),
+            good_send_rate: 60,
+            handshake_enabled: false,
+            handshake_resend_interval: Duration::from_millis(250),
+            handshake_timeout: Duration::from_secs(5),
+            heartbeat_interval: Some(Duration::from_secs(1)),
             idle_connection_timeout: Duration::from_secs(5),
             max_fragments: 16,
+            max_mtu: 1500,
+            max_paced_packets: 1024,
+            max_retransmissions: 10,
+            mtu_discovery_enabled: false,
+            ordering_buffer_size: 256,
+            payload_pool_size: 256,
             receive_buffer_size_bytes: 1500,
             rtt_smoothing_factor: 0.10,
             rtt_max_value: 250,
+            shutdown_timeout: Duration::from_secs(1),
             socket_event_buffer_size: 1024,
             socket_polling_timeout: Some(Duration::from_millis(100)),
+            transfer_window_size: 32,
         }
     }
 }
+
+/// Builds a `SocketConfig`, starting out with the default settings.
+///
+/// The settings are only checked once the config is built, see `SocketConfig::validate`.
+#[derive(Clone, Default)]
+pub struct SocketConfigBuilder {
+    config: SocketConfig,
+}
+
+impl SocketConfigBuilder {
+    /// Checks the settings and returns the config if they make sense.
+    pub fn build(self) -> Result<SocketConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+
+    /// Sets the maximal amount of time acknowledgements wait for an outgoing packet to be added to.
+    pub fn ack_delay(mut self, ack_delay: Duration) -> Self {
+        self.config.ack_delay = ack_delay;
+        self
+    }
+
+    /// Sets the number of packets before the most recent one that are acknowledged in every
+    /// reliable header.
+    pub fn ack_window_size(mut self, ack_window_size: u8) -> Self {
+        self.config.ack_window_size = ack_window_size;
+        self
+    }
+
+    /// Sets the number of packets per second we send while the network quality is bad.
+    pub fn bad_send_rate(mut self, bad_send_rate: u32) -> Self {
+        self.config.bad_send_rate = bad_send_rate;
+        self
+    }
+
+    /// Sets the variance of the round trip time above which the network quality is considered bad.
+    pub fn congestion_jitter_threshold(mut self, congestion_jitter_threshold: Duration) -> Self {
+        self.config.congestion_jitter_threshold = congestion_jitter_threshold;
+        self
+    }
+
+    /// Sets the fraction of lost packets above which the network quality is considered bad.
+    pub fn congestion_loss_threshold(mut self, congestion_loss_threshold: f32) -> Self {
+        self.config.congestion_loss_threshold = congestion_loss_threshold;
+        self
+    }
+
+    /// Sets the round trip time above which the network quality is considered bad.
+    pub fn congestion_rtt_threshold(mut self, congestion_rtt_threshold: Duration) -> Self {
+        self.config.congestion_rtt_threshold = congestion_rtt_threshold;
+        self
+    }
+
+    /// Sets the size of a single fragment.
+    pub fn fragment_size_bytes(mut self, fragment_size_bytes: u16) -> Self {
+        self.config.fragment_size_bytes = fragment_size_bytes;
+        self
+    }
+
+    /// Sets the number of packets that can be reassembled at the same time.
+    pub fn fragment_reassembly_buffer_size(mut self, fragment_reassembly_buffer_size: u16) -> Self {
+        self.config.fragment_reassembly_buffer_size = fragment_reassembly_buffer_size;
+        self
+    }
+
+    /// Sets the time after which an incomplete packet is thrown away.
+    pub fn fragment_reassembly_timeout(mut self, fragment_reassembly_timeout: Duration) -> Self {
+        self.config.fragment_reassembly_timeout = fragment_reassembly_timeout;
+        self
+    }
+
+    /// Sets the number of packets per second we send while the network quality is good.
+    pub fn good_send_rate(mut self, good_send_rate: u32) -> Self {
+        self.config.good_send_rate = good_send_rate;
+        self
+    }
+
+    /// Sets whether remote endpoints have to complete a handshake before they are connected.
+    pub fn handshake_enabled(mut self, handshake_enabled: bool) -> Self {
+        self.config.handshake_enabled = handshake_enabled;
+        self
+    }
+
+    /// Sets the interval at which unanswered handshake packets are sent again.
+    pub fn handshake_resend_interval(mut self, handshake_resend_interval: Duration) -> Self {
+        self.config.handshake_resend_interval = handshake_resend_interval;
+        self
+    }
+
+    /// Sets the time after which a handshake is given up.
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.config.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets the interval at which heartbeats are sent to quiet connections, `None` disables them.
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Option<Duration>) -> Self {
+        self.config.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Sets the time after which an idle connection is cleaned up.
+    pub fn idle_connection_timeout(mut self, idle_connection_timeout: Duration) -> Self {
+        self.config.idle_connection_timeout = idle_connection_timeout;
+        self
+    }
+
+    /// Sets the maximal number of fragments a packet can be split into.
+    pub fn max_fragments(mut self, max_fragments: u8) -> Self {
+        self.config.max_fragments = max_fragments;
+        self
+    }
+
+    /// Sets the largest datagram we send to any connection.
+    pub fn max_mtu(mut self, max_mtu: u16) -> Self {
+        self.config.max_mtu = max_mtu;
+        self
+    }
+
+    /// Sets whether the largest datagram that makes it to each connection is discovered.
+    pub fn mtu_discovery_enabled(mut self, mtu_discovery_enabled: bool) -> Self {
+        self.config.mtu_discovery_enabled = mtu_discovery_enabled;
+        self
+    }
+
+    /// Sets the number of packets for a connection that can wait for the congestion control.
+    pub fn max_paced_packets(mut self, max_paced_packets: usize) -> Self {
+        self.config.max_paced_packets = max_paced_packets;
+        self
+    }
+
+    /// Sets the number of times an unacknowledged reliable packet is sent again.
+    pub fn max_retransmissions(mut self, max_retransmissions: u8) -> Self {
+        self.config.max_retransmissions = max_retransmissions;
+        self
+    }
+
+    /// Sets the number of packets an ordered stream can hold back.
+    pub fn ordering_buffer_size(mut self, ordering_buffer_size: u16) -> Self {
+        self.config.ordering_buffer_size = ordering_buffer_size;
+        self
+    }
+
+    /// Sets the number of payload buffers of received packets that are kept around to be reused.
+    pub fn payload_pool_size(mut self, payload_pool_size: usize) -> Self {
+        self.config.payload_pool_size = payload_pool_size;
+        self
+    }
+
+    /// Sets the size of the buffer the underlying UDP socket reads data into.
+    pub fn receive_buffer_size_bytes(mut self, receive_buffer_size_bytes: usize) -> Self {
+        self.config.receive_buffer_size_bytes = receive_buffer_size_bytes;
+        self
+    }
+
+    /// Sets the maximal round trip time (rtt) in milliseconds.
+    pub fn rtt_max_value(mut self, rtt_max_value: u16) -> Self {
+        self.config.rtt_max_value = rtt_max_value;
+        self
+    }
+
+    /// Sets the factor used for smoothing out network jitter.
+    pub fn rtt_smoothing_factor(mut self, rtt_smoothing_factor: f32) -> Self {
+        self.config.rtt_smoothing_factor = rtt_smoothing_factor;
+        self
+    }
+
+    /// Sets how long a shutdown waits for reliable packets to be acknowledged.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Sets the size of the buffer socket events are read into.
+    pub fn socket_event_buffer_size(mut self, socket_event_buffer_size: usize) -> Self {
+        self.config.socket_event_buffer_size = socket_event_buffer_size;
+        self
+    }
+
+    /// Sets the duration we block while polling for socket events.
+    pub fn socket_polling_timeout(mut self, socket_polling_timeout: Option<Duration>) -> Self {
+        self.config.socket_polling_timeout = socket_polling_timeout;
+        self
+    }
+
+    /// Sets the number of chunks of a transfer that may be waiting for an acknowledgement.
+    pub fn transfer_window_size(mut self, transfer_window_size: u16) -> Self {
+        self.config.transfer_window_size = transfer_window_size;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketConfig;
+    use crate::errors::ConfigError;
+    use std::time::Duration;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(SocketConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn builds_config_with_changed_settings() {
+        let config = SocketConfig::builder()
+            .fragment_size_bytes(1000)
+            .max_fragments(8)
+            .idle_connection_timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        assert_eq!(config.fragment_size_bytes(), 1000);
+        assert_eq!(config.max_fragments(), 8);
+        assert_eq!(config.idle_connection_timeout(), Duration::from_secs(10));
+        assert_eq!(config.max_packet_size_bytes(), 8000);
+    }
+
+    #[test]
+    fn rejects_receive_buffer_smaller_than_fragment() {
+        let result = SocketConfig::builder()
+            .fragment_size_bytes(1450)
+            .receive_buffer_size_bytes(1450)
+            .build();
+
+        match result {
+            Err(ConfigError::ReceiveBufferTooSmall { required, .. }) => assert!(required > 1450),
+            _ => panic!("Expected the receive buffer to be too small."),
+        }
+    }
+
+    #[test]
+    fn rejects_more_fragments_than_can_be_reassembled() {
+        let result = SocketConfig::builder().max_fragments(17).build();
+
+        assert_eq!(
+            result.err(),
+            Some(ConfigError::TooManyFragments {
+                max_fragments: 17,
+                limit: 16
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_max_mtu_below_minimum() {
+        let result = SocketConfig::builder().max_mtu(500).build();
+
+        assert_eq!(
+            result.err(),
+            Some(ConfigError::MaxMtuTooSmall {
+                max_mtu: 500,
+                limit: 508
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_settings() {
+        let errors = [
+            SocketConfig::builder()
+                .ordering_buffer_size(0)
+                .build()
+                .err(),
+            SocketConfig::builder().ack_window_size(129).build().err(),
+            SocketConfig::builder()
+                .congestion_loss_threshold(1.5)
+                .build()
+                .err(),
+            SocketConfig::builder().bad_send_rate(100).build().err(),
+            SocketConfig::builder()
+                .heartbeat_interval(Some(Duration::from_secs(5)))
+                .build()
+                .err(),
+        ];
+
+        assert_eq!(
+            errors[0],
+            Some(ConfigError::ZeroValue("ordering_buffer_size"))
+        );
+        assert!(matches!(
+            errors[1],
+            Some(ConfigError::AckWindowTooLarge { .. })
+        ));
+        assert_eq!(
+            errors[2],
+            Some(ConfigError::InvalidRatio("congestion_loss_threshold", 1.5))
+        );
+        assert!(matches!(
+            errors[3],
+            Some(ConfigError::BadSendRateTooHigh { .. })
+        ));
+        assert!(matches!(
+            errors[4],
+            Some(ConfigError::HeartbeatIntervalTooLong { .. })
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+use super::SocketConfig;
+use crate::errors::ConfigError;
+use serde_json::{Map, Value};
+use std::{env, fs, path::Path};
+
+/// The prefix of the environment variables that override settings of a loaded config.
+const ENV_PREFIX: &str = "LAMINAR_";
+
+impl SocketConfig {
+    /// Loads the config from a TOML, RON or JSON file, depending on its extension.
+    ///
+    /// Settings that are missing from the file keep their default value. After that, every
+    /// environment variable named after a setting with the `LAMINAR_` prefix overrides it, for
+    /// example `LAMINAR_IDLE_CONNECTION_TIMEOUT=10s` or `LAMINAR_HEARTBEAT_INTERVAL=null`. Other
+    /// variables with that prefix, like `LAMINAR_LOG`, are left alone.
+    ///
+    /// Durations are written in a human readable way, like `250ms` or `1m 30s`. The loaded
+    /// config is validated, see `SocketConfig::validate`.
+    pub fn load(path: impl AsRef<Path>) -> Result<SocketConfig, ConfigError> {
+        load_with_overrides(path.as_ref(), env::vars())
+    }
+}
+
+/// Loads the config from a file and applies the overrides from the given environment variables.
+fn load_with_overrides(
+    path: &Path,
+    env_vars: impl Iterator<Item = (String, String)>,
+) -> Result<SocketConfig, ConfigError> {
+    let contents =
+        fs::read_to_string(path).map_err(|error| ConfigError::ReadError(error.to_string()))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    let mut settings = parse_settings(&contents, extension)?;
+    let known_settings = known_settings();
+    for (name, value) in env_vars {
+        let setting = match name.strip_prefix(ENV_PREFIX) {
+            Some(setting) => setting.to_lowercase(),
+            None => continue,
+        };
+        if !known_settings.contains_key(&setting) {
+            continue;
+        }
+
+        // Values that aren't numbers, booleans or `null` are taken as strings, which is how
+        // durations are written.
+        let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+        settings.insert(setting, value);
+    }
+
+    let config: SocketConfig = serde_json::from_value(Value::Object(settings))
+        .map_err(|error| ConfigError::ParseError(error.to_string()))?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Get the names of every setting, which are the keys of the serialized default config.
+fn known_settings() -> Map<String, Value> {
+    match serde_json::to_value(SocketConfig::default()) {
+        Ok(Value::Object(settings)) => settings,
+        _ => Map::new(),
+    }
+}
+
+/// Parses the settings in the file contents, so they can be merged with the overrides.
+fn parse_settings(contents: &str, extension: &str) -> Result<Map<String, Value>, ConfigError> {
+    let parse_error = |error: &dyn std::fmt::Display| ConfigError::ParseError(error.to_string());
+    match extension {
+        "toml" => toml::from_str(contents).map_err(|error| parse_error(&error)),
+        // RON writes structs with parentheses, which only its own value type can read as a map.
+        "ron" => ron::from_str::<ron::Value>(contents)
+            .map_err(|error| parse_error(&error))
+            .and_then(|value| value.into_rust().map_err(|error| parse_error(&error))),
+        "json" => serde_json::from_str(contents).map_err(|error| parse_error(&error)),
+        extension => Err(ConfigError::UnsupportedFormat(extension.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load_with_overrides;
+    use crate::errors::ConfigError;
+    use std::{env, fs, iter, path::PathBuf, time::Duration};
+
+    /// Writes the config file to a temporary directory and returns its path.
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("laminar-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_toml_over_defaults() {
+        let path = write_config(
+            "config.toml",
+            "fragment_size_bytes = 1000\nidle_connection_timeout = \"10s\"\n",
+        );
+
+        let config = load_with_overrides(&path, iter::empty()).unwrap();
+        assert_eq!(config.fragment_size_bytes(), 1000);
+        assert_eq!(config.idle_connection_timeout(), Duration::from_secs(10));
+        assert_eq!(config.max_fragments(), 16);
+    }
+
+    #[test]
+    fn loads_ron_and_json() {
+        let ron = write_config("config.ron", "(handshake_timeout: \"1m 30s\")");
+        let config = load_with_overrides(&ron, iter::empty()).unwrap();
+        assert_eq!(config.handshake_timeout(), Duration::from_secs(90));
+
+        let json = write_config("config.json", "{\"heartbeat_interval\": null}");
+        let config = load_with_overrides(&json, iter::empty()).unwrap();
+        assert_eq!(config.heartbeat_interval(), None);
+    }
+
+    #[test]
+    fn applies_environment_overrides() {
+        let path = write_config("overrides.toml", "max_fragments = 4\n");
+        let env_vars = vec![
+            ("LAMINAR_MAX_FRAGMENTS".to_string(), "8".to_string()),
+            ("LAMINAR_ACK_DELAY".to_string(), "5ms".to_string()),
+            ("OTHER_MAX_FRAGMENTS".to_string(), "2".to_string()),
+            ("LAMINAR_LOG".to_string(), "debug".to_string()),
+        ];
+
+        let config = load_with_overrides(&path, env_vars.into_iter()).unwrap();
+        assert_eq!(config.max_fragments(), 8);
+        assert_eq!(config.ack_delay(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let invalid = write_config("invalid.toml", "max_fragments = 32\n");
+        assert!(matches!(
+            load_with_overrides(&invalid, iter::empty()),
+            Err(ConfigError::TooManyFragments { .. })
+        ));
+
+        let unknown = write_config("unknown.toml", "max_fragment = 8\n");
+        assert!(matches!(
+            load_with_overrides(&unknown, iter::empty()),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        let unsupported = write_config("config.yaml", "max_fragments: 8\n");
+        assert_eq!(
+            load_with_overrides(&unsupported, iter::empty()).err(),
+            Some(ConfigError::UnsupportedFormat("yaml".to_string()))
+        );
+    }
+}
@@ -2,10 +2,14 @@ use std::{
     error::Error,
     fmt::{self, Display, Formatter},
     io,
+    time::Duration,
 };
 
+/// Top level error type for all errors that can occur within laminar
 #[derive(Debug)]
 pub enum LaminarError {
+    /// Error relating to an invalid socket configuration
+    ConfigError(ConfigError),
     /// Error relating to receiving or parsing a fragment
     FragmentError(FragmentError),
     /// Error relating to sending or receiving a packet,
@@ -17,11 +21,16 @@ pub enum LaminarError {
     ProtocolVersionMismatch,
     /// Did not receive enough data
     ReceivedDataTooShort,
+    /// The socket is no longer running, so it can't be given any commands
+    SocketClosed,
 }
 
 impl Display for LaminarError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
+            LaminarError::ConfigError(kind) => {
+                write!(f, "The socket configuration is invalid. Reason: {}", kind)
+            }
             LaminarError::FragmentError(kind) => write!(
                 f,
                 "Something went wrong with receiving/parsing fragments. Reason: {:?}.",
@@ -42,12 +51,14 @@ impl Display for LaminarError {
             LaminarError::ReceivedDataTooShort => {
                 write!(f, "The received data did not have any length.")
             }
+            LaminarError::SocketClosed => write!(f, "The socket is no longer running."),
         }
     }
 }
 
 impl Error for LaminarError {}
 
+#[allow(clippy::from_over_into)]
 impl Into<io::Error> for LaminarError {
     fn into(self) -> io::Error {
         io::Error::new(io::ErrorKind::InvalidData, self)
@@ -61,6 +72,8 @@ pub enum FragmentError {
     PacketHeaderNotFound,
     /// Max number of allowed fragments has been exceeded
     ExceededMaxFragments,
+    /// The fragment id or fragment count did not make sense for the packet it belongs to
+    InvalidFragmentHeader,
 }
 
 impl Display for FragmentError {
@@ -69,13 +82,17 @@ impl Display for FragmentError {
             FragmentError::PacketHeaderNotFound => write!(f, "Packet header not found."),
             FragmentError::ExceededMaxFragments => write!(f,
                 "The total number of required fragments is bigger than the maximum number of allowed fragments."
-            )
+            ),
+            FragmentError::InvalidFragmentHeader => write!(f,
+                "The fragment id or fragment count does not match the packet it belongs to."
+            ),
         }
     }
 }
 
 impl Error for FragmentError {}
 
+#[allow(clippy::from_over_into)]
 impl Into<io::Error> for FragmentError {
     fn into(self) -> io::Error {
         LaminarError::FragmentError(self).into()
@@ -87,6 +104,12 @@ impl Into<io::Error> for FragmentError {
 pub enum PacketError {
     /// Max packet size was exceeded
     ExceededMaxPacketSize,
+    /// The ack field of a reliable header was empty or wider than we can handle
+    InvalidAckField,
+    /// A chunk of a transfer did not fit within the message it belongs to
+    InvalidTransferChunk,
+    /// Too many packets are waiting for the congestion control of the connection
+    SendQueueFull,
 }
 
 impl Display for PacketError {
@@ -95,14 +118,157 @@ impl Display for PacketError {
             PacketError::ExceededMaxPacketSize => {
                 write!(f, "The packet size was bigger than the max allowed size.")
             }
+            PacketError::InvalidAckField => {
+                write!(f, "The ack field of the packet has an invalid length.")
+            }
+            PacketError::InvalidTransferChunk => {
+                write!(f, "The transfer chunk does not fit within its message.")
+            }
+            PacketError::SendQueueFull => write!(
+                f,
+                "Too many packets are waiting for the connection to send them."
+            ),
         }
     }
 }
 
 impl Error for PacketError {}
 
+#[allow(clippy::from_over_into)]
 impl Into<io::Error> for PacketError {
     fn into(self) -> io::Error {
         LaminarError::PacketError(self).into()
     }
 }
+
+/// Errors that could occur when validating a `SocketConfig`
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// The setting with this name has to be greater than zero
+    ZeroValue(&'static str),
+    /// The setting with this name has to be a ratio between 0 and 1
+    InvalidRatio(&'static str, f32),
+    /// The ack window is wider than the reliable header can hold
+    AckWindowTooLarge {
+        /// The configured ack window size
+        ack_window_size: u8,
+        /// The widest ack window the reliable header can hold
+        limit: u8,
+    },
+    /// The send rate while the network quality is bad is higher than while it is good
+    BadSendRateTooHigh {
+        /// The configured send rate while the network quality is bad
+        bad_send_rate: u32,
+        /// The configured send rate while the network quality is good
+        good_send_rate: u32,
+    },
+    /// A packet can be split into more fragments than we can reassemble
+    TooManyFragments {
+        /// The configured maximal number of fragments
+        max_fragments: u8,
+        /// The maximal number of fragments we can reassemble
+        limit: u16,
+    },
+    /// The receive buffer can't hold the largest datagram we could receive
+    ReceiveBufferTooSmall {
+        /// The configured size of the receive buffer
+        receive_buffer_size_bytes: usize,
+        /// The size of the largest datagram we could receive
+        required: usize,
+    },
+    /// The largest datagram we may send is smaller than what every path can carry
+    MaxMtuTooSmall {
+        /// The configured largest datagram size
+        max_mtu: u16,
+        /// The datagram size every path can carry
+        limit: u16,
+    },
+    /// Heartbeats are not sent often enough to keep quiet connections from timing out
+    HeartbeatIntervalTooLong {
+        /// The configured heartbeat interval
+        heartbeat_interval: Duration,
+        /// The configured idle connection timeout
+        idle_connection_timeout: Duration,
+    },
+    /// The config file could not be read
+    ReadError(String),
+    /// The config file has an extension other than `toml`, `ron` or `json`
+    UnsupportedFormat(String),
+    /// The config file or an environment variable override could not be parsed
+    ParseError(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ConfigError::ZeroValue(name) => write!(f, "`{}` has to be greater than zero.", name),
+            ConfigError::InvalidRatio(name, value) => {
+                write!(f, "`{}` has to be between 0 and 1, but is {}.", name, value)
+            }
+            ConfigError::AckWindowTooLarge {
+                ack_window_size,
+                limit,
+            } => write!(
+                f,
+                "`ack_window_size` is {}, but can be at most {}.",
+                ack_window_size, limit
+            ),
+            ConfigError::BadSendRateTooHigh {
+                bad_send_rate,
+                good_send_rate,
+            } => write!(
+                f,
+                "`bad_send_rate` ({}) can't be higher than `good_send_rate` ({}).",
+                bad_send_rate, good_send_rate
+            ),
+            ConfigError::TooManyFragments {
+                max_fragments,
+                limit,
+            } => write!(
+                f,
+                "`max_fragments` is {}, but at most {} fragments can be reassembled.",
+                max_fragments, limit
+            ),
+            ConfigError::ReceiveBufferTooSmall {
+                receive_buffer_size_bytes,
+                required,
+            } => write!(
+                f,
+                "`receive_buffer_size_bytes` is {}, but datagrams of up to {} bytes can be received.",
+                receive_buffer_size_bytes, required
+            ),
+            ConfigError::MaxMtuTooSmall { max_mtu, limit } => write!(
+                f,
+                "`max_mtu` is {}, but has to be at least {} bytes.",
+                max_mtu, limit
+            ),
+            ConfigError::HeartbeatIntervalTooLong {
+                heartbeat_interval,
+                idle_connection_timeout,
+            } => write!(
+                f,
+                "`heartbeat_interval` ({:?}) has to be shorter than `idle_connection_timeout` ({:?}).",
+                heartbeat_interval, idle_connection_timeout
+            ),
+            ConfigError::ReadError(reason) => {
+                write!(f, "The config file could not be read. Reason: {}", reason)
+            }
+            ConfigError::UnsupportedFormat(extension) => write!(
+                f,
+                "Config files with the extension `{}` are not supported, use `toml`, `ron` or `json`.",
+                extension
+            ),
+            ConfigError::ParseError(reason) => {
+                write!(f, "The config could not be parsed. Reason: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(error: ConfigError) -> io::Error {
+        LaminarError::ConfigError(error).into()
+    }
+}
@@ -1,11 +1,30 @@
+mod arranging;
 mod connection;
 mod delivery_method;
 mod events;
 mod external_ack;
+mod handle;
+mod handshake;
 mod local_ack;
+mod reassembly;
 mod socket;
 
 pub use self::{
-    delivery_method::DeliveryMethod, events::SocketEvent, external_ack::ExternalAcks,
-    local_ack::LocalAckRecord, socket::LaminarSocket,
+    connection::{
+        ConnectionStats, NetworkQuality, RoundTripTime, TransferId, TransferProgress, MIN_MTU,
+    },
+    delivery_method::DeliveryMethod,
+    events::SocketEvent,
+    external_ack::ExternalAcks,
+    handle::{MessageTicket, SocketHandle},
+    local_ack::LocalAckRecord,
+    socket::LaminarSocket,
 };
+
+// Only public so the benchmarks can measure the processing of packets without a socket.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use self::connection::VirtualConnection;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use crate::packet::BufferPool;
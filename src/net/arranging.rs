@@ -0,0 +1,284 @@
+mod ordering;
+mod sequencing;
+mod unordered;
+
+pub use self::ordering::OrderingStream;
+pub use self::sequencing::SequencingStream;
+pub use self::unordered::UnorderedStream;
+
+use crate::{config::SocketConfig, net::DeliveryMethod, packet::headers::ArrangingHeader, Packet};
+use std::collections::HashMap;
+
+/// The stream packets are arranged on when no stream id was given.
+pub const DEFAULT_STREAM_ID: u8 = 255;
+
+/// Keeps track of the ordering, sequencing and unordered streams of a single connection.
+///
+/// Streams are identified by their stream id and every delivery method that arranges packets has
+/// its own set of streams, so a reliable ordered packet that is waiting to be resent will only
+/// hold back the packets sent after it on that same stream. Reliable unordered packets are put on
+/// streams as well, so that duplicates of a packet that was resent can be recognized. Streams are
+/// created the first time a packet is sent or received on them.
+pub struct ArrangingSystem {
+    ordering_buffer_size: u16,
+    reliable_unordered: HashMap<u8, UnorderedStream>,
+    reliable_ordered: HashMap<u8, OrderingStream>,
+    unreliable_ordered: HashMap<u8, SequencingStream>,
+    sequenced: HashMap<u8, SequencingStream>,
+}
+
+impl ArrangingSystem {
+    /// Creates the arranging system for a connection.
+    pub fn new(config: &SocketConfig) -> Self {
+        Self {
+            ordering_buffer_size: config.ordering_buffer_size(),
+            reliable_unordered: HashMap::new(),
+            reliable_ordered: HashMap::new(),
+            unreliable_ordered: HashMap::new(),
+            sequenced: HashMap::new(),
+        }
+    }
+
+    /// Creates the arranging header for an outgoing packet with the given delivery method on the
+    /// given stream, or on the default stream if no stream id is given.
+    ///
+    /// Returns `None` for delivery methods that do not arrange their packets.
+    pub fn outgoing_header(
+        &mut self,
+        delivery_method: DeliveryMethod,
+        stream_id: Option<u8>,
+    ) -> Option<ArrangingHeader> {
+        let stream_id = stream_id.unwrap_or(DEFAULT_STREAM_ID);
+        let arranging_id = match delivery_method {
+            DeliveryMethod::ReliableUnordered => self.unordered_stream(stream_id).new_outgoing_id(),
+            DeliveryMethod::ReliableOrdered => self.ordering_stream(stream_id).new_outgoing_id(),
+            DeliveryMethod::UnreliableOrdered => {
+                sequencing_stream(&mut self.unreliable_ordered, stream_id).new_outgoing_id()
+            }
+            DeliveryMethod::Sequenced => {
+                sequencing_stream(&mut self.sequenced, stream_id).new_outgoing_id()
+            }
+            _ => return None,
+        };
+        Some(ArrangingHeader::new(arranging_id, stream_id))
+    }
+
+    /// Checks if an incoming packet with the given header should be processed any further.
+    ///
+    /// For ordered packets this tells if the packet fits in the ordering buffer, if it does not it
+    /// should not be acknowledged. The same goes for reliable unordered packets, which are
+    /// accepted if they fit in the window of their stream, duplicates included, so that they get
+    /// acknowledged again. Sequenced packets are only accepted if they are more recent than the
+    /// packets we have released so far, so older fragments don't have to be reassembled.
+    pub fn accepts(&mut self, delivery_method: DeliveryMethod, header: ArrangingHeader) -> bool {
+        let arranging_id = header.arranging_id();
+        match delivery_method {
+            DeliveryMethod::ReliableOrdered => self
+                .ordering_stream(header.stream_id())
+                .is_within_window(arranging_id),
+            DeliveryMethod::ReliableUnordered => self
+                .unordered_stream(header.stream_id())
+                .is_within_window(arranging_id),
+            DeliveryMethod::UnreliableOrdered => {
+                sequencing_stream(&mut self.unreliable_ordered, header.stream_id())
+                    .is_newest(arranging_id)
+            }
+            DeliveryMethod::Sequenced => {
+                sequencing_stream(&mut self.sequenced, header.stream_id()).is_newest(arranging_id)
+            }
+            _ => true,
+        }
+    }
+
+    /// Hands an incoming packet to its stream and returns the packets that can now be released to
+    /// the application.
+    pub fn arrange(
+        &mut self,
+        delivery_method: DeliveryMethod,
+        header: ArrangingHeader,
+        packet: Packet,
+    ) -> Vec<Packet> {
+        let arranging_id = header.arranging_id();
+        match delivery_method {
+            DeliveryMethod::ReliableUnordered => self
+                .unordered_stream(header.stream_id())
+                .arrange(arranging_id, packet)
+                .into_iter()
+                .collect(),
+            DeliveryMethod::ReliableOrdered => self
+                .ordering_stream(header.stream_id())
+                .arrange(arranging_id, packet),
+            DeliveryMethod::UnreliableOrdered => {
+                sequencing_stream(&mut self.unreliable_ordered, header.stream_id())
+                    .arrange(arranging_id, packet)
+                    .into_iter()
+                    .collect()
+            }
+            DeliveryMethod::Sequenced => sequencing_stream(&mut self.sequenced, header.stream_id())
+                .arrange(arranging_id, packet)
+                .into_iter()
+                .collect(),
+            _ => vec![packet],
+        }
+    }
+
+    /// Get the reliable unordered stream with the given id, creating it if it doesn't exist yet.
+    fn unordered_stream(&mut self, stream_id: u8) -> &mut UnorderedStream {
+        let window_size = self.ordering_buffer_size;
+        self.reliable_unordered
+            .entry(stream_id)
+            .or_insert_with(|| UnorderedStream::new(window_size))
+    }
+
+    /// Get the reliable ordering stream with the given id, creating it if it doesn't exist yet.
+    fn ordering_stream(&mut self, stream_id: u8) -> &mut OrderingStream {
+        let buffer_size = self.ordering_buffer_size;
+        self.reliable_ordered
+            .entry(stream_id)
+            .or_insert_with(|| OrderingStream::new(buffer_size))
+    }
+}
+
+/// Get the sequencing stream with the given id, creating it if it doesn't exist yet.
+fn sequencing_stream(
+    streams: &mut HashMap<u8, SequencingStream>,
+    stream_id: u8,
+) -> &mut SequencingStream {
+    streams.entry(stream_id).or_default()
+}
+
+/// Checks if sequence number `s1` is more recent than `s2`, taking into account that sequence
+/// numbers wrap around once they reach `u16::MAX`.
+///
+/// A number is considered more recent if it is less than half of the sequence space ahead of the
+/// other, so `1` is more recent than `65535` but `40000` is not more recent than `1`.
+pub fn sequence_greater_than(s1: u16, s2: u16) -> bool {
+    ((s1 > s2) && (s1 - s2 <= 32768)) || ((s1 < s2) && (s2 - s1 > 32768))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sequence_greater_than, ArrangingSystem, DEFAULT_STREAM_ID};
+    use crate::{
+        config::SocketConfig, net::DeliveryMethod, packet::headers::ArrangingHeader, Packet,
+    };
+
+    fn packet(delivery_method: DeliveryMethod) -> Packet {
+        let address = "127.0.0.1:12345".parse().unwrap();
+        Packet::new(address, vec![1], delivery_method, None)
+    }
+
+    #[test]
+    fn unreliable_unordered_packets_have_no_header() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+
+        assert!(system
+            .outgoing_header(DeliveryMethod::UnreliableUnordered, None)
+            .is_none());
+    }
+
+    #[test]
+    fn drops_duplicate_reliable_unordered_packets() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+        let unordered = DeliveryMethod::ReliableUnordered;
+
+        let header = system.outgoing_header(unordered, Some(1)).unwrap();
+        assert_eq!(
+            system.arrange(unordered, header, packet(unordered)).len(),
+            1
+        );
+
+        assert!(system.accepts(unordered, header));
+        assert!(system
+            .arrange(unordered, header, packet(unordered))
+            .is_empty());
+    }
+
+    #[test]
+    fn uses_default_stream_without_stream_id() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+
+        let header = system
+            .outgoing_header(DeliveryMethod::ReliableOrdered, None)
+            .unwrap();
+        assert_eq!(header.stream_id(), DEFAULT_STREAM_ID);
+    }
+
+    #[test]
+    fn streams_have_independent_arranging_ids() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+        let ordered = DeliveryMethod::ReliableOrdered;
+
+        system.outgoing_header(ordered, Some(1)).unwrap();
+        let second = system.outgoing_header(ordered, Some(1)).unwrap();
+        let other = system.outgoing_header(ordered, Some(2)).unwrap();
+
+        assert_eq!(second.arranging_id(), 1);
+        assert_eq!(other.arranging_id(), 0);
+        assert_eq!(other.stream_id(), 2);
+    }
+
+    #[test]
+    fn stalled_stream_does_not_block_other_streams() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+        let ordered = DeliveryMethod::ReliableOrdered;
+
+        // The first packet of stream 1 is missing, so the second one is held back.
+        let held_back = ArrangingHeader::new(1, 1);
+        assert!(system
+            .arrange(ordered, held_back, packet(ordered))
+            .is_empty());
+
+        let other = ArrangingHeader::new(0, 2);
+        assert_eq!(system.arrange(ordered, other, packet(ordered)).len(), 1);
+
+        let missing = ArrangingHeader::new(0, 1);
+        assert_eq!(system.arrange(ordered, missing, packet(ordered)).len(), 2);
+    }
+
+    #[test]
+    fn delivery_methods_use_separate_streams() {
+        let mut system = ArrangingSystem::new(&SocketConfig::default());
+        let sequenced = DeliveryMethod::Sequenced;
+        let unreliable_ordered = DeliveryMethod::UnreliableOrdered;
+
+        let first = system.outgoing_header(sequenced, None).unwrap();
+        let second = system.outgoing_header(sequenced, None).unwrap();
+        let other = system.outgoing_header(unreliable_ordered, None).unwrap();
+        assert_eq!(other.arranging_id(), first.arranging_id());
+
+        assert_eq!(
+            system.arrange(sequenced, second, packet(sequenced)).len(),
+            1
+        );
+        assert!(!system.accepts(sequenced, first));
+        assert!(system.accepts(unreliable_ordered, other));
+        assert_eq!(
+            system
+                .arrange(unreliable_ordered, other, packet(unreliable_ordered))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn compares_sequence_numbers() {
+        assert!(sequence_greater_than(1, 0));
+        assert!(!sequence_greater_than(0, 1));
+        assert!(!sequence_greater_than(1, 1));
+    }
+
+    #[test]
+    fn compares_sequence_numbers_around_zero() {
+        assert!(sequence_greater_than(0, u16::MAX));
+        assert!(sequence_greater_than(10, u16::MAX - 10));
+        assert!(!sequence_greater_than(u16::MAX, 0));
+    }
+
+    #[test]
+    fn compares_sequence_numbers_half_the_space_apart() {
+        assert!(sequence_greater_than(32768, 0));
+        assert!(!sequence_greater_than(32769, 0));
+        assert!(sequence_greater_than(0, 32769));
+    }
+}
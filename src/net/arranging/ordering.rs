@@ -0,0 +1,168 @@
+use super::sequence_greater_than;
+use crate::{packet::Packet, sequence_buffer::SequenceBuffer};
+
+/// Releases the packets of a single stream to the application strictly in the order they were
+/// sent in.
+///
+/// Packets that arrive before one of their predecessors are held back until the gap is filled. The
+/// number of packets that can be held back is bounded by the size of the ordering buffer; a packet
+/// that is too far ahead of the next expected packet should not be acknowledged, so that the other
+/// side will send it again once there is room for it.
+pub struct OrderingStream {
+    /// The arranging id we will give to the next packet we send on this stream.
+    outgoing_id: u16,
+    /// The arranging id of the next packet we are able to release.
+    expected_id: u16,
+    /// Packets that arrived before all of their predecessors did.
+    buffer: SequenceBuffer<Option<Packet>>,
+}
+
+impl OrderingStream {
+    /// Creates a new stream which is able to hold back up to `buffer_size` packets.
+    pub fn new(buffer_size: u16) -> Self {
+        Self {
+            outgoing_id: 0,
+            expected_id: 0,
+            buffer: SequenceBuffer::with_capacity(buffer_size as usize),
+        }
+    }
+
+    /// Returns the arranging id for the next outgoing packet on this stream.
+    pub fn new_outgoing_id(&mut self) -> u16 {
+        let id = self.outgoing_id;
+        self.outgoing_id = self.outgoing_id.wrapping_add(1);
+        id
+    }
+
+    /// Checks if a packet with the given arranging id can be handled by this stream.
+    ///
+    /// This is the case for packets which fit in the ordering buffer, and for packets that have
+    /// already been released (which will simply be ignored).
+    pub fn is_within_window(&self, arranging_id: u16) -> bool {
+        let distance = arranging_id.wrapping_sub(self.expected_id);
+        (distance as usize) < self.buffer.len()
+            || !sequence_greater_than(arranging_id, self.expected_id)
+    }
+
+    /// Hands a packet to the stream and returns all packets that can now be released in order.
+    ///
+    /// The returned collection is empty if the packet has to wait for one of its predecessors, was
+    /// already released before, or does not fit in the ordering buffer.
+    pub fn arrange(&mut self, arranging_id: u16, packet: Packet) -> Vec<Packet> {
+        if arranging_id == self.expected_id {
+            let mut packets = vec![packet];
+            self.expected_id = self.expected_id.wrapping_add(1);
+
+            while let Some(packet) = self.take_buffered(self.expected_id) {
+                packets.push(packet);
+                self.expected_id = self.expected_id.wrapping_add(1);
+            }
+
+            packets
+        } else {
+            if sequence_greater_than(arranging_id, self.expected_id)
+                && self.is_within_window(arranging_id)
+            {
+                self.buffer.insert(Some(packet), arranging_id);
+            }
+            Vec::new()
+        }
+    }
+
+    /// Removes the packet with the given arranging id from the buffer, if it is there.
+    fn take_buffered(&mut self, arranging_id: u16) -> Option<Packet> {
+        let packet = self.buffer.get_mut(arranging_id).and_then(Option::take);
+        if packet.is_some() {
+            self.buffer.remove(arranging_id);
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderingStream;
+    use crate::Packet;
+
+    fn packet(id: u8) -> Packet {
+        Packet::reliable_ordered("127.0.0.1:12345".parse().unwrap(), vec![id], None)
+    }
+
+    fn payloads(packets: Vec<Packet>) -> Vec<u8> {
+        packets.iter().map(|packet| packet.payload()[0]).collect()
+    }
+
+    #[test]
+    fn releases_packets_in_order() {
+        let mut stream = OrderingStream::new(16);
+
+        assert_eq!(payloads(stream.arrange(0, packet(0))), vec![0]);
+        assert_eq!(payloads(stream.arrange(1, packet(1))), vec![1]);
+        assert_eq!(payloads(stream.arrange(2, packet(2))), vec![2]);
+        assert_eq!(stream.expected_id, 3);
+    }
+
+    #[test]
+    fn holds_back_packets_until_gap_is_filled() {
+        let mut stream = OrderingStream::new(16);
+
+        assert!(stream.arrange(2, packet(2)).is_empty());
+        assert!(stream.arrange(1, packet(1)).is_empty());
+        assert!(stream.arrange(4, packet(4)).is_empty());
+        assert_eq!(payloads(stream.arrange(0, packet(0))), vec![0, 1, 2]);
+        assert_eq!(payloads(stream.arrange(3, packet(3))), vec![3, 4]);
+        assert_eq!(stream.expected_id, 5);
+    }
+
+    #[test]
+    fn ignores_already_released_packets() {
+        let mut stream = OrderingStream::new(16);
+
+        stream.arrange(0, packet(0));
+        stream.arrange(1, packet(1));
+
+        assert!(stream.is_within_window(0));
+        assert!(stream.arrange(0, packet(0)).is_empty());
+        assert_eq!(stream.expected_id, 2);
+    }
+
+    #[test]
+    fn ignores_duplicate_buffered_packets() {
+        let mut stream = OrderingStream::new(16);
+
+        stream.arrange(1, packet(1));
+        stream.arrange(1, packet(1));
+
+        assert_eq!(payloads(stream.arrange(0, packet(0))), vec![0, 1]);
+    }
+
+    #[test]
+    fn rejects_packets_outside_of_window() {
+        let mut stream = OrderingStream::new(16);
+
+        assert!(stream.is_within_window(15));
+        assert!(!stream.is_within_window(16));
+        assert!(stream.arrange(16, packet(16)).is_empty());
+
+        for id in 0..16 {
+            stream.arrange(id, packet(id as u8));
+        }
+        // The packet which did not fit was not kept, so it has to be sent again.
+        assert_eq!(stream.expected_id, 16);
+        assert_eq!(payloads(stream.arrange(16, packet(16))), vec![16]);
+    }
+
+    #[test]
+    fn releases_packets_around_zero() {
+        let mut stream = OrderingStream::new(16);
+        for id in 0..u16::MAX {
+            stream.arrange(id, packet(0));
+        }
+        assert_eq!(stream.expected_id, u16::MAX);
+
+        assert!(stream.arrange(1, packet(1)).is_empty());
+        assert!(stream.arrange(0, packet(0)).is_empty());
+        assert_eq!(payloads(stream.arrange(u16::MAX, packet(2))), vec![2, 0, 1]);
+        assert_eq!(stream.expected_id, 2);
+    }
+}
@@ -0,0 +1,107 @@
+use super::sequence_greater_than;
+use crate::packet::Packet;
+
+/// Releases only those packets of a single stream that are more recent than any packet released
+/// before them.
+///
+/// Older packets are thrown away, as are duplicates of the most recent packet. This is useful for
+/// state updates where only the latest state matters, like the position of a player.
+#[derive(Default)]
+pub struct SequencingStream {
+    /// The arranging id we will give to the next packet we send on this stream.
+    outgoing_id: u16,
+    /// The arranging id of the most recent packet we released, if we released one yet.
+    newest_id: Option<u16>,
+}
+
+impl SequencingStream {
+    /// Returns the arranging id for the next outgoing packet on this stream.
+    pub fn new_outgoing_id(&mut self) -> u16 {
+        let id = self.outgoing_id;
+        self.outgoing_id = self.outgoing_id.wrapping_add(1);
+        id
+    }
+
+    /// Checks if a packet with the given arranging id is more recent than all packets released so
+    /// far.
+    pub fn is_newest(&self, arranging_id: u16) -> bool {
+        match self.newest_id {
+            Some(newest_id) => sequence_greater_than(arranging_id, newest_id),
+            None => true,
+        }
+    }
+
+    /// Hands a packet to the stream and returns it if it is the most recent packet so far.
+    pub fn arrange(&mut self, arranging_id: u16, packet: Packet) -> Option<Packet> {
+        if self.is_newest(arranging_id) {
+            self.newest_id = Some(arranging_id);
+            Some(packet)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SequencingStream;
+    use crate::Packet;
+
+    fn packet() -> Packet {
+        Packet::sequenced("127.0.0.1:12345".parse().unwrap(), vec![1], None)
+    }
+
+    #[test]
+    fn releases_newer_packets() {
+        let mut stream = SequencingStream::default();
+
+        assert!(stream.arrange(0, packet()).is_some());
+        assert!(stream.arrange(1, packet()).is_some());
+        assert!(stream.arrange(5, packet()).is_some());
+    }
+
+    #[test]
+    fn releases_first_packet_regardless_of_id() {
+        let mut stream = SequencingStream::default();
+
+        assert!(stream.arrange(40000, packet()).is_some());
+    }
+
+    #[test]
+    fn drops_older_packets() {
+        let mut stream = SequencingStream::default();
+
+        assert!(stream.arrange(5, packet()).is_some());
+        assert!(stream.arrange(3, packet()).is_none());
+        assert!(stream.arrange(4, packet()).is_none());
+        assert!(stream.arrange(6, packet()).is_some());
+    }
+
+    #[test]
+    fn drops_duplicate_packets() {
+        let mut stream = SequencingStream::default();
+
+        assert!(stream.arrange(5, packet()).is_some());
+        assert!(stream.arrange(5, packet()).is_none());
+    }
+
+    #[test]
+    fn releases_packets_around_zero() {
+        let mut stream = SequencingStream::default();
+
+        assert!(stream.arrange(u16::MAX - 1, packet()).is_some());
+        assert!(stream.arrange(1, packet()).is_some());
+        assert!(stream.arrange(u16::MAX, packet()).is_none());
+        assert!(stream.arrange(0, packet()).is_none());
+        assert!(stream.arrange(2, packet()).is_some());
+    }
+
+    #[test]
+    fn hands_out_outgoing_ids_around_zero() {
+        let mut stream = SequencingStream::default();
+        stream.outgoing_id = u16::MAX;
+
+        assert_eq!(stream.new_outgoing_id(), u16::MAX);
+        assert_eq!(stream.new_outgoing_id(), 0);
+    }
+}
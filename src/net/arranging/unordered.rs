@@ -0,0 +1,152 @@
+use super::sequence_greater_than;
+use crate::{packet::Packet, sequence_buffer::SequenceBuffer};
+
+/// Releases every packet of a single stream exactly once, in the order they arrive in.
+///
+/// Reliable packets that are sent again get a new sequence number, so the arranging id is what
+/// tells us whether we have seen a packet before. The stream keeps track of the arranging id up to
+/// which every packet has been released, and remembers which of the packets after it were released
+/// already. Packets that are too far ahead of that point are not accepted, so they are not
+/// acknowledged and the other side will send them again once there is room for them.
+pub struct UnorderedStream {
+    /// The arranging id we will give to the next packet we send on this stream.
+    outgoing_id: u16,
+    /// Every packet with an arranging id before this one has been released.
+    expected_id: u16,
+    /// Marks the arranging ids after `expected_id` that were released already.
+    received: SequenceBuffer<bool>,
+}
+
+impl UnorderedStream {
+    /// Creates a new stream which can release up to `window_size` packets ahead of the oldest
+    /// packet that is missing.
+    pub fn new(window_size: u16) -> Self {
+        Self {
+            outgoing_id: 0,
+            expected_id: 0,
+            received: SequenceBuffer::with_capacity(window_size as usize),
+        }
+    }
+
+    /// Returns the arranging id for the next outgoing packet on this stream.
+    pub fn new_outgoing_id(&mut self) -> u16 {
+        let id = self.outgoing_id;
+        self.outgoing_id = self.outgoing_id.wrapping_add(1);
+        id
+    }
+
+    /// Checks if a packet with the given arranging id can be handled by this stream.
+    ///
+    /// This is the case for packets which fit in the window, and for packets that have already
+    /// been released (which will simply be ignored).
+    pub fn is_within_window(&self, arranging_id: u16) -> bool {
+        let distance = arranging_id.wrapping_sub(self.expected_id);
+        (distance as usize) < self.received.len()
+            || !sequence_greater_than(arranging_id, self.expected_id)
+    }
+
+    /// Checks if a packet with the given arranging id was released before.
+    pub fn is_duplicate(&self, arranging_id: u16) -> bool {
+        if arranging_id != self.expected_id
+            && !sequence_greater_than(arranging_id, self.expected_id)
+        {
+            return true;
+        }
+
+        self.received.get(arranging_id) == Some(&true)
+    }
+
+    /// Hands a packet to the stream and returns it, unless it was released before or does not fit
+    /// in the window.
+    pub fn arrange(&mut self, arranging_id: u16, packet: Packet) -> Option<Packet> {
+        if self.is_duplicate(arranging_id) || !self.is_within_window(arranging_id) {
+            return None;
+        }
+
+        self.received.insert(true, arranging_id);
+        while self.received.get(self.expected_id) == Some(&true) {
+            self.received.remove(self.expected_id);
+            self.expected_id = self.expected_id.wrapping_add(1);
+        }
+
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnorderedStream;
+    use crate::Packet;
+
+    fn packet() -> Packet {
+        Packet::reliable_unordered("127.0.0.1:12345".parse().unwrap(), vec![1])
+    }
+
+    #[test]
+    fn releases_packets_in_any_order() {
+        let mut stream = UnorderedStream::new(16);
+
+        assert!(stream.arrange(2, packet()).is_some());
+        assert!(stream.arrange(0, packet()).is_some());
+        assert!(stream.arrange(1, packet()).is_some());
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let mut stream = UnorderedStream::new(16);
+
+        assert!(stream.arrange(0, packet()).is_some());
+        assert!(stream.arrange(3, packet()).is_some());
+        assert!(stream.arrange(0, packet()).is_none());
+        assert!(stream.arrange(3, packet()).is_none());
+        // Packets in the gap have not been seen yet.
+        assert!(stream.arrange(1, packet()).is_some());
+        assert!(stream.arrange(1, packet()).is_none());
+    }
+
+    #[test]
+    fn drops_duplicates_around_zero() {
+        let mut stream = UnorderedStream::new(16);
+        stream.expected_id = u16::MAX - 1;
+
+        assert!(stream.arrange(u16::MAX - 1, packet()).is_some());
+        assert!(stream.arrange(1, packet()).is_some());
+        assert!(stream.arrange(u16::MAX, packet()).is_some());
+        assert!(stream.arrange(0, packet()).is_some());
+
+        assert!(stream.arrange(u16::MAX - 1, packet()).is_none());
+        assert!(stream.arrange(u16::MAX, packet()).is_none());
+        assert!(stream.arrange(0, packet()).is_none());
+        assert!(stream.arrange(1, packet()).is_none());
+    }
+
+    #[test]
+    fn does_not_accept_packets_beyond_the_window() {
+        let mut stream = UnorderedStream::new(16);
+
+        assert!(!stream.is_within_window(20));
+        assert!(stream.arrange(20, packet()).is_none());
+
+        // Older packets are never lost, however far behind the newest packet they are.
+        assert!(stream.arrange(15, packet()).is_some());
+        for arranging_id in 0..15 {
+            assert!(stream.arrange(arranging_id, packet()).is_some());
+        }
+        assert!(stream.is_within_window(20));
+        assert!(stream.arrange(20, packet()).is_some());
+        assert!(stream.arrange(4, packet()).is_none());
+    }
+
+    #[test]
+    fn reuses_slots_of_released_packets() {
+        let mut stream = UnorderedStream::new(16);
+
+        assert!(stream.arrange(0, packet()).is_some());
+        // This id maps onto the same slot as the first packet.
+        assert!(stream.arrange(16, packet()).is_some());
+
+        assert!(stream.arrange(16, packet()).is_none());
+        assert!(stream.arrange(0, packet()).is_none());
+        assert!(stream.arrange(1, packet()).is_some());
+    }
+}
@@ -1,39 +1,77 @@
+mod congestion;
+mod mtu;
 mod quality;
+mod stats;
+mod transfer;
 mod virtual_connection;
 
-pub use self::quality::{NetworkQuality, RttMeasurer};
+pub use self::congestion::CongestionControl;
+pub use self::mtu::{MtuDiscovery, MIN_MTU};
+pub use self::quality::{NetworkQuality, RoundTripTime, RttMeasurer};
+pub use self::stats::{ConnectionStats, StatsRecorder};
+pub use self::transfer::{TransferEvent, TransferId, TransferProgress, Transfers};
 pub use self::virtual_connection::VirtualConnection;
 
-use crate::config::SocketConfig;
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use crate::{config::SocketConfig, packet::BufferPool};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 /// Maintains a registry of active "connections". Essentially, when we receive a packet on the
 /// socket from a particular `SocketAddr`, we will track information about it here.
 pub struct ActiveConnections {
     connections: HashMap<SocketAddr, VirtualConnection>,
+    // Shared by all connections, so a buffer can be reused whichever connection it came from.
+    buffer_pool: BufferPool,
 }
 
 impl ActiveConnections {
-    pub fn new() -> Self {
+    pub fn new(config: &SocketConfig) -> Self {
         Self {
             connections: HashMap::new(),
+            buffer_pool: BufferPool::new(
+                config.payload_pool_size(),
+                config.receive_buffer_size_bytes(),
+            ),
         }
     }
 
     /// Try to get a VirtualConnection by address. If the connection does not exist, it will be
     /// inserted and returned.
+    ///
+    /// Also returns whether the connection was newly inserted.
     pub fn get_or_insert_connection(
         &mut self,
         address: &SocketAddr,
         config: &SocketConfig,
-    ) -> &mut VirtualConnection {
-        if !self.connections.contains_key(address) {
-            self.connections
-                .insert(*address, VirtualConnection::new(*address, config));
+    ) -> (&mut VirtualConnection, bool) {
+        let is_new = !self.connections.contains_key(address);
+        if is_new {
+            let connection = VirtualConnection::new(*address, config, self.buffer_pool.clone());
+            self.connections.insert(*address, connection);
         }
-        self.connections
+        let connection = self
+            .connections
             .get_mut(address)
-            .expect("We just added this key. It should definitely exist.")
+            .expect("We just added this key. It should definitely exist.");
+        (connection, is_new)
+    }
+
+    /// Get the VirtualConnection with the given address, if there is one.
+    pub fn get_connection(&self, address: &SocketAddr) -> Option<&VirtualConnection> {
+        self.connections.get(address)
+    }
+
+    /// Get the VirtualConnection with the given address mutably, if there is one.
+    pub fn get_connection_mut(&mut self, address: &SocketAddr) -> Option<&mut VirtualConnection> {
+        self.connections.get_mut(address)
+    }
+
+    /// Checks whether there is a connection with the given address.
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        self.connections.contains_key(address)
     }
 
     /// Removes the connection from ActiveConnections by socket address.
@@ -45,14 +83,73 @@ impl ActiveConnections {
     }
 
     /// Check for and return VirtualConnections which have been idling longer than `max_idle_time`.
-    pub fn idle_connections(&mut self, max_idle_time: Duration) -> Vec<SocketAddr> {
+    pub fn idle_connections(&mut self, max_idle_time: Duration, now: Instant) -> Vec<SocketAddr> {
         self.connections
             .iter()
-            .filter(|(_, connection)| connection.time_since_last_packet() >= max_idle_time)
-            .map(|(address, _)| address.clone())
+            .filter(|(_, connection)| connection.time_since_last_packet(now) >= max_idle_time)
+            .map(|(address, _)| *address)
             .collect()
     }
 
+    /// Returns the VirtualConnections we haven't sent anything to for at least `heartbeat_interval`.
+    pub fn heartbeat_required_connections(
+        &mut self,
+        heartbeat_interval: Duration,
+        now: Instant,
+    ) -> impl Iterator<Item = &mut VirtualConnection> {
+        self.connections
+            .values_mut()
+            .filter(move |connection| connection.time_since_last_sent(now) >= heartbeat_interval)
+    }
+
+    /// Returns the VirtualConnections that have been waiting for at least `ack_delay` to send our
+    /// acknowledgements.
+    pub fn ack_required_connections(
+        &mut self,
+        ack_delay: Duration,
+        now: Instant,
+    ) -> impl Iterator<Item = &mut VirtualConnection> {
+        self.connections
+            .values_mut()
+            .filter(move |connection| connection.acks_required(ack_delay, now))
+    }
+
+    /// Checks if any of the VirtualConnections has acknowledgements that weren't sent yet.
+    pub fn has_pending_acks(&self) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.has_pending_acks())
+    }
+
+    /// Checks if any of the VirtualConnections has packets that are waiting to be acknowledged.
+    pub fn has_unacked_packets(&self) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.has_unacked_packets())
+    }
+
+    /// Get the time until the first of the VirtualConnections with paced packets or transfer chunks
+    /// is allowed to send again, or `None` if there is nothing waiting to be sent at all.
+    pub fn time_until_next_send(&self) -> Option<Duration> {
+        self.connections
+            .values()
+            .filter(|connection| {
+                connection.has_paced_packets() || connection.has_sendable_transfer_chunks()
+            })
+            .map(|connection| connection.time_until_next_send())
+            .min()
+    }
+
+    /// Returns an iterator over all of the VirtualConnections.
+    pub fn connections_mut(&mut self) -> impl Iterator<Item = &mut VirtualConnection> {
+        self.connections.values_mut()
+    }
+
+    /// Get the addresses of all connected clients.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.connections.keys().cloned().collect()
+    }
+
     /// Get the number of connected clients.
     pub fn count(&self) -> usize {
         self.connections.len()
@@ -62,14 +159,17 @@ impl ActiveConnections {
 #[cfg(test)]
 mod tests {
     use super::{ActiveConnections, SocketConfig};
-    use std::{thread, time::Duration};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
 
     const ADDRESS: &str = "127.0.0.1:12345";
 
     #[test]
     fn connection_timed_out() {
-        let mut connections = ActiveConnections::new();
         let config = SocketConfig::default();
+        let mut connections = ActiveConnections::new(&config);
 
         // add 10 clients
         for i in 0..10 {
@@ -84,37 +184,40 @@ mod tests {
         // Sleep a little longer than the polling interval.
         thread::sleep(Duration::from_millis(400));
 
-        let timed_out_connections = connections.idle_connections(Duration::from_millis(200));
+        let timed_out_connections =
+            connections.idle_connections(Duration::from_millis(200), Instant::now());
 
         assert_eq!(timed_out_connections.len(), 10);
     }
 
     #[test]
     fn insert_connection() {
-        let mut connections = ActiveConnections::new();
         let config = SocketConfig::default();
+        let mut connections = ActiveConnections::new(&config);
 
         let address = &ADDRESS.parse().unwrap();
-        connections.get_or_insert_connection(address, &config);
+        let (_, is_new) = connections.get_or_insert_connection(address, &config);
+        assert!(is_new);
         assert!(connections.connections.contains_key(address));
     }
 
     #[test]
     fn insert_existing_connection() {
-        let mut connections = ActiveConnections::new();
         let config = SocketConfig::default();
+        let mut connections = ActiveConnections::new(&config);
 
         let address = &ADDRESS.parse().unwrap();
         connections.get_or_insert_connection(address, &config);
         assert!(connections.connections.contains_key(address));
-        connections.get_or_insert_connection(address, &config);
+        let (_, is_new) = connections.get_or_insert_connection(address, &config);
+        assert!(!is_new);
         assert!(connections.connections.contains_key(address));
     }
 
     #[test]
     fn remove_connection() {
-        let mut connections = ActiveConnections::new();
         let config = SocketConfig::default();
+        let mut connections = ActiveConnections::new(&config);
 
         let address = &ADDRESS.parse().unwrap();
         connections.get_or_insert_connection(address, &config);
@@ -125,7 +228,7 @@ mod tests {
 
     #[test]
     fn remove_non_existing_connection() {
-        let mut connections = ActiveConnections::new();
+        let mut connections = ActiveConnections::new(&SocketConfig::default());
 
         let address = &ADDRESS.parse().unwrap();
         connections.remove_connection(address);
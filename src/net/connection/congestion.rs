@@ -0,0 +1,311 @@
+use super::NetworkQuality;
+use crate::config::SocketConfig;
+use std::time::{Duration, Instant};
+
+/// How much a single acknowledged or lost packet moves the smoothed packet loss.
+const LOSS_SMOOTHING_FACTOR: f32 = 0.1;
+/// The time conditions have to stay good before a connection returns to good mode, at first.
+const INITIAL_RECOVERY_TIME: Duration = Duration::from_secs(4);
+/// The recovery time never gets shorter than this.
+const MIN_RECOVERY_TIME: Duration = Duration::from_secs(1);
+/// The recovery time never gets longer than this.
+const MAX_RECOVERY_TIME: Duration = Duration::from_secs(60);
+/// A connection that drops back to bad mode within this time after recovering has its recovery
+/// time doubled, and every time it stays in good mode this long its recovery time is halved.
+const PENALTY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Decides how fast we are allowed to send to a connection.
+///
+/// This is the simple binary congestion avoidance described by Gaffer on Games. A connection is
+/// either in good or in bad mode, each with its own send rate. It drops to bad mode as soon as
+/// the round trip time, its variance (jitter) or the packet loss crosses its threshold, and only
+/// returns to good mode after conditions have been good for a while. Connections that keep
+/// flapping between the modes have to wait longer before they are trusted again.
+///
+/// Packets are paced with a token bucket which holds up to a second worth of packets.
+pub struct CongestionControl {
+    quality: NetworkQuality,
+    good_send_rate: u32,
+    bad_send_rate: u32,
+    rtt_threshold: Duration,
+    jitter_threshold: Duration,
+    loss_threshold: f32,
+    rtt: Option<Duration>,
+    jitter: Option<Duration>,
+    packet_loss: f32,
+    /// When we entered the current mode.
+    mode_changed_at: Instant,
+    /// Since when conditions are good again while we are in bad mode.
+    good_conditions_since: Option<Instant>,
+    /// How long conditions have to stay good before we return to good mode.
+    recovery_time: Duration,
+    /// The last time the recovery time was shortened while in good mode.
+    last_recovery_reduction: Instant,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl CongestionControl {
+    /// Creates the congestion control for a new connection, which starts out in good mode.
+    pub fn new(config: &SocketConfig, now: Instant) -> Self {
+        Self {
+            quality: NetworkQuality::Good,
+            good_send_rate: config.good_send_rate(),
+            bad_send_rate: config.bad_send_rate(),
+            rtt_threshold: config.congestion_rtt_threshold(),
+            jitter_threshold: config.congestion_jitter_threshold(),
+            loss_threshold: config.congestion_loss_threshold(),
+            rtt: None,
+            jitter: None,
+            packet_loss: 0.0,
+            mode_changed_at: now,
+            good_conditions_since: None,
+            recovery_time: INITIAL_RECOVERY_TIME,
+            last_recovery_reduction: now,
+            tokens: config.good_send_rate() as f32,
+            last_refill: now,
+        }
+    }
+
+    /// Get the mode the connection is in.
+    pub fn quality(&self) -> NetworkQuality {
+        self.quality
+    }
+
+    /// Get the number of packets per second we are allowed to send in the current mode.
+    pub fn send_rate(&self) -> u32 {
+        match self.quality() {
+            NetworkQuality::Good => self.good_send_rate,
+            NetworkQuality::Bad => self.bad_send_rate,
+        }
+    }
+
+    /// Get the smoothed fraction of packets that got lost.
+    pub fn packet_loss(&self) -> f32 {
+        self.packet_loss
+    }
+
+    /// Takes the new smoothed round trip time and its variance into account. The variance is
+    /// `None` while there are too few samples to tell.
+    pub fn on_rtt_sample(&mut self, rtt: Duration, jitter: Option<Duration>, now: Instant) {
+        self.rtt = Some(rtt);
+        self.jitter = jitter;
+        self.update(now);
+    }
+
+    /// Takes an acknowledged packet into account.
+    pub fn on_packet_acked(&mut self, now: Instant) {
+        self.packet_loss *= 1.0 - LOSS_SMOOTHING_FACTOR;
+        self.update(now);
+    }
+
+    /// Takes a lost packet into account.
+    pub fn on_packet_lost(&mut self, now: Instant) {
+        self.packet_loss += (1.0 - self.packet_loss) * LOSS_SMOOTHING_FACTOR;
+        self.update(now);
+    }
+
+    /// Takes a token to send a packet with, if there is one.
+    pub fn try_send(&mut self, now: Instant) -> bool {
+        self.update(now);
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Get the time until the next token becomes available.
+    pub fn time_until_next_send(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::from_secs(0);
+        }
+        let missing = 1.0 - self.tokens;
+        Duration::from_secs_f32(missing / self.send_rate().max(1) as f32)
+    }
+
+    /// Switches between good and bad mode if conditions call for it.
+    fn update(&mut self, now: Instant) {
+        let conditions_are_bad = self.rtt.is_some_and(|rtt| rtt > self.rtt_threshold)
+            || self
+                .jitter
+                .is_some_and(|jitter| jitter > self.jitter_threshold)
+            || self.packet_loss > self.loss_threshold;
+
+        match self.quality {
+            NetworkQuality::Good if conditions_are_bad => {
+                // Connections that can't stay in good mode have to prove themselves for longer.
+                if now.duration_since(self.mode_changed_at) < PENALTY_INTERVAL {
+                    self.recovery_time = (self.recovery_time * 2).min(MAX_RECOVERY_TIME);
+                }
+                self.quality = NetworkQuality::Bad;
+                self.mode_changed_at = now;
+                self.good_conditions_since = None;
+                self.tokens = self.tokens.min(self.bad_send_rate as f32);
+            }
+            NetworkQuality::Good => {
+                if now.duration_since(self.last_recovery_reduction) >= PENALTY_INTERVAL {
+                    self.recovery_time = (self.recovery_time / 2).max(MIN_RECOVERY_TIME);
+                    self.last_recovery_reduction = now;
+                }
+            }
+            NetworkQuality::Bad if conditions_are_bad => self.good_conditions_since = None,
+            NetworkQuality::Bad => {
+                let good_conditions_since = *self.good_conditions_since.get_or_insert(now);
+                if now.duration_since(good_conditions_since) >= self.recovery_time {
+                    self.quality = NetworkQuality::Good;
+                    self.mode_changed_at = now;
+                    self.last_recovery_reduction = now;
+                }
+            }
+        }
+    }
+
+    /// Adds the tokens that became available since the last refill.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let send_rate = self.send_rate() as f32;
+        self.tokens = (self.tokens + elapsed.as_secs_f32() * send_rate).min(send_rate);
+        self.last_refill = self.last_refill.max(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CongestionControl, INITIAL_RECOVERY_TIME, PENALTY_INTERVAL};
+    use crate::{config::SocketConfig, net::connection::NetworkQuality};
+    use std::time::{Duration, Instant};
+
+    const JITTER: Option<Duration> = Some(Duration::from_millis(10));
+
+    fn congestion_control(now: Instant) -> CongestionControl {
+        CongestionControl::new(&SocketConfig::default(), now)
+    }
+
+    #[test]
+    fn drops_to_bad_mode_on_high_rtt() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+        let config = SocketConfig::default();
+
+        control.on_rtt_sample(Duration::from_millis(50), JITTER, now);
+        assert_eq!(control.quality(), NetworkQuality::Good);
+        assert_eq!(control.send_rate(), config.good_send_rate());
+
+        control.on_rtt_sample(Duration::from_millis(500), JITTER, now);
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+        assert_eq!(control.send_rate(), config.bad_send_rate());
+    }
+
+    #[test]
+    fn drops_to_bad_mode_on_packet_loss() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+
+        // A single lost packet is no reason to slow down yet.
+        control.on_packet_lost(now);
+        assert_eq!(control.quality(), NetworkQuality::Good);
+
+        control.on_packet_lost(now);
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+
+        for _ in 0..10 {
+            control.on_packet_acked(now);
+        }
+        assert!(control.packet_loss() < SocketConfig::default().congestion_loss_threshold());
+    }
+
+    #[test]
+    fn drops_to_bad_mode_on_high_jitter() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+        let rtt = Duration::from_millis(50);
+
+        control.on_rtt_sample(rtt, Some(Duration::from_millis(20)), now);
+        assert_eq!(control.quality(), NetworkQuality::Good);
+
+        control.on_rtt_sample(rtt, Some(Duration::from_millis(200)), now);
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+    }
+
+    #[test]
+    fn recovers_after_good_conditions_last() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+        let later = now + PENALTY_INTERVAL;
+
+        control.on_rtt_sample(Duration::from_millis(500), JITTER, later);
+        control.on_rtt_sample(Duration::from_millis(50), JITTER, later);
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+
+        control.on_rtt_sample(
+            Duration::from_millis(50),
+            JITTER,
+            later + INITIAL_RECOVERY_TIME / 2,
+        );
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+
+        control.on_rtt_sample(
+            Duration::from_millis(50),
+            JITTER,
+            later + INITIAL_RECOVERY_TIME,
+        );
+        assert_eq!(control.quality(), NetworkQuality::Good);
+    }
+
+    #[test]
+    fn flapping_doubles_recovery_time() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+
+        // Dropping to bad mode right after entering good mode is penalized.
+        control.on_rtt_sample(Duration::from_millis(500), JITTER, now);
+        control.on_rtt_sample(Duration::from_millis(50), JITTER, now);
+        assert_eq!(control.recovery_time, INITIAL_RECOVERY_TIME * 2);
+
+        control.on_rtt_sample(
+            Duration::from_millis(50),
+            JITTER,
+            now + INITIAL_RECOVERY_TIME,
+        );
+        assert_eq!(control.quality(), NetworkQuality::Bad);
+        control.on_rtt_sample(
+            Duration::from_millis(50),
+            JITTER,
+            now + INITIAL_RECOVERY_TIME * 2,
+        );
+        assert_eq!(control.quality(), NetworkQuality::Good);
+    }
+
+    #[test]
+    fn paces_packets() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+        let send_rate = SocketConfig::default().good_send_rate();
+
+        for _ in 0..send_rate {
+            assert!(control.try_send(now));
+        }
+        assert!(!control.try_send(now));
+
+        let next_send = control.time_until_next_send();
+        assert!(next_send > Duration::from_secs(0));
+        assert!(control.try_send(now + next_send + Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn paces_slower_in_bad_mode() {
+        let now = Instant::now();
+        let mut control = congestion_control(now);
+        let bad_send_rate = SocketConfig::default().bad_send_rate();
+
+        control.on_rtt_sample(Duration::from_millis(500), JITTER, now);
+        for _ in 0..bad_send_rate {
+            assert!(control.try_send(now));
+        }
+        assert!(!control.try_send(now));
+    }
+}
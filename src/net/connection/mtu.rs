@@ -0,0 +1,268 @@
+use crate::{config::SocketConfig, packet::headers::max_header_size};
+use std::time::{Duration, Instant};
+
+/// The largest datagram every IPv4 path has to carry without fragmenting: the minimal reassembly
+/// buffer size of 576 bytes, minus the largest IP header and the UDP header.
+pub const MIN_MTU: u16 = 508;
+/// The search stops once the known good and known bad sizes are this close to each other.
+const PROBE_PRECISION: u16 = 16;
+/// The number of times a probe of the same size is sent before we conclude it doesn't fit.
+const MAX_PROBE_ATTEMPTS: u8 = 3;
+/// The time after which a finished search starts over, in case the path has changed.
+const REPROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// A probe that is waiting to be acknowledged.
+struct Probe {
+    size: u16,
+    sent_at: Instant,
+    attempts: u8,
+}
+
+/// Finds the largest datagram that makes it to a connection, by sending it padded probes.
+///
+/// This is a binary search between the largest size that was acknowledged and the smallest size
+/// that wasn't. The search starts with the size we would use without it, so a path that can't
+/// carry that size is noticed right away, and we fall back to the largest size that is known to
+/// work until the search finds a better one.
+///
+/// The operating system may fragment our datagrams on the IP level, in which case probes larger
+/// than the real MTU still arrive. This finds the largest size that *arrives*, which is what
+/// matters on tunnels and VPNs that drop IP fragments.
+pub struct MtuDiscovery {
+    is_enabled: bool,
+    /// Whether the peer proved it is reachable, we don't send probes to unverified addresses.
+    is_started: bool,
+    /// The largest datagram size we are allowed to send.
+    max_mtu: u16,
+    /// The datagram size we are using.
+    mtu: u16,
+    /// The largest size that is known to arrive.
+    known_good: u16,
+    /// The largest size that might still arrive.
+    maybe_good: u16,
+    probe: Option<Probe>,
+    /// The next time a probe should be sent, or `None` while a probe is in flight.
+    next_probe_at: Option<Instant>,
+}
+
+impl MtuDiscovery {
+    /// Creates the discovery for a new connection. It doesn't search until `start` is called.
+    pub fn new(config: &SocketConfig) -> Self {
+        let max_mtu = config.max_mtu();
+        let initial_mtu = (config.fragment_size_bytes() as usize + max_header_size())
+            .min(max_mtu as usize) as u16;
+        Self {
+            is_enabled: config.mtu_discovery_enabled(),
+            is_started: false,
+            max_mtu,
+            mtu: initial_mtu,
+            known_good: MIN_MTU.min(initial_mtu),
+            maybe_good: max_mtu,
+            probe: None,
+            next_probe_at: None,
+        }
+    }
+
+    /// Starts the search, once the peer proved it is reachable.
+    ///
+    /// Probes are much larger than the packets that caused them, so sending them to an address
+    /// that might be spoofed would let anyone use us to flood a third party.
+    pub fn start(&mut self, now: Instant) {
+        if !self.is_started {
+            self.is_started = true;
+            self.next_probe_at = Some(now);
+        }
+    }
+
+    /// Get the datagram size we are currently using.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Returns the size of the probe to send now, if one should be sent. A probe that was not
+    /// acknowledged within `timeout` is sent again, until it is considered too large.
+    pub fn poll_probe(&mut self, now: Instant, timeout: Duration) -> Option<u16> {
+        if !self.is_enabled {
+            return None;
+        }
+
+        if let Some(probe) = &mut self.probe {
+            if now.duration_since(probe.sent_at) < timeout {
+                return None;
+            }
+            if probe.attempts < MAX_PROBE_ATTEMPTS {
+                probe.attempts += 1;
+                probe.sent_at = now;
+                return Some(probe.size);
+            }
+
+            let size = probe.size;
+            self.probe = None;
+            self.on_probe_lost(size, now);
+        }
+
+        match self.next_probe_at {
+            Some(next_probe_at) if now >= next_probe_at => {}
+            _ => return None,
+        }
+
+        // The size we are using comes first, since everything breaks if it doesn't fit.
+        let size = if self.mtu > self.known_good {
+            self.mtu
+        } else if self.maybe_good - self.known_good > PROBE_PRECISION {
+            self.known_good + (self.maybe_good - self.known_good).div_ceil(2)
+        } else {
+            // The search is done, try again later in case the path changed.
+            self.next_probe_at = Some(now + REPROBE_INTERVAL);
+            self.known_good = MIN_MTU.min(self.mtu);
+            self.maybe_good = self.max_mtu;
+            return None;
+        };
+
+        self.next_probe_at = None;
+        self.probe = Some(Probe {
+            size,
+            sent_at: now,
+            attempts: 1,
+        });
+        Some(size)
+    }
+
+    /// Takes the acknowledgement of a probe of the given size into account.
+    ///
+    /// Returns `true` if we switched to a larger datagram size.
+    pub fn on_probe_acked(&mut self, size: u16, now: Instant) -> bool {
+        match &self.probe {
+            Some(probe) if probe.size == size => self.probe = None,
+            _ => return false,
+        }
+
+        self.known_good = self.known_good.max(size);
+        self.next_probe_at = Some(now);
+        if size > self.mtu {
+            self.mtu = size;
+            return true;
+        }
+        false
+    }
+
+    /// Takes a probe that was never acknowledged into account.
+    fn on_probe_lost(&mut self, size: u16, now: Instant) {
+        self.maybe_good = self.maybe_good.min(size - 1).max(self.known_good);
+        if self.mtu > self.maybe_good {
+            // The size we were using doesn't fit, fall back to the largest size that does.
+            self.mtu = self.known_good;
+        }
+        self.next_probe_at = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MtuDiscovery, MAX_PROBE_ATTEMPTS, MIN_MTU, REPROBE_INTERVAL};
+    use crate::config::SocketConfig;
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn enabled_config() -> SocketConfig {
+        SocketConfig::builder()
+            .mtu_discovery_enabled(true)
+            .build()
+            .unwrap()
+    }
+
+    /// Runs the search against a path that carries datagrams up to `path_mtu` bytes and returns the
+    /// size the discovery settles on.
+    fn discover(config: &SocketConfig, path_mtu: u16) -> u16 {
+        let mut now = Instant::now();
+        let mut discovery = MtuDiscovery::new(config);
+        discovery.start(now);
+
+        for _ in 0..100 {
+            match discovery.poll_probe(now, TIMEOUT) {
+                Some(size) if size <= path_mtu => {
+                    discovery.on_probe_acked(size, now);
+                }
+                Some(_) => now += TIMEOUT,
+                None if discovery.probe.is_some() => now += TIMEOUT,
+                None => return discovery.mtu(),
+            }
+        }
+        panic!("The search did not finish.");
+    }
+
+    #[test]
+    fn keeps_initial_mtu_when_path_is_large_enough() {
+        let config = enabled_config();
+        let initial_mtu = MtuDiscovery::new(&config).mtu();
+
+        assert_eq!(discover(&config, initial_mtu), initial_mtu);
+    }
+
+    #[test]
+    fn discovers_larger_mtu() {
+        let config = SocketConfig::builder()
+            .fragment_size_bytes(1000)
+            .mtu_discovery_enabled(true)
+            .build()
+            .unwrap();
+
+        let mtu = discover(&config, config.max_mtu());
+        assert!(mtu > config.max_mtu() - 16);
+        assert!(mtu <= config.max_mtu());
+    }
+
+    #[test]
+    fn falls_back_on_smaller_path() {
+        let config = enabled_config();
+
+        let mtu = discover(&config, 1200);
+        assert!(mtu > 1200 - 16);
+        assert!(mtu <= 1200);
+    }
+
+    #[test]
+    fn gives_up_on_probe_after_attempts() {
+        let now = Instant::now();
+        let config = enabled_config();
+        let mut discovery = MtuDiscovery::new(&config);
+        discovery.start(now);
+        let initial_mtu = discovery.mtu();
+
+        for attempt in 0..MAX_PROBE_ATTEMPTS {
+            let at = now + TIMEOUT * u32::from(attempt);
+            assert_eq!(discovery.poll_probe(at, TIMEOUT), Some(initial_mtu));
+        }
+
+        // The size we were using doesn't fit, so the next probe is smaller.
+        let at = now + TIMEOUT * u32::from(MAX_PROBE_ATTEMPTS);
+        let next_size = discovery.poll_probe(at, TIMEOUT).unwrap();
+        assert!(next_size < initial_mtu);
+        assert_eq!(discovery.mtu(), MIN_MTU);
+    }
+
+    #[test]
+    fn does_nothing_when_disabled() {
+        let config = SocketConfig::builder()
+            .mtu_discovery_enabled(false)
+            .build()
+            .unwrap();
+        let mut discovery = MtuDiscovery::new(&config);
+        discovery.start(Instant::now());
+
+        assert_eq!(discovery.poll_probe(Instant::now(), TIMEOUT), None);
+    }
+
+    #[test]
+    fn does_not_probe_until_started() {
+        let now = Instant::now();
+        let mut discovery = MtuDiscovery::new(&enabled_config());
+
+        assert_eq!(discovery.poll_probe(now, TIMEOUT), None);
+        assert_eq!(discovery.poll_probe(now + REPROBE_INTERVAL, TIMEOUT), None);
+
+        discovery.start(now);
+        assert_eq!(discovery.poll_probe(now, TIMEOUT), Some(discovery.mtu()));
+    }
+}
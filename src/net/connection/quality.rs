@@ -1,9 +1,16 @@
 use crate::config::SocketConfig;
-use crate::sequence_buffer::CongestionData;
 
 use std::time::Duration;
 
+/// How much a single sample moves the rtt variance, as recommended by RFC 6298.
+const RTT_VARIANCE_FACTOR: f32 = 0.25;
+/// The number of samples before the rtt variance tells anything about the jitter. It starts out
+/// at half the first sample, which keeps the retransmission timeout on the safe side, but would
+/// look like jitter on any connection with a high round trip time.
+const MIN_JITTER_SAMPLES: u32 = 4;
+
 /// Represents the quality of a network.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum NetworkQuality {
     /// Connection is generally good, minimal packet loss or latency
     Good,
@@ -11,104 +18,186 @@ pub enum NetworkQuality {
     Bad,
 }
 
-/// This type helps with calculating the round trip time from any packet.
-/// It is able to smooth out the network jitter if there is any.
+/// A snapshot of the round trip time (rtt) measurements of a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RoundTripTime {
+    smoothed: Duration,
+    variance: Duration,
+    min: Duration,
+    latest: Duration,
+}
+
+impl RoundTripTime {
+    /// Get the smoothed round trip time, which is what you would show as the ping of a connection.
+    pub fn smoothed(&self) -> Duration {
+        self.smoothed
+    }
+
+    /// Get the smoothed mean deviation of the round trip time, also known as jitter.
+    pub fn variance(&self) -> Duration {
+        self.variance
+    }
+
+    /// Get the lowest round trip time that was measured.
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// Get the round trip time that was measured last.
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+}
+
+/// This type helps with calculating the round trip time (rtt) from any packet.
+///
+/// It keeps a smoothed rtt and the smoothed mean deviation of the rtt samples, as described in
+/// RFC 6298, so that a single slow packet doesn't directly make the connection look bad.
 pub struct RttMeasurer {
-    rtt_max_value: i64,
     rtt_smoothing_factor: f32,
+    round_trip_time: Option<RoundTripTime>,
+    samples: u32,
 }
 
 impl RttMeasurer {
     /// Creates and returns a new RttMeasurer
     pub fn new(config: &SocketConfig) -> RttMeasurer {
         Self {
-            rtt_max_value: i64::from(config.rtt_max_value()),
             rtt_smoothing_factor: config.rtt_smoothing_factor(),
+            round_trip_time: None,
+            samples: 0,
         }
     }
 
-    /// This will calculate the round trip time (rtt) from the given acknowledgement.
-    /// Where after it updates the rtt from the given connection.
-    pub fn get_rtt(&self, congestion_data: Option<&mut CongestionData>) -> f32 {
-        self.get_smoothed_rtt(congestion_data)
+    /// Get the current measurements, or `None` if nothing has been measured yet.
+    pub fn round_trip_time(&self) -> Option<RoundTripTime> {
+        self.round_trip_time
     }
 
-    /// This will get the smoothed round trip time (rtt) from the time we last heard from an packet.
-    fn get_smoothed_rtt(&self, congestion_avoidance_entry: Option<&mut CongestionData>) -> f32 {
-        match congestion_avoidance_entry {
-            Some(avoidance_data) => {
-                let elapsed_time = avoidance_data.sending_time.elapsed();
-
-                let rtt_time = self.as_milliseconds(elapsed_time);
-
-                self.smooth_out_rtt(rtt_time)
-            }
-            None => 0.0,
+    /// Get the smoothed mean deviation of the round trip time, or `None` until enough samples
+    /// were taken for it to mean anything.
+    pub fn jitter(&self) -> Option<Duration> {
+        match self.round_trip_time {
+            Some(rtt) if self.samples >= MIN_JITTER_SAMPLES => Some(rtt.variance()),
+            _ => None,
         }
     }
 
-    /// Converts a duration to milliseconds.
+    /// Takes the round trip time of a single acknowledged packet into account.
     ///
-    /// `as_milliseconds` is not supported yet supported in rust stable.
-    /// See this stackoverflow post for more info: https://stackoverflow.com/questions/36816072/how-do-i-get-a-duration-as-a-number-of-milliseconds-in-rust
-    fn as_milliseconds(&self, duration: Duration) -> u64 {
-        let nanos = u64::from(duration.subsec_nanos());
-        (1000 * 1000 * 1000 * duration.as_secs() + nanos) / (1000 * 1000)
+    /// Packets that were sent more than once should not be measured, since we can't tell which of
+    /// the sends was acknowledged.
+    pub fn update(&mut self, rtt: Duration) {
+        let round_trip_time = match self.round_trip_time {
+            Some(previous) => {
+                let deviation = rtt.abs_diff(previous.smoothed);
+                RoundTripTime {
+                    smoothed: lerp(previous.smoothed, rtt, self.rtt_smoothing_factor),
+                    variance: lerp(previous.variance, deviation, RTT_VARIANCE_FACTOR),
+                    min: previous.min.min(rtt),
+                    latest: rtt,
+                }
+            }
+            None => RoundTripTime {
+                smoothed: rtt,
+                variance: rtt / 2,
+                min: rtt,
+                latest: rtt,
+            },
+        };
+        self.round_trip_time = Some(round_trip_time);
+        self.samples = self.samples.saturating_add(1);
     }
+}
 
-    /// Smooth out round trip time (rtt) value by the specified smoothing factor.
-    ///
-    /// First we subtract the max allowed rtt.
-    /// This way we can see by how many we are off from the max allowed rtt.
-    /// Then we multiply with or smoothing factor.
-    ///
-    /// We do this so that if one packet has an bad rtt it will not directly bring down the or network quality estimation.
-    /// The default is 10% smoothing so if in total or packet is 50 milliseconds later than max allowed rtt we will increase or rtt estimation with 5.
-    fn smooth_out_rtt(&self, rtt: u64) -> f32 {
-        let exceeded_rrt_time = (rtt as i64 - self.rtt_max_value) as f32;
-        exceeded_rrt_time * self.rtt_smoothing_factor
-    }
+/// Moves `from` towards `to` by the given factor.
+fn lerp(from: Duration, to: Duration, factor: f32) -> Duration {
+    from.mul_f32(1.0 - factor) + to.mul_f32(factor)
 }
 
 #[cfg(test)]
 mod test {
-    use super::RttMeasurer;
+    use super::{RttMeasurer, MIN_JITTER_SAMPLES};
     use crate::config::SocketConfig;
     use crate::net::connection::VirtualConnection;
+    use crate::packet::BufferPool;
     use std::net::ToSocketAddrs;
     use std::time::Duration;
 
     static TEST_HOST_IP: &'static str = "127.0.0.1";
     static TEST_PORT: &'static str = "20000";
 
+    /// Checks that the durations are equal apart from floating point rounding errors.
+    fn assert_close(actual: Duration, expected: Duration) {
+        let difference = (actual.as_secs_f64() - expected.as_secs_f64()).abs();
+        assert!(difference < 1e-6, "{:?} != {:?}", actual, expected);
+    }
+
     #[test]
     fn test_create_connection() {
         let mut addr = format!("{}:{}", TEST_HOST_IP, TEST_PORT)
             .to_socket_addrs()
             .unwrap();
-        let _new_conn = VirtualConnection::new(addr.next().unwrap(), &SocketConfig::default());
+        let config = SocketConfig::default();
+        let buffer_pool = BufferPool::new(
+            config.payload_pool_size(),
+            config.receive_buffer_size_bytes(),
+        );
+        let _new_conn = VirtualConnection::new(addr.next().unwrap(), &config, buffer_pool);
     }
 
     #[test]
-    fn convert_duration_to_milliseconds_test() {
-        let network_quality = RttMeasurer::new(&SocketConfig::default());
-        let milliseconds1 = network_quality.as_milliseconds(Duration::from_secs(1));
-        let milliseconds2 = network_quality.as_milliseconds(Duration::from_millis(1500));
-        let milliseconds3 = network_quality.as_milliseconds(Duration::from_millis(1671));
-
-        assert_eq!(milliseconds1, 1000);
-        assert_eq!(milliseconds2, 1500);
-        assert_eq!(milliseconds3, 1671);
+    fn first_sample_initializes_measurements() {
+        let mut measurer = RttMeasurer::new(&SocketConfig::default());
+        assert_eq!(measurer.round_trip_time(), None);
+
+        measurer.update(Duration::from_millis(100));
+
+        let rtt = measurer.round_trip_time().unwrap();
+        assert_eq!(rtt.smoothed(), Duration::from_millis(100));
+        assert_eq!(rtt.variance(), Duration::from_millis(50));
+        assert_eq!(rtt.min(), Duration::from_millis(100));
+        assert_eq!(rtt.latest(), Duration::from_millis(100));
     }
 
     #[test]
-    fn smooth_out_rtt() {
-        let config = SocketConfig::default();
+    fn reports_jitter_after_a_few_samples() {
+        let mut measurer = RttMeasurer::new(&SocketConfig::default());
+
+        // A high but steady round trip time is not jitter.
+        for _ in 0..MIN_JITTER_SAMPLES - 1 {
+            measurer.update(Duration::from_millis(240));
+            assert_eq!(measurer.jitter(), None);
+        }
+        measurer.update(Duration::from_millis(240));
+        assert!(measurer.jitter().unwrap() < Duration::from_millis(100));
+    }
 
-        let network_quality = RttMeasurer::new(&config);
-        let smoothed_rtt = network_quality.smooth_out_rtt(300);
+    #[test]
+    fn smooths_out_rtt() {
+        let mut measurer = RttMeasurer::new(&SocketConfig::default());
+        measurer.update(Duration::from_millis(100));
+        measurer.update(Duration::from_millis(300));
+
+        // The default smoothing factor only moves the smoothed rtt 10% towards the new sample.
+        let rtt = measurer.round_trip_time().unwrap();
+        assert_close(rtt.smoothed(), Duration::from_millis(120));
+        // The variance moves 25% from 50ms towards the deviation of 200ms.
+        assert_close(rtt.variance(), Duration::from_micros(87_500));
+        assert_eq!(rtt.min(), Duration::from_millis(100));
+        assert_eq!(rtt.latest(), Duration::from_millis(300));
+    }
 
-        // 300ms has exceeded 50ms over the max allowed rtt. So we check if or smoothing factor is now 10% from 50.
-        assert_eq!(smoothed_rtt, 5.0);
+    #[test]
+    fn keeps_lowest_rtt() {
+        let mut measurer = RttMeasurer::new(&SocketConfig::default());
+        measurer.update(Duration::from_millis(100));
+        measurer.update(Duration::from_millis(40));
+        measurer.update(Duration::from_millis(80));
+
+        assert_eq!(
+            measurer.round_trip_time().unwrap().min(),
+            Duration::from_millis(40)
+        );
     }
 }
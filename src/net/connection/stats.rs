@@ -0,0 +1,223 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The time over which the send and receive bandwidth are averaged.
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+/// A snapshot of the traffic statistics of a connection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ConnectionStats {
+    packets_sent: u64,
+    packets_received: u64,
+    packets_acked: u64,
+    packets_lost: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    packet_loss: f32,
+    send_bandwidth: f32,
+    receive_bandwidth: f32,
+}
+
+impl ConnectionStats {
+    /// Get the number of datagrams that were sent to the connection.
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+
+    /// Get the number of datagrams that were received from the connection.
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+
+    /// Get the number of packets the connection acknowledged.
+    pub fn packets_acked(&self) -> u64 {
+        self.packets_acked
+    }
+
+    /// Get the number of packets that were not acknowledged by the connection, either because a
+    /// newer packet was acknowledged or because it took too long.
+    pub fn packets_lost(&self) -> u64 {
+        self.packets_lost
+    }
+
+    /// Get the number of bytes that were sent to the connection.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Get the number of bytes that were received from the connection.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Get the smoothed percentage (0 to 100) of the packets that got lost.
+    pub fn packet_loss(&self) -> f32 {
+        self.packet_loss
+    }
+
+    /// Get the number of bytes per second that were sent to the connection over the last second.
+    pub fn send_bandwidth(&self) -> f32 {
+        self.send_bandwidth
+    }
+
+    /// Get the number of bytes per second that were received from the connection over the last
+    /// second.
+    pub fn receive_bandwidth(&self) -> f32 {
+        self.receive_bandwidth
+    }
+}
+
+/// Keeps track of the traffic of a single connection.
+pub struct StatsRecorder {
+    packets_sent: u64,
+    packets_received: u64,
+    packets_acked: u64,
+    packets_lost: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    sent: BandwidthWindow,
+    received: BandwidthWindow,
+}
+
+impl StatsRecorder {
+    /// Creates a recorder for a connection without any traffic yet.
+    pub fn new() -> Self {
+        Self {
+            packets_sent: 0,
+            packets_received: 0,
+            packets_acked: 0,
+            packets_lost: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            sent: BandwidthWindow::new(),
+            received: BandwidthWindow::new(),
+        }
+    }
+
+    /// Records a datagram of `bytes` bytes that was sent to the connection.
+    pub fn record_sent(&mut self, bytes: usize, now: Instant) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+        self.sent.record(bytes, now);
+    }
+
+    /// Records a datagram of `bytes` bytes that was received from the connection.
+    pub fn record_received(&mut self, bytes: usize, now: Instant) {
+        self.packets_received += 1;
+        self.bytes_received += bytes as u64;
+        self.received.record(bytes, now);
+    }
+
+    /// Records a packet that was acknowledged by the connection.
+    pub fn record_acked(&mut self) {
+        self.packets_acked += 1;
+    }
+
+    /// Records a packet that got lost on the way to the connection.
+    pub fn record_lost(&mut self) {
+        self.packets_lost += 1;
+    }
+
+    /// Creates a snapshot of the statistics, where `packet_loss` is the smoothed fraction of
+    /// packets that got lost.
+    pub fn stats(&self, packet_loss: f32, now: Instant) -> ConnectionStats {
+        ConnectionStats {
+            packets_sent: self.packets_sent,
+            packets_received: self.packets_received,
+            packets_acked: self.packets_acked,
+            packets_lost: self.packets_lost,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            packet_loss: packet_loss * 100.0,
+            send_bandwidth: self.sent.bandwidth(now),
+            receive_bandwidth: self.received.bandwidth(now),
+        }
+    }
+}
+
+/// Remembers how many bytes went over the wire within the `BANDWIDTH_WINDOW`.
+struct BandwidthWindow {
+    samples: VecDeque<(Instant, usize)>,
+}
+
+impl BandwidthWindow {
+    fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records `bytes` bytes going over the wire and forgets about the samples that left the
+    /// window.
+    fn record(&mut self, bytes: usize, now: Instant) {
+        while let Some(&(time, _)) = self.samples.front() {
+            if now.saturating_duration_since(time) < BANDWIDTH_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, bytes));
+    }
+
+    /// Get the average number of bytes per second within the window.
+    fn bandwidth(&self, now: Instant) -> f32 {
+        let bytes: usize = self
+            .samples
+            .iter()
+            .filter(|(time, _)| now.saturating_duration_since(*time) < BANDWIDTH_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        bytes as f32 / BANDWIDTH_WINDOW.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatsRecorder, BANDWIDTH_WINDOW};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn counts_packets_and_bytes() {
+        let now = Instant::now();
+        let mut recorder = StatsRecorder::new();
+
+        recorder.record_sent(100, now);
+        recorder.record_sent(50, now);
+        recorder.record_received(20, now);
+        recorder.record_acked();
+        recorder.record_lost();
+
+        let stats = recorder.stats(0.25, now);
+        assert_eq!(stats.packets_sent(), 2);
+        assert_eq!(stats.bytes_sent(), 150);
+        assert_eq!(stats.packets_received(), 1);
+        assert_eq!(stats.bytes_received(), 20);
+        assert_eq!(stats.packets_acked(), 1);
+        assert_eq!(stats.packets_lost(), 1);
+        assert_eq!(stats.packet_loss(), 25.0);
+    }
+
+    #[test]
+    fn measures_bandwidth_over_window() {
+        let now = Instant::now();
+        let mut recorder = StatsRecorder::new();
+
+        recorder.record_sent(1000, now);
+        recorder.record_sent(500, now + BANDWIDTH_WINDOW / 2);
+        recorder.record_received(200, now);
+
+        let stats = recorder.stats(0.0, now + BANDWIDTH_WINDOW / 2);
+        assert_eq!(stats.send_bandwidth(), 1500.0);
+        assert_eq!(stats.receive_bandwidth(), 200.0);
+
+        // The first packets left the window.
+        let stats = recorder.stats(0.0, now + BANDWIDTH_WINDOW + Duration::from_millis(1));
+        assert_eq!(stats.send_bandwidth(), 500.0);
+        assert_eq!(stats.receive_bandwidth(), 0.0);
+
+        // The totals are never forgotten.
+        assert_eq!(stats.bytes_sent(), 1500);
+    }
+}
@@ -0,0 +1,657 @@
+use crate::{
+    errors::PacketError,
+    packet::headers::{TransferAction, TransferHeader},
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io, mem,
+};
+
+/// The number of finished incoming transfers we remember, so chunks of them that were sent again
+/// are not mistaken for a new transfer.
+const CLOSED_TRANSFER_MEMORY: usize = 64;
+/// The number of incoming transfers a connection can have at the same time. Chunks of any further
+/// transfer are not accepted until one of them is over.
+const MAX_INCOMING_TRANSFERS: usize = 16;
+
+/// Identifies a large message transfer, see `SocketHandle::send_transfer`.
+///
+/// Transfers are identified by the sender, so the id of an incoming transfer is only unique
+/// together with the address it comes from, and may be equal to the id of an outgoing transfer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransferId {
+    id: u32,
+    is_outgoing: bool,
+}
+
+impl TransferId {
+    pub(crate) fn outgoing(id: u32) -> Self {
+        Self {
+            id,
+            is_outgoing: true,
+        }
+    }
+
+    pub(crate) fn incoming(id: u32) -> Self {
+        Self {
+            id,
+            is_outgoing: false,
+        }
+    }
+
+    /// Get the number the sender gave the transfer.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns whether we are the sender of the transfer.
+    pub fn is_outgoing(&self) -> bool {
+        self.is_outgoing
+    }
+}
+
+/// How far a large message transfer has come.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TransferProgress {
+    transferred_bytes: u64,
+    total_bytes: u64,
+}
+
+impl TransferProgress {
+    /// Get the number of bytes that were acknowledged by the receiver of an outgoing transfer,
+    /// or handed to the application for an incoming transfer.
+    pub fn transferred_bytes(&self) -> u64 {
+        self.transferred_bytes
+    }
+
+    /// Get the size of the whole message.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+}
+
+/// Something that happened to one of the transfers of a connection.
+#[derive(Debug, PartialEq)]
+pub enum TransferEvent {
+    /// The next part of an incoming message.
+    Data(TransferId, Vec<u8>),
+    /// The progress of a transfer changed.
+    Progress(TransferId, TransferProgress),
+    /// The whole message was acknowledged or handed to the application.
+    Completed(TransferId),
+    /// Either side gave up on the transfer.
+    Cancelled(TransferId),
+}
+
+/// A message we are sending.
+struct OutgoingTransfer {
+    id: u32,
+    payload: Vec<u8>,
+    /// The offset of the next chunk we will send.
+    next_offset: u64,
+    /// Whether every chunk was sent at least once, which is not the same as `next_offset`
+    /// reaching the end for empty messages.
+    is_fully_sent: bool,
+    acked_bytes: u64,
+    chunks_in_flight: u16,
+    progress_changed: bool,
+}
+
+impl OutgoingTransfer {
+    fn total_size(&self) -> u64 {
+        self.payload.len() as u64
+    }
+
+    fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            transferred_bytes: self.acked_bytes,
+            total_bytes: self.total_size(),
+        }
+    }
+}
+
+/// A message we are receiving.
+struct IncomingTransfer {
+    total_size: u64,
+    /// The number of bytes we handed to the application.
+    delivered: u64,
+    /// The chunks that arrived before the chunks in front of them, by offset.
+    pending: BTreeMap<u64, Vec<u8>>,
+    progress_changed: bool,
+}
+
+impl IncomingTransfer {
+    fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            transferred_bytes: self.delivered,
+            total_bytes: self.total_size,
+        }
+    }
+}
+
+/// Splits the large messages we send to a connection into chunks and puts the chunks we receive
+/// back together.
+///
+/// Chunks are sent reliably, but only `transfer_window_size` chunks of a transfer may be waiting
+/// for an acknowledgement at the same time. That keeps a transfer from flooding the connection and
+/// limits the number of chunks the receiver has to hold on to while it waits for a chunk that was
+/// dropped. Every other chunk is handed to the application as soon as the chunks in front of it
+/// have arrived, so the receiver never needs to keep the whole message around.
+///
+/// Multiple transfers to the same connection take turns sending a chunk.
+pub struct Transfers {
+    window_size: u16,
+    outgoing: VecDeque<OutgoingTransfer>,
+    incoming: HashMap<u32, IncomingTransfer>,
+    closed_incoming: VecDeque<u32>,
+    events: Vec<TransferEvent>,
+}
+
+impl Transfers {
+    /// Creates the transfers of a connection that doesn't transfer anything yet.
+    pub fn new(window_size: u16) -> Self {
+        Self {
+            window_size,
+            outgoing: VecDeque::new(),
+            incoming: HashMap::new(),
+            closed_incoming: VecDeque::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Starts sending the given message.
+    pub fn start(&mut self, id: u32, payload: Vec<u8>) {
+        self.outgoing.push_back(OutgoingTransfer {
+            id,
+            payload,
+            next_offset: 0,
+            is_fully_sent: false,
+            acked_bytes: 0,
+            chunks_in_flight: 0,
+            progress_changed: false,
+        });
+    }
+
+    /// Checks if any of the outgoing transfers is allowed to send a chunk.
+    pub fn has_sendable_chunks(&self) -> bool {
+        self.outgoing.iter().any(|transfer| self.can_send(transfer))
+    }
+
+    /// Takes the next chunk of at most `chunk_size` bytes from the outgoing transfers, if any of
+    /// them is allowed to send one.
+    pub fn next_chunk(&mut self, chunk_size: usize) -> Option<(TransferHeader, Vec<u8>)> {
+        let index = self
+            .outgoing
+            .iter()
+            .position(|transfer| self.can_send(transfer))?;
+
+        // Move the transfer to the back, so the others get their turn first.
+        let mut transfer = self.outgoing.remove(index)?;
+        let start = transfer.next_offset as usize;
+        let end = (start + chunk_size).min(transfer.payload.len());
+        let header = TransferHeader::chunk(transfer.id, start as u64, transfer.total_size());
+        let chunk = transfer.payload[start..end].to_vec();
+
+        transfer.next_offset = end as u64;
+        transfer.is_fully_sent = end == transfer.payload.len();
+        transfer.chunks_in_flight += 1;
+        self.outgoing.push_back(transfer);
+
+        Some((header, chunk))
+    }
+
+    /// Takes a chunk that was acknowledged by the receiver into account.
+    pub fn on_chunk_acked(&mut self, header: TransferHeader, chunk_size: usize) {
+        let index = match self
+            .outgoing
+            .iter()
+            .position(|transfer| transfer.id == header.transfer_id())
+        {
+            Some(index) => index,
+            // The transfer was cancelled while the chunk was on its way.
+            None => return,
+        };
+
+        let transfer = &mut self.outgoing[index];
+        transfer.chunks_in_flight = match transfer.chunks_in_flight.checked_sub(1) {
+            Some(chunks_in_flight) => chunks_in_flight,
+            // We are not waiting for a chunk of this transfer.
+            None => return,
+        };
+        transfer.acked_bytes += chunk_size as u64;
+        transfer.progress_changed = true;
+
+        if transfer.is_fully_sent && transfer.acked_bytes == transfer.total_size() {
+            let id = TransferId::outgoing(transfer.id);
+            self.events
+                .push(TransferEvent::Progress(id, transfer.progress()));
+            self.events.push(TransferEvent::Completed(id));
+            self.outgoing.remove(index);
+        }
+    }
+
+    /// Takes into account that a chunk in flight is sent again as `chunk_count` smaller chunks,
+    /// each of which is acknowledged on its own.
+    pub fn on_chunk_split(&mut self, header: TransferHeader, chunk_count: usize) {
+        if let Some(transfer) = self
+            .outgoing
+            .iter_mut()
+            .find(|transfer| transfer.id == header.transfer_id())
+        {
+            let extra_chunks = chunk_count.saturating_sub(1).min(u16::MAX as usize) as u16;
+            transfer.chunks_in_flight = transfer.chunks_in_flight.saturating_add(extra_chunks);
+        }
+    }
+
+    /// Handles a transfer packet we received.
+    ///
+    /// Returns `Ok(false)` if a chunk could not be accepted because we are holding on to too much
+    /// data already. Those chunks should not be acknowledged, so they are sent again later.
+    pub fn handle_packet(&mut self, header: TransferHeader, data: &[u8]) -> io::Result<bool> {
+        match header.action() {
+            TransferAction::Chunk => return self.handle_chunk(header, data),
+            TransferAction::SenderCancel => {
+                if self.incoming.remove(&header.transfer_id()).is_some() {
+                    self.events
+                        .push(TransferEvent::Cancelled(TransferId::incoming(
+                            header.transfer_id(),
+                        )));
+                }
+                // Remember the transfer even if we never saw a chunk of it, in case one still
+                // arrives.
+                self.close_incoming(header.transfer_id());
+            }
+            TransferAction::ReceiverCancel => {
+                self.remove_outgoing(header.transfer_id());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Cancels one of our transfers. Returns the header of the packet that lets the other side
+    /// know, or `None` if there is no such transfer.
+    pub fn cancel(&mut self, id: TransferId) -> Option<TransferHeader> {
+        if id.is_outgoing() {
+            self.remove_outgoing(id.id())
+                .then(|| TransferHeader::cancel(id.id(), TransferAction::SenderCancel))
+        } else {
+            self.incoming.remove(&id.id())?;
+            self.close_incoming(id.id());
+            self.events.push(TransferEvent::Cancelled(id));
+            Some(TransferHeader::cancel(
+                id.id(),
+                TransferAction::ReceiverCancel,
+            ))
+        }
+    }
+
+    /// Get the ids of the transfers that are still going on.
+    pub fn ids(&self) -> Vec<TransferId> {
+        let outgoing = self
+            .outgoing
+            .iter()
+            .map(|transfer| TransferId::outgoing(transfer.id));
+        let incoming = self.incoming.keys().map(|id| TransferId::incoming(*id));
+        outgoing.chain(incoming).collect()
+    }
+
+    /// Returns what happened to the transfers since the last time this was called. The progress
+    /// of a transfer is reported at most once per call.
+    pub fn take_events(&mut self) -> Vec<TransferEvent> {
+        for transfer in self.outgoing.iter_mut() {
+            if mem::take(&mut transfer.progress_changed) {
+                self.events.push(TransferEvent::Progress(
+                    TransferId::outgoing(transfer.id),
+                    transfer.progress(),
+                ));
+            }
+        }
+        for (id, transfer) in self.incoming.iter_mut() {
+            if mem::take(&mut transfer.progress_changed) {
+                self.events.push(TransferEvent::Progress(
+                    TransferId::incoming(*id),
+                    transfer.progress(),
+                ));
+            }
+        }
+        mem::take(&mut self.events)
+    }
+
+    /// Checks if the transfer has a chunk left to send and room for it in its window.
+    fn can_send(&self, transfer: &OutgoingTransfer) -> bool {
+        !transfer.is_fully_sent && transfer.chunks_in_flight < self.window_size
+    }
+
+    /// Stores the chunk of an incoming transfer and hands every chunk that is no longer waiting
+    /// for the chunks in front of it to the application.
+    ///
+    /// At most `window_size` chunks of a transfer are held back, and only `MAX_INCOMING_TRANSFERS`
+    /// transfers are received at the same time, so a peer can't make us hold on to any amount of
+    /// data. Chunks beyond that are not accepted.
+    fn handle_chunk(&mut self, header: TransferHeader, data: &[u8]) -> io::Result<bool> {
+        let id = header.transfer_id();
+        if self.closed_incoming.contains(&id) {
+            return Ok(true);
+        }
+        match header.offset().checked_add(data.len() as u64) {
+            Some(end) if end <= header.total_size() => {}
+            _ => return Err(PacketError::InvalidTransferChunk.into()),
+        }
+        if !self.incoming.contains_key(&id) && self.incoming.len() >= MAX_INCOMING_TRANSFERS {
+            return Ok(false);
+        }
+
+        let transfer = self.incoming.entry(id).or_insert_with(|| IncomingTransfer {
+            total_size: header.total_size(),
+            delivered: 0,
+            pending: BTreeMap::new(),
+            progress_changed: false,
+        });
+        if header.total_size() != transfer.total_size {
+            return Err(PacketError::InvalidTransferChunk.into());
+        }
+
+        // Chunks that were sent again might arrive twice.
+        if header.offset() < transfer.delivered || transfer.pending.contains_key(&header.offset()) {
+            return Ok(true);
+        }
+        // The next chunk we are waiting for is always accepted, since it releases the others.
+        if header.offset() != transfer.delivered
+            && transfer.pending.len() >= self.window_size as usize
+        {
+            return Ok(false);
+        }
+        transfer.pending.insert(header.offset(), data.to_vec());
+
+        let mut released = Vec::new();
+        while let Some(chunk) = transfer.pending.remove(&transfer.delivered) {
+            transfer.delivered += chunk.len() as u64;
+            released.extend(chunk);
+            transfer.progress_changed = true;
+        }
+
+        let id = TransferId::incoming(id);
+        if !released.is_empty() {
+            self.events.push(TransferEvent::Data(id, released));
+        }
+        if transfer.delivered == transfer.total_size {
+            self.events
+                .push(TransferEvent::Progress(id, transfer.progress()));
+            self.events.push(TransferEvent::Completed(id));
+            self.incoming.remove(&id.id());
+            self.close_incoming(id.id());
+        }
+        Ok(true)
+    }
+
+    /// Removes one of our transfers. Returns `false` if there is no such transfer.
+    fn remove_outgoing(&mut self, id: u32) -> bool {
+        match self.outgoing.iter().position(|transfer| transfer.id == id) {
+            Some(index) => {
+                self.outgoing.remove(index);
+                self.events
+                    .push(TransferEvent::Cancelled(TransferId::outgoing(id)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers that an incoming transfer is over.
+    fn close_incoming(&mut self, id: u32) {
+        if self.closed_incoming.len() == CLOSED_TRANSFER_MEMORY {
+            self.closed_incoming.pop_front();
+        }
+        self.closed_incoming.push_back(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransferEvent, TransferId, TransferProgress, Transfers, MAX_INCOMING_TRANSFERS};
+    use crate::packet::headers::{TransferAction, TransferHeader};
+
+    const CHUNK_SIZE: usize = 4;
+
+    /// Takes every chunk the sender is allowed to send right now.
+    fn take_chunks(sender: &mut Transfers) -> Vec<(TransferHeader, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = sender.next_chunk(CHUNK_SIZE) {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    fn progress(transferred_bytes: u64, total_bytes: u64) -> TransferProgress {
+        TransferProgress {
+            transferred_bytes,
+            total_bytes,
+        }
+    }
+
+    #[test]
+    fn limits_chunks_in_flight_to_window() {
+        let mut sender = Transfers::new(2);
+        sender.start(1, (0..10).collect());
+
+        let chunks = take_chunks(&mut sender);
+        assert_eq!(chunks.len(), 2);
+        assert!(!sender.has_sendable_chunks());
+
+        sender.on_chunk_acked(chunks[0].0, chunks[0].1.len());
+        let chunks = take_chunks(&mut sender);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0.offset(), 8);
+        assert_eq!(chunks[0].1, vec![8, 9]);
+    }
+
+    #[test]
+    fn reports_progress_and_completion_to_sender() {
+        let mut sender = Transfers::new(8);
+        let id = TransferId::outgoing(1);
+        sender.start(1, vec![0; 6]);
+
+        let chunks = take_chunks(&mut sender);
+        sender.on_chunk_acked(chunks[0].0, chunks[0].1.len());
+        assert_eq!(
+            sender.take_events(),
+            vec![TransferEvent::Progress(id, progress(4, 6))]
+        );
+        assert_eq!(sender.take_events(), vec![]);
+
+        sender.on_chunk_acked(chunks[1].0, chunks[1].1.len());
+        assert_eq!(
+            sender.take_events(),
+            vec![
+                TransferEvent::Progress(id, progress(6, 6)),
+                TransferEvent::Completed(id)
+            ]
+        );
+        assert!(sender.ids().is_empty());
+    }
+
+    #[test]
+    fn delivers_chunks_in_order() {
+        let mut sender = Transfers::new(8);
+        let mut receiver = Transfers::new(8);
+        let id = TransferId::incoming(1);
+        sender.start(1, (0..10).collect());
+
+        let chunks = take_chunks(&mut sender);
+        receiver.handle_packet(chunks[1].0, &chunks[1].1).unwrap();
+        assert_eq!(receiver.take_events(), vec![]);
+
+        receiver.handle_packet(chunks[0].0, &chunks[0].1).unwrap();
+        receiver.handle_packet(chunks[0].0, &chunks[0].1).unwrap();
+        assert_eq!(
+            receiver.take_events(),
+            vec![
+                TransferEvent::Data(id, (0..8).collect()),
+                TransferEvent::Progress(id, progress(8, 10))
+            ]
+        );
+
+        receiver.handle_packet(chunks[2].0, &chunks[2].1).unwrap();
+        assert_eq!(
+            receiver.take_events(),
+            vec![
+                TransferEvent::Data(id, vec![8, 9]),
+                TransferEvent::Progress(id, progress(10, 10)),
+                TransferEvent::Completed(id)
+            ]
+        );
+
+        // A chunk that was sent again after the transfer completed doesn't start a new one.
+        receiver.handle_packet(chunks[2].0, &chunks[2].1).unwrap();
+        assert_eq!(receiver.take_events(), vec![]);
+    }
+
+    #[test]
+    fn transfers_empty_message() {
+        let mut sender = Transfers::new(8);
+        let mut receiver = Transfers::new(8);
+        sender.start(1, Vec::new());
+
+        let chunks = take_chunks(&mut sender);
+        assert_eq!(chunks.len(), 1);
+        receiver.handle_packet(chunks[0].0, &chunks[0].1).unwrap();
+
+        let id = TransferId::incoming(1);
+        assert_eq!(
+            receiver.take_events(),
+            vec![
+                TransferEvent::Progress(id, progress(0, 0)),
+                TransferEvent::Completed(id)
+            ]
+        );
+    }
+
+    #[test]
+    fn takes_turns_between_transfers() {
+        let mut sender = Transfers::new(8);
+        sender.start(1, vec![0; 8]);
+        sender.start(2, vec![0; 8]);
+
+        let ids: Vec<u32> = take_chunks(&mut sender)
+            .iter()
+            .map(|(header, _)| header.transfer_id())
+            .collect();
+        assert_eq!(ids, vec![1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn cancels_transfers_on_both_sides() {
+        // The window keeps both transfers from completing.
+        let mut sender = Transfers::new(2);
+        let mut receiver = Transfers::new(8);
+        sender.start(1, vec![0; 10]);
+        sender.start(2, vec![0; 10]);
+        for (header, chunk) in take_chunks(&mut sender) {
+            receiver.handle_packet(header, &chunk).unwrap();
+        }
+        receiver.take_events();
+
+        // The sender cancels the first transfer.
+        let cancel = sender.cancel(TransferId::outgoing(1)).unwrap();
+        assert_eq!(cancel.action(), TransferAction::SenderCancel);
+        receiver.handle_packet(cancel, &[]).unwrap();
+        assert_eq!(
+            receiver.take_events(),
+            vec![TransferEvent::Cancelled(TransferId::incoming(1))]
+        );
+
+        // The receiver cancels the second one.
+        let cancel = receiver.cancel(TransferId::incoming(2)).unwrap();
+        assert_eq!(cancel.action(), TransferAction::ReceiverCancel);
+        sender.handle_packet(cancel, &[]).unwrap();
+        assert_eq!(
+            sender.take_events(),
+            vec![
+                TransferEvent::Cancelled(TransferId::outgoing(1)),
+                TransferEvent::Cancelled(TransferId::outgoing(2))
+            ]
+        );
+
+        assert!(sender.ids().is_empty());
+        assert!(receiver.ids().is_empty());
+        assert_eq!(sender.cancel(TransferId::outgoing(1)), None);
+    }
+
+    #[test]
+    fn rejects_chunk_outside_of_message() {
+        let mut receiver = Transfers::new(8);
+
+        let header = TransferHeader::chunk(1, 8, 10);
+        assert!(receiver.handle_packet(header, &[0; 4]).is_err());
+        assert!(receiver.ids().is_empty());
+    }
+
+    #[test]
+    fn rejects_chunk_with_overflowing_offset() {
+        let mut receiver = Transfers::new(8);
+
+        let header = TransferHeader::chunk(1, u64::MAX, 20);
+        assert!(receiver.handle_packet(header, &[0; 4]).is_err());
+        assert!(receiver.ids().is_empty());
+    }
+
+    #[test]
+    fn ignores_acks_of_chunks_not_in_flight() {
+        let mut sender = Transfers::new(2);
+        sender.start(1, (0..10).collect());
+
+        let chunks = take_chunks(&mut sender);
+        sender.on_chunk_acked(chunks[0].0, chunks[0].1.len());
+        sender.on_chunk_acked(chunks[1].0, chunks[1].1.len());
+        sender.on_chunk_acked(chunks[1].0, chunks[1].1.len());
+
+        // The window is not thrown off by the extra ack.
+        assert_eq!(take_chunks(&mut sender).len(), 1);
+        assert!(!sender.has_sendable_chunks());
+    }
+
+    #[test]
+    fn limits_data_held_back_for_sender() {
+        let mut receiver = Transfers::new(2);
+
+        // Chunks far ahead of the missing first one are only held back up to the window size.
+        assert!(receiver
+            .handle_packet(TransferHeader::chunk(1, 4, 20), &[0; 4])
+            .unwrap());
+        assert!(receiver
+            .handle_packet(TransferHeader::chunk(1, 8, 20), &[0; 4])
+            .unwrap());
+        assert!(!receiver
+            .handle_packet(TransferHeader::chunk(1, 12, 20), &[0; 4])
+            .unwrap());
+        assert!(receiver
+            .handle_packet(TransferHeader::chunk(1, 0, 20), &[0; 4])
+            .unwrap());
+
+        // New transfers are not accepted once too many are going on.
+        for id in 2..=MAX_INCOMING_TRANSFERS as u32 {
+            assert!(receiver
+                .handle_packet(TransferHeader::chunk(id, 0, 20), &[0; 4])
+                .unwrap());
+        }
+        let id = MAX_INCOMING_TRANSFERS as u32 + 1;
+        assert!(!receiver
+            .handle_packet(TransferHeader::chunk(id, 0, 20), &[0; 4])
+            .unwrap());
+    }
+
+    #[test]
+    fn waits_for_every_part_of_a_split_chunk() {
+        let mut sender = Transfers::new(2);
+        sender.start(1, (0..20).collect());
+
+        let chunks = take_chunks(&mut sender);
+        sender.on_chunk_split(chunks[0].0, 2);
+        sender.on_chunk_acked(TransferHeader::chunk(1, 0, 20), 2);
+        sender.on_chunk_acked(TransferHeader::chunk(1, 2, 20), 2);
+
+        // The second chunk is still in flight.
+        assert_eq!(take_chunks(&mut sender).len(), 1);
+        assert!(!sender.has_sendable_chunks());
+    }
+}
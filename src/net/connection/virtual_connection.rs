@@ -1,74 +1,154 @@
-use super::RttMeasurer;
+use super::{
+    CongestionControl, ConnectionStats, MtuDiscovery, NetworkQuality, RoundTripTime, RttMeasurer,
+    StatsRecorder, TransferEvent, TransferId, Transfers,
+};
 use crate::{
     config::SocketConfig,
     errors::{LaminarError, PacketError},
-    net::{DeliveryMethod, ExternalAcks, LocalAckRecord},
+    net::{
+        arranging::ArrangingSystem, local_ack::SentPacket, reassembly::FragmentReassembler,
+        DeliveryMethod, ExternalAcks, LocalAckRecord, MessageTicket, SocketEvent,
+    },
     packet::{
-        headers::{HeaderReader, ReliableHeader, StandardHeader},
-        PacketType, ProcessedPacket,
+        headers::{
+            max_header_size, transfer_header_size, ArrangingHeader, ChallengeHeader,
+            FragmentHeader, HeaderReader, HeaderWriter, ReliableHeader, StandardHeader,
+            TransferAction, TransferHeader,
+        },
+        BufferPool, PacketType, ProcessedPacket,
     },
     protocol_version,
     sequence_buffer::{CongestionData, SequenceBuffer},
     Packet,
 };
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::{
-    fmt, io,
-    io::Read,
+    collections::VecDeque,
+    fmt, io, mem,
     net::SocketAddr,
     time::{Duration, Instant},
 };
 
+/// The lower bound of the retransmission timeout, so jitter on fast networks doesn't make us send
+/// everything twice.
+const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(50);
+
 /// Contains the information about 'virtual connections' over UDP.
 pub struct VirtualConnection {
     /// Last time we received a packet from this client
     last_packet_time: Instant,
+    /// Last time we sent a packet to this client
+    last_sent_time: Instant,
     /// The address of the remote endpoint
     remote_address: SocketAddr,
     /// Maximum size a packet can be.
     max_packet_size_bytes: usize,
+    /// Maximum number of fragments a packet can be split into.
+    max_fragments: u8,
 
     // TODO: These likely won't stay here
     // reliability control
     sequence_num: u16,
     local_acks: LocalAckRecord,
     external_acks: ExternalAcks,
-    dropped_packets: Vec<Box<[u8]>>,
+    acks_pending_since: Option<Instant>,
+    dropped_packets: Vec<SentPacket>,
+    acked_tickets: Vec<MessageTicket>,
+    lost_tickets: Vec<MessageTicket>,
+
+    // fragmentation
+    reassembler: FragmentReassembler,
+
+    // ordering and sequencing
+    arranging_system: ArrangingSystem,
 
     // congestion control
+    congestion_control: CongestionControl,
+    reported_quality: NetworkQuality,
+    paced_packets: VecDeque<Packet>,
+    max_paced_packets: usize,
     rtt_measurer: RttMeasurer,
     congestion_data: SequenceBuffer<CongestionData>,
-    rtt: f32,
+    max_rtt: Duration,
+    max_retransmissions: u8,
+
+    // path MTU discovery
+    mtu_discovery: MtuDiscovery,
+    mtu_probe_replies: Vec<u16>,
+
+    // the challenge of the handshake that created this connection, if there was one
+    handshake_challenge: Option<ChallengeHeader>,
+
+    // large message transfers
+    transfers: Transfers,
+
+    // the buffers the payloads of received packets are copied into
+    buffer_pool: BufferPool,
+
+    // statistics
+    stats: StatsRecorder,
 }
 
 impl VirtualConnection {
-    pub fn new(remote_address: SocketAddr, config: &SocketConfig) -> Self {
+    /// Creates the state of a new connection to the remote endpoint with the given address.
+    ///
+    /// The payloads of received packets are copied into buffers of the given pool, which can be
+    /// shared by every connection of a socket.
+    pub fn new(remote_address: SocketAddr, config: &SocketConfig, buffer_pool: BufferPool) -> Self {
         Self {
             last_packet_time: Instant::now(),
+            last_sent_time: Instant::now(),
             remote_address,
             max_packet_size_bytes: config.max_packet_size_bytes(),
+            max_fragments: config.max_fragments(),
 
             // reliability control
             sequence_num: 0,
             local_acks: LocalAckRecord::default(),
-            external_acks: ExternalAcks::default(),
+            external_acks: ExternalAcks::new(config.ack_window_size()),
+            acks_pending_since: None,
             dropped_packets: Vec::new(),
+            acked_tickets: Vec::new(),
+            lost_tickets: Vec::new(),
+
+            // fragmentation
+            reassembler: FragmentReassembler::new(config),
+
+            // ordering and sequencing
+            arranging_system: ArrangingSystem::new(config),
 
             // congestion control
-            rtt_measurer: RttMeasurer::new(&config),
-            congestion_data: SequenceBuffer::with_capacity(<u16>::max_value() as usize),
-            rtt: 0.0,
+            congestion_control: CongestionControl::new(config, Instant::now()),
+            reported_quality: NetworkQuality::Good,
+            paced_packets: VecDeque::new(),
+            max_paced_packets: config.max_paced_packets(),
+            rtt_measurer: RttMeasurer::new(config),
+            congestion_data: SequenceBuffer::with_capacity(u16::MAX as usize),
+            max_rtt: Duration::from_millis(u64::from(config.rtt_max_value())),
+            max_retransmissions: config.max_retransmissions(),
+            mtu_discovery: MtuDiscovery::new(config),
+            mtu_probe_replies: Vec::new(),
+            handshake_challenge: None,
+            transfers: Transfers::new(config.transfer_window_size()),
+            buffer_pool,
+            stats: StatsRecorder::new(),
         }
     }
 
-    /// This processes incoming payload data and returns a packet if the data is complete.
+    /// This processes incoming payload data and returns the packets that are ready to be handed
+    /// to the application.
     ///
-    /// Returns `Ok(None)`:
+    /// Returns an empty collection:
     /// 1. In the case of fragmentation and not all fragments are received
     /// 2. In the case of the packet being queued for ordering and we are waiting on older packets
     ///    first.
-    pub fn process_incoming(&mut self, payload: &[u8]) -> io::Result<Option<Packet>> {
-        // TODO: Only implementing the reliable packets currently
-        self.last_packet_time = Instant::now();
+    ///
+    /// Multiple packets are returned when a packet fills the gap in front of packets that were
+    /// held back for ordering.
+    pub fn process_incoming(&mut self, payload: &[u8]) -> io::Result<Vec<Packet>> {
+        let now = Instant::now();
+        self.last_packet_time = now;
+        self.stats.record_received(payload.len(), now);
 
         let mut cursor = io::Cursor::new(payload);
         let standard_header = StandardHeader::read(&mut cursor)?;
@@ -77,84 +157,592 @@ impl VirtualConnection {
             return Err(LaminarError::ProtocolVersionMismatch.into());
         }
 
-        if standard_header.packet_type() == PacketType::Fragment {
+        let fragment_header = if standard_header.packet_type() == PacketType::Fragment {
+            Some(FragmentHeader::read(&mut cursor)?)
+        } else {
+            None
+        };
 
+        // Any packet can carry our acknowledgements.
+        if standard_header.has_acks() {
+            let reliable_header = ReliableHeader::read(&mut cursor)?;
+            self.process_acks(reliable_header);
         }
 
-        match standard_header.delivery_method() {
-            DeliveryMethod::ReliableUnordered => {
-                let reliable_header = ReliableHeader::read(&mut cursor)?;
+        match standard_header.packet_type() {
+            PacketType::MtuProbe => {
+                // Probes that didn't arrive in one piece don't tell the sender anything.
+                let size = cursor.read_u16::<BigEndian>()?;
+                if payload.len() == size as usize {
+                    self.mtu_probe_replies.push(size);
+                }
+                return Ok(Vec::new());
+            }
+            PacketType::MtuProbeAck => {
+                let size = cursor.read_u16::<BigEndian>()?;
+                self.mtu_discovery.on_probe_acked(size, now);
+                return Ok(Vec::new());
+            }
+            PacketType::Transfer => {
+                let transfer_header = TransferHeader::read(&mut cursor)?;
+                let is_accepted = self
+                    .transfers
+                    .handle_packet(transfer_header, &payload[cursor.position() as usize..])?;
+                if !is_accepted {
+                    return Ok(Vec::new());
+                }
+
+                // Transfer packets that were sent again are acknowledged as well, `Transfers`
+                // drops the duplicates.
                 self.external_acks.ack(standard_header.sequence_num());
+                self.acks_pending_since.get_or_insert(now);
+                return Ok(Vec::new());
+            }
+            _ => {}
+        }
+
+        // Heartbeats only keep the connection alive and ack packets only carry acknowledgement
+        // information.
+        if matches!(
+            standard_header.packet_type(),
+            PacketType::HeartBeat | PacketType::Ack
+        ) {
+            return Ok(Vec::new());
+        }
 
-                // Update congestion information.
-                let congestion_data = self.congestion_data.get_mut(reliable_header.last_acked());
-                self.rtt = self.rtt_measurer.get_rtt(congestion_data);
+        let delivery_method = standard_header.delivery_method();
 
-                // Update dropped packets if there are any.
-                let dropped_packets = self
-                    .local_acks
-                    .ack(reliable_header.last_acked(), reliable_header.ack_field());
+        let arranging_header = if delivery_method.is_arranged() {
+            let arranging_header = ArrangingHeader::read(&mut cursor)?;
 
-                self.dropped_packets = dropped_packets.into_iter().map(|(_, p)| p).collect();
+            // Drop packets that won't be released anyway. Ordered packets we can't hold on to are
+            // not acknowledged, so they will be sent again.
+            if !self
+                .arranging_system
+                .accepts(delivery_method, arranging_header)
+            {
+                return Ok(Vec::new());
             }
-            _ => {}
-        }
 
-        // Read the rest of the bytes from the cursor to get the payload.
-        let mut payload = Vec::with_capacity(payload.len());
-        cursor.read_to_end(&mut payload)?;
+            Some(arranging_header)
+        } else {
+            None
+        };
 
-        Ok(Some(Packet::new(
+        let payload = match fragment_header {
+            Some(fragment_header) => {
+                let fragment_payload = &payload[cursor.position() as usize..];
+                match self.reassembler.handle_fragment(
+                    standard_header.sequence_num(),
+                    fragment_header,
+                    fragment_payload,
+                    now,
+                ) {
+                    Ok(Some(payload)) => self.buffer_pool.adopt(payload),
+                    Ok(None) => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            None => {
+                // The rest of the bytes after the headers are the payload.
+                let mut buffer = self.buffer_pool.take();
+                buffer.extend_from_slice(&payload[cursor.position() as usize..]);
+                buffer
+            }
+        };
+
+        // Only acknowledge a packet once all of its fragments have arrived, so that the other
+        // side will resend it if any of them got lost.
+        if delivery_method.is_acknowledged() {
+            self.external_acks.ack(standard_header.sequence_num());
+            self.acks_pending_since.get_or_insert(now);
+        }
+
+        let packet = Packet::new(
             self.remote_address,
-            payload.into_boxed_slice(),
-            standard_header.delivery_method(),
-        )))
+            payload,
+            delivery_method,
+            arranging_header.map(|header| header.stream_id()),
+        );
+
+        match arranging_header {
+            Some(arranging_header) => {
+                Ok(self
+                    .arranging_system
+                    .arrange(delivery_method, arranging_header, packet))
+            }
+            None => Ok(vec![packet]),
+        }
+    }
+
+    /// Holds on to a packet the application wants to send until the congestion control allows it
+    /// to go out, see `process_paced_packets`.
+    ///
+    /// The queue is bounded by `max_paced_packets`. A sequenced packet replaces the packets of its
+    /// stream that are still waiting, since those would be dropped by the receiver anyway. When
+    /// the queue is full, the oldest unreliable packet is dropped to make room. If there is none,
+    /// an unreliable packet is dropped itself and a reliable packet is refused with
+    /// `PacketError::SendQueueFull`. Dropped packets count as lost, refused packets are reported
+    /// as lost by the caller.
+    pub fn queue_outgoing(&mut self, packet: Packet) -> io::Result<()> {
+        if packet.payload().len() > self.max_packet_size_bytes() {
+            return Err(PacketError::ExceededMaxPacketSize.into());
+        }
+
+        if packet.delivery_method() == DeliveryMethod::Sequenced {
+            let (superseded, paced_packets) = mem::take(&mut self.paced_packets)
+                .into_iter()
+                .partition(|queued: &Packet| {
+                    queued.delivery_method() == DeliveryMethod::Sequenced
+                        && queued.stream_id() == packet.stream_id()
+                });
+            self.paced_packets = paced_packets;
+            superseded
+                .into_iter()
+                .for_each(|queued| self.drop_paced_packet(queued));
+        }
+
+        if self.paced_packets.len() >= self.max_paced_packets {
+            let unreliable = self
+                .paced_packets
+                .iter()
+                .position(|queued| !queued.delivery_method().is_reliable());
+            match unreliable.and_then(|index| self.paced_packets.remove(index)) {
+                Some(queued) => self.drop_paced_packet(queued),
+                None if packet.delivery_method().is_reliable() => {
+                    return Err(PacketError::SendQueueFull.into());
+                }
+                None => {
+                    self.drop_paced_packet(packet);
+                    return Ok(());
+                }
+            }
+        }
+
+        self.paced_packets.push_back(packet);
+        Ok(())
+    }
+
+    /// Drops a packet that never left the queue, which is lost as far as its ticket is concerned.
+    fn drop_paced_packet(&mut self, packet: Packet) {
+        if let Some(ticket) = packet.ticket() {
+            self.lost_tickets.push(ticket);
+        }
+    }
+
+    /// This pre-processes as many of the queued packets as the current send rate allows to be sent
+    /// over the network, in the order they were queued in.
+    pub fn process_paced_packets(&mut self, now: Instant) -> Vec<ProcessedPacket> {
+        let mut processed = Vec::new();
+        while !self.paced_packets.is_empty() && self.congestion_control.try_send(now) {
+            let packet = self.paced_packets.pop_front().unwrap();
+            processed.push(self.process_checked_outgoing(packet));
+        }
+        processed
+    }
+
+    /// This pre-processes all of the queued packets to be sent over the network, regardless of
+    /// the current send rate.
+    pub fn flush_paced_packets(&mut self) -> Vec<ProcessedPacket> {
+        mem::take(&mut self.paced_packets)
+            .into_iter()
+            .map(|packet| self.process_checked_outgoing(packet))
+            .collect()
+    }
+
+    /// Starts sending a large message to this connection, see `Transfers`. Its chunks are sent by
+    /// `process_transfer_chunks`.
+    pub fn start_transfer(&mut self, id: u32, payload: Vec<u8>) {
+        self.transfers.start(id, payload);
+    }
+
+    /// This pre-processes as many chunks of the transfers to this connection as their windows and
+    /// the current send rate allow. Queued packets go first, so a transfer never holds back the
+    /// packets the application sends in the meantime.
+    pub fn process_transfer_chunks(&mut self, now: Instant) -> Vec<ProcessedPacket> {
+        let chunk_size = self.transfer_chunk_size();
+        let mut processed = Vec::new();
+        while self.paced_packets.is_empty()
+            && self.transfers.has_sendable_chunks()
+            && self.congestion_control.try_send(now)
+        {
+            let (header, chunk) = self
+                .transfers
+                .next_chunk(chunk_size)
+                .expect("We just checked that there is a chunk to send.");
+            processed.push(self.process_transfer_packet(header, chunk, now));
+        }
+        processed
+    }
+
+    /// Checks if any of the transfers to this connection is allowed to send a chunk.
+    pub fn has_sendable_transfer_chunks(&self) -> bool {
+        self.transfers.has_sendable_chunks()
+    }
+
+    /// Cancels the transfer with the given id, in either direction. Returns the packet which lets
+    /// the remote endpoint know, or `None` if there is no such transfer.
+    pub fn cancel_transfer(&mut self, id: TransferId) -> Option<ProcessedPacket> {
+        let header = self.transfers.cancel(id)?;
+        Some(self.process_transfer_packet(header, Vec::new(), Instant::now()))
+    }
+
+    /// Get the ids of the transfers with this connection that are still going on.
+    pub fn transfer_ids(&self) -> Vec<TransferId> {
+        self.transfers.ids()
+    }
+
+    /// Returns the events of the transfers with this connection since the last time this was
+    /// called.
+    pub fn take_transfer_events(&mut self) -> Vec<SocketEvent> {
+        let address = self.remote_address;
+        self.transfers
+            .take_events()
+            .into_iter()
+            .map(|event| match event {
+                TransferEvent::Data(id, data) => SocketEvent::TransferData(address, id, data),
+                TransferEvent::Progress(id, progress) => {
+                    SocketEvent::TransferProgress(address, id, progress)
+                }
+                TransferEvent::Completed(id) => SocketEvent::TransferCompleted(address, id),
+                TransferEvent::Cancelled(id) => SocketEvent::TransferCancelled(address, id),
+            })
+            .collect()
+    }
+
+    /// Checks if there are packets waiting for the congestion control to allow them to be sent.
+    pub fn has_paced_packets(&self) -> bool {
+        !self.paced_packets.is_empty()
+    }
+
+    /// Get the time until the congestion control allows the next packet to be sent.
+    pub fn time_until_next_send(&self) -> Duration {
+        self.congestion_control.time_until_next_send()
+    }
+
+    /// Get the round trip time measurements of this connection, or `None` if none of our packets
+    /// has been acknowledged yet.
+    pub fn round_trip_time(&self) -> Option<RoundTripTime> {
+        self.rtt_measurer.round_trip_time()
+    }
+
+    /// Records a datagram of `bytes` bytes that was sent to this connection.
+    pub fn record_sent(&mut self, bytes: usize, now: Instant) {
+        self.stats.record_sent(bytes, now);
+    }
+
+    /// Get the traffic statistics of this connection.
+    pub fn stats(&self, now: Instant) -> ConnectionStats {
+        self.stats.stats(self.congestion_control.packet_loss(), now)
+    }
+
+    /// Returns the network quality of this connection if it changed since the last time this was
+    /// called. Connections start out with a good network quality.
+    pub fn take_quality_change(&mut self) -> Option<NetworkQuality> {
+        let quality = self.congestion_control.quality();
+        if quality == self.reported_quality {
+            return None;
+        }
+
+        self.reported_quality = quality;
+        Some(quality)
+    }
+
+    /// Get the number of packets per second we are currently allowed to send to this connection.
+    pub fn send_rate(&self) -> u32 {
+        self.congestion_control.send_rate()
     }
 
     /// This pre-process the given Packet to be send over the network.
     /// It will perform some actions related to how the packet should be delivered and return
     /// a ProcessedPacket
+    #[cfg(any(test, feature = "bench"))]
     pub fn process_outgoing(&mut self, packet: Packet) -> io::Result<ProcessedPacket> {
-        if packet.payload().len() > self.max_packet_size_bytes {
+        if packet.payload().len() > self.max_packet_size_bytes() {
             return Err(PacketError::ExceededMaxPacketSize.into());
         }
 
-        let reliability_header = match packet.delivery_method() {
-            // TODO: Only implementing the reliable packets currently
-            DeliveryMethod::ReliableUnordered => {
-                // Queue congestion data.
+        Ok(self.process_checked_outgoing(packet))
+    }
+
+    /// Pre-processes a packet whose size was already checked, like the packets that were queued
+    /// by `queue_outgoing`.
+    fn process_checked_outgoing(&mut self, packet: Packet) -> ProcessedPacket {
+        let arranging_header = self
+            .arranging_system
+            .outgoing_header(packet.delivery_method(), packet.stream_id());
+
+        self.process_sent_packet(SentPacket::new(packet, arranging_header, Instant::now()))
+    }
+
+    /// This pre-processes the reliable packets that were dropped or not acknowledged in time
+    /// (see `queue_retransmissions`) to be sent over the network again.
+    ///
+    /// Every packet gets a new sequence number and is awaiting acknowledgement again, but keeps
+    /// its place within its ordering or sequencing stream.
+    pub fn process_dropped_packets(&mut self, now: Instant) -> Vec<ProcessedPacket> {
+        let chunk_size = self.transfer_chunk_size();
+        let dropped_packets = mem::take(&mut self.dropped_packets);
+        let mut processed = Vec::with_capacity(dropped_packets.len());
+        for mut sent_packet in dropped_packets {
+            sent_packet.sent_at = now;
+            sent_packet.retries = sent_packet.retries.saturating_add(1);
+            for sent_packet in self.split_transfer_chunk(sent_packet, chunk_size) {
+                processed.push(self.process_sent_packet(sent_packet));
+            }
+        }
+        processed
+    }
+
+    /// Get the largest chunk of a transfer that fits a single datagram.
+    fn transfer_chunk_size(&self) -> usize {
+        self.mtu() as usize - transfer_header_size()
+    }
+
+    /// Splits a chunk of a transfer that no longer fits a single datagram, because the path MTU
+    /// went down since it was first sent, into chunks of at most `chunk_size` bytes. Chunks are
+    /// addressed by their offset, so the receiver can't tell the difference. Any other packet is
+    /// returned as it is.
+    fn split_transfer_chunk(
+        &mut self,
+        sent_packet: SentPacket,
+        chunk_size: usize,
+    ) -> Vec<SentPacket> {
+        let header = match sent_packet.transfer_header {
+            Some(header)
+                if header.action() == TransferAction::Chunk
+                    && sent_packet.packet.payload().len() > chunk_size =>
+            {
+                header
+            }
+            _ => return vec![sent_packet],
+        };
+
+        let chunks: Vec<SentPacket> = sent_packet
+            .packet
+            .payload()
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, data)| SentPacket {
+                packet: Packet::reliable_unordered(self.remote_address, data.to_vec()),
+                arranging_header: None,
+                transfer_header: Some(TransferHeader::chunk(
+                    header.transfer_id(),
+                    header.offset() + (index * chunk_size) as u64,
+                    header.total_size(),
+                )),
+                sent_at: sent_packet.sent_at,
+                retries: sent_packet.retries,
+            })
+            .collect();
+        self.transfers.on_chunk_split(header, chunks.len());
+        chunks
+    }
+
+    /// Wraps a packet of a transfer in a reliable packet, which is sent again like any other
+    /// reliable packet until it is acknowledged.
+    fn process_transfer_packet(
+        &mut self,
+        header: TransferHeader,
+        data: Vec<u8>,
+        now: Instant,
+    ) -> ProcessedPacket {
+        let packet = Packet::reliable_unordered(self.remote_address, data);
+        let sent_packet = SentPacket {
+            transfer_header: Some(header),
+            ..SentPacket::new(packet, None, now)
+        };
+        self.process_sent_packet(sent_packet)
+    }
+
+    /// Assigns the next sequence number to a packet and adds the headers it needs to be delivered.
+    ///
+    /// Our acknowledgements are added to every packet that has to be acknowledged itself, and to
+    /// any other packet as long as there are acknowledgements we haven't sent yet.
+    fn process_sent_packet(&mut self, sent_packet: SentPacket) -> ProcessedPacket {
+        let is_acknowledged = sent_packet.packet.delivery_method().is_acknowledged();
+        if is_acknowledged {
+            // Queue congestion data. We can't tell which send of a packet that was sent again gets
+            // acknowledged, so those are left out of the round trip time measurements.
+            if sent_packet.retries == 0 {
                 self.congestion_data.insert(
-                    CongestionData::new(self.sequence_num, Instant::now()),
+                    CongestionData::new(self.sequence_num, sent_packet.sent_at),
                     self.sequence_num,
                 );
+            }
 
-                // Queue packet for awaiting acknowledgement.
-                self.local_acks.enqueue(self.sequence_num, packet.payload());
-
-                let header = ReliableHeader::new(
-                    self.external_acks.last_acked(),
-                    self.external_acks.ack_field(),
-                );
+            // Queue packet for awaiting acknowledgement.
+            self.local_acks
+                .enqueue(self.sequence_num, sent_packet.clone());
+        }
 
-                Some(header)
-            }
-            _ => None,
+        let reliability_header = if is_acknowledged || self.has_pending_acks() {
+            Some(self.take_reliable_header())
+        } else {
+            None
         };
 
-        let processed_packet = ProcessedPacket::new(self.sequence_num, packet, reliability_header);
+        self.last_sent_time = sent_packet.sent_at;
+        let mut processed_packet = ProcessedPacket::new(
+            self.sequence_num,
+            sent_packet.packet,
+            reliability_header,
+            sent_packet.arranging_header,
+        );
+        if let Some(transfer_header) = sent_packet.transfer_header {
+            processed_packet = processed_packet.with_transfer_header(transfer_header);
+        }
 
         // Increase local sequence number.
         self.sequence_num = self.sequence_num.wrapping_add(1);
 
-        Ok(processed_packet)
+        processed_packet
+    }
+
+    /// Serializes a heartbeat packet, which keeps the connection alive while we have nothing else
+    /// to send. It also carries our acknowledgements, so the remote endpoint finds out about
+    /// delivered and dropped packets even if we are silent.
+    ///
+    /// Heartbeats don't use a sequence number, so they are never acknowledged themselves.
+    pub fn create_heartbeat_packet(&mut self) -> io::Result<Vec<u8>> {
+        self.create_acks_only_packet(PacketType::HeartBeat)
+    }
+
+    /// Serializes a packet that only carries our acknowledgements, which is sent when we haven't
+    /// been able to add them to any other packet for a while.
+    ///
+    /// Ack packets don't use a sequence number, so they are never acknowledged themselves.
+    pub fn create_ack_packet(&mut self) -> io::Result<Vec<u8>> {
+        self.create_acks_only_packet(PacketType::Ack)
+    }
+
+    /// Serializes a packet of the given type that carries nothing but our acknowledgements.
+    fn create_acks_only_packet(&mut self, packet_type: PacketType) -> io::Result<Vec<u8>> {
+        let header = StandardHeader::new(
+            DeliveryMethod::UnreliableUnordered,
+            packet_type,
+            self.sequence_num,
+        )
+        .with_acks(true);
+        let reliable_header = self.take_reliable_header();
+        self.last_sent_time = Instant::now();
+
+        let mut buffer = Vec::with_capacity(header.size() + reliable_header.size());
+        header.write(&mut buffer)?;
+        reliable_header.write(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Records that the remote endpoint proved it is reachable, which allows us to send it probes.
+    pub fn mark_reachable(&mut self, now: Instant) {
+        self.mtu_discovery.start(now);
+    }
+
+    /// Serializes a probe if it is time to send one, see `MtuDiscovery`. The probe is padded to
+    /// the size it is testing.
+    pub fn create_mtu_probe(&mut self, now: Instant) -> io::Result<Option<Vec<u8>>> {
+        let timeout = self.retransmission_timeout();
+        let size = match self.mtu_discovery.poll_probe(now, timeout) {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        let header = StandardHeader::new(
+            DeliveryMethod::UnreliableUnordered,
+            PacketType::MtuProbe,
+            self.sequence_num,
+        );
+        let mut buffer = Vec::with_capacity(size as usize);
+        header.write(&mut buffer)?;
+        buffer.write_u16::<BigEndian>(size)?;
+        buffer.resize(size as usize, 0);
+        Ok(Some(buffer))
+    }
+
+    /// Serializes the answers to the probes we received since the last time this was called.
+    pub fn create_mtu_probe_replies(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let header = StandardHeader::new(
+            DeliveryMethod::UnreliableUnordered,
+            PacketType::MtuProbeAck,
+            self.sequence_num,
+        );
+        mem::take(&mut self.mtu_probe_replies)
+            .into_iter()
+            .map(|size| {
+                let mut buffer = Vec::with_capacity(header.size() + 2);
+                header.write(&mut buffer)?;
+                buffer.write_u16::<BigEndian>(size)?;
+                Ok(buffer)
+            })
+            .collect()
+    }
+
+    /// Get the largest datagram we currently send to this connection.
+    pub fn mtu(&self) -> u16 {
+        self.mtu_discovery.mtu()
+    }
+
+    /// Get the size of the fragments packets to this connection are split into, which leaves room
+    /// for the headers within the MTU.
+    pub fn fragment_size(&self) -> u16 {
+        (self.mtu() as usize - max_header_size()) as u16
+    }
+
+    /// Get the maximal size of a packet to this connection, which goes down when we have to use
+    /// smaller fragments.
+    pub fn max_packet_size_bytes(&self) -> usize {
+        self.max_packet_size_bytes
+            .min(self.fragment_size() as usize * self.max_fragments as usize)
+    }
+
+    /// Records that the connection handshake with the remote endpoint is done.
+    ///
+    /// Only the two endpoints know the challenge of the handshake, so it is sent along with our
+    /// disconnect packet to prove it comes from us.
+    pub fn complete_handshake(&mut self, challenge: ChallengeHeader, now: Instant) {
+        self.handshake_challenge = Some(challenge);
+        self.mark_reachable(now);
+    }
+
+    /// Checks whether a disconnect packet, read up to its standard header, was sent by the remote
+    /// endpoint. If the connection was created by a handshake, the packet has to carry the
+    /// challenge of that handshake.
+    pub fn is_authentic_disconnect(&self, cursor: &mut io::Cursor<&[u8]>) -> bool {
+        match self.handshake_challenge {
+            Some(expected) => ChallengeHeader::read(cursor).ok() == Some(expected),
+            None => true,
+        }
+    }
+
+    /// Serializes a disconnect packet, which lets the remote endpoint know we are about to forget
+    /// about this connection.
+    pub fn create_disconnect_packet(&mut self) -> io::Result<Vec<u8>> {
+        let header = StandardHeader::new(
+            DeliveryMethod::UnreliableUnordered,
+            PacketType::Disconnect,
+            self.sequence_num,
+        );
+        self.sequence_num = self.sequence_num.wrapping_add(1);
+        self.last_sent_time = Instant::now();
+
+        let mut buffer = Vec::with_capacity(header.size());
+        header.write(&mut buffer)?;
+        if let Some(challenge) = self.handshake_challenge {
+            challenge.write(&mut buffer)?;
+        }
+        Ok(buffer)
     }
 
     /// Represents the duration since we last received a packet from this client
-    pub fn time_since_last_packet(&self) -> Duration {
-        let now = Instant::now();
+    pub fn time_since_last_packet(&self, now: Instant) -> Duration {
         now.duration_since(self.last_packet_time)
     }
 
+    /// Represents the duration since we last sent a packet to this client
+    pub fn time_since_last_sent(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_sent_time)
+    }
+
     /// The remote address of the client
     pub fn remote_address(&self) -> SocketAddr {
         self.remote_address
@@ -162,26 +750,161 @@ impl VirtualConnection {
 
     /// Check if this channel has dropped packets.
     ///
-    /// You could directly call `ReliableChannel::drain_dropped_packets()` and if it returns an empty vector you know there are no packets.
-    /// But draining a vector will have its extra check logic even if it's empty.
+    /// You could directly call `VirtualConnection::process_dropped_packets()` and if it returns an empty vector you know there are no packets.
     /// So that's why this function exists just a little shortcut to check if there are dropped packets which will be faster at the end.
     pub fn has_dropped_packets(&self) -> bool {
         !self.dropped_packets.is_empty()
     }
 
-    /// Creates a draining iterator that removes dropped packets and yield the ones that are removed.
-    ///
-    /// So why drain?
-    /// You have to think about the packet flow first.
-    /// 1. Once we send a packet we place it in a queue until acknowledged.
-    /// 2. If the packet doesn't get acknowledged in some time it will be dropped.
-    /// 3. When we notice the packet drop we directly want to resend the dropped packet.
-    /// 4. Once we notice that we start at '1' again.
+    /// Returns the tickets of the packets the remote endpoint acknowledged since this was last
+    /// called.
+    pub fn take_acked_tickets(&mut self) -> Vec<MessageTicket> {
+        mem::take(&mut self.acked_tickets)
+    }
+
+    /// Discards the fragments of packets that did not all arrive within the
+    /// `fragment_reassembly_timeout` config.
+    pub fn remove_expired_fragments(&mut self, now: Instant) {
+        self.reassembler.remove_expired(now);
+    }
+
+    /// Checks if we received packets we haven't sent our acknowledgements for yet.
+    pub fn has_pending_acks(&self) -> bool {
+        self.acks_pending_since.is_some()
+    }
+
+    /// Checks if we received packets at least `ack_delay` ago that we haven't sent our
+    /// acknowledgements for yet, in which case we should send an ack packet.
+    pub fn acks_required(&self, ack_delay: Duration, now: Instant) -> bool {
+        self.acks_pending_since
+            .is_some_and(|since| now.duration_since(since) >= ack_delay)
+    }
+
+    /// Returns the tickets of the unreliable notified packets that were dropped on the way since
+    /// this was last called.
+    pub fn take_lost_tickets(&mut self) -> Vec<MessageTicket> {
+        mem::take(&mut self.lost_tickets)
+    }
+
+    /// Checks if any of the packets we sent is still waiting to be acknowledged, including the
+    /// reliable packets that are waiting to be sent again.
+    pub fn has_unacked_packets(&self) -> bool {
+        !self.local_acks.is_empty() || !self.dropped_packets.is_empty()
+    }
+
+    /// Returns the tickets of the packets that are still waiting for an acknowledgement, which
+    /// will never come if this connection is dropped.
+    pub fn unacked_tickets(&self) -> Vec<MessageTicket> {
+        let paced_packets = self
+            .paced_packets
+            .iter()
+            .filter(|packet| packet.delivery_method().is_acknowledged());
+        self.local_acks
+            .iter()
+            .chain(self.dropped_packets.iter())
+            .map(|sent_packet| &sent_packet.packet)
+            .chain(paced_packets)
+            .filter_map(|packet| packet.ticket())
+            .collect()
+    }
+
+    /// Queues the reliable packets that were not acknowledged within the retransmission timeout
+    /// to be sent again, see `process_dropped_packets`. The timeout is the smoothed round trip time
+    /// plus four times its variance and doubles with every retry of a packet. Notified packets that were not
+    /// acknowledged in time are considered lost instead.
     ///
-    /// So keeping track of old dropped packets does not make sense, at least for now.
-    /// We except when dropped packets are retrieved they will be sent out so we don't need to keep track of them internally the caller of this function will have ownership over them after the call.
-    pub fn drain_dropped_packets(&mut self) -> Vec<Box<[u8]>> {
-        self.dropped_packets.drain(..).collect()
+    /// Returns `true` if a packet still wasn't acknowledged after being sent again
+    /// `max_retransmissions` times, in which case this connection should be considered broken.
+    pub fn queue_retransmissions(&mut self, now: Instant) -> bool {
+        let retransmissions = self
+            .local_acks
+            .retransmissions(now, self.retransmission_timeout());
+
+        let mut is_broken = false;
+        for (_, sent_packet) in retransmissions {
+            if sent_packet.packet.delivery_method().is_reliable() {
+                is_broken |= sent_packet.retries >= self.max_retransmissions;
+            }
+            self.handle_dropped_packet(sent_packet, now);
+        }
+        is_broken
+    }
+
+    /// The time we wait for a reliable packet to be acknowledged before sending it again.
+    fn retransmission_timeout(&self) -> Duration {
+        match self.rtt_measurer.round_trip_time() {
+            Some(rtt) => (rtt.smoothed() + rtt.variance() * 4).max(MIN_RETRANSMISSION_TIMEOUT),
+            // We haven't measured anything yet, so assume the worst acceptable round trip time.
+            None => self.max_rtt,
+        }
+    }
+
+    /// Creates the header with our acknowledgements for an outgoing packet, after which they are
+    /// no longer pending.
+    fn take_reliable_header(&mut self) -> ReliableHeader {
+        self.acks_pending_since = None;
+        self.external_acks.reliable_header()
+    }
+
+    /// Processes the acknowledgements the remote endpoint sent us.
+    fn process_acks(&mut self, reliable_header: ReliableHeader) {
+        let now = Instant::now();
+
+        // Update acked and dropped packets if there are any.
+        let (acked_packets, dropped_packets) = self.local_acks.ack(
+            reliable_header.last_acked(),
+            reliable_header.ack_field(),
+            reliable_header.window_size(),
+        );
+
+        // Every packet is only acknowledged once here, so each one gives a fresh measurement of
+        // the round trip time.
+        if !acked_packets.is_empty() {
+            self.mark_reachable(now);
+        }
+        for (sequence, sent_packet) in acked_packets {
+            if let Some(congestion_data) = self.congestion_data.get(sequence) {
+                let rtt = now.duration_since(congestion_data.sending_time);
+                self.rtt_measurer.update(rtt);
+                self.congestion_data.remove(sequence);
+            }
+            self.congestion_control.on_packet_acked(now);
+            self.stats.record_acked();
+            self.acked_tickets.extend(sent_packet.packet.ticket());
+            // Cancel packets carry the id of a transfer as well, which may belong to a transfer
+            // of the other side.
+            match sent_packet.transfer_header {
+                Some(transfer_header) if transfer_header.action() == TransferAction::Chunk => {
+                    self.transfers
+                        .on_chunk_acked(transfer_header, sent_packet.packet.payload().len());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(rtt) = self.rtt_measurer.round_trip_time() {
+            let jitter = self.rtt_measurer.jitter();
+            self.congestion_control
+                .on_rtt_sample(rtt.smoothed(), jitter, now);
+        }
+
+        for (sequence, sent_packet) in dropped_packets {
+            self.congestion_data.remove(sequence);
+            self.handle_dropped_packet(sent_packet, now);
+        }
+    }
+
+    /// Queues a reliable packet that was dropped to be sent again, notified packets are reported
+    /// as lost.
+    fn handle_dropped_packet(&mut self, sent_packet: SentPacket, now: Instant) {
+        self.congestion_control.on_packet_lost(now);
+        self.stats.record_lost();
+
+        if sent_packet.packet.delivery_method().is_reliable() {
+            self.dropped_packets.push(sent_packet);
+        } else if let Some(ticket) = sent_packet.packet.ticket() {
+            self.lost_tickets.push(ticket);
+        }
     }
 }
 
@@ -195,3 +918,638 @@ impl fmt::Debug for VirtualConnection {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{NetworkQuality, VirtualConnection};
+    use crate::{
+        config::SocketConfig,
+        net::{local_ack::SentPacket, MessageTicket},
+        packet::{
+            headers::{HeaderReader, StandardHeader, TransferHeader},
+            BufferPool, PacketType, ProcessedPacket,
+        },
+        Packet,
+    };
+    use std::{
+        io::Cursor,
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    fn create_connection() -> VirtualConnection {
+        create_connection_with_config(&SocketConfig::default())
+    }
+
+    fn create_connection_with_config(config: &SocketConfig) -> VirtualConnection {
+        let address: SocketAddr = "127.0.0.1:10000".parse().unwrap();
+        let buffer_pool = BufferPool::new(
+            config.payload_pool_size(),
+            config.receive_buffer_size_bytes(),
+        );
+        VirtualConnection::new(address, config, buffer_pool)
+    }
+
+    /// Serializes a packet on the sending connection and returns all of the resulting datagrams.
+    fn serialize(connection: &mut VirtualConnection, packet: Packet) -> Vec<Vec<u8>> {
+        let processed = connection.process_outgoing(packet).unwrap();
+        write_datagrams(&processed)
+    }
+
+    /// Writes out every datagram of a processed packet.
+    fn write_datagrams(processed: &ProcessedPacket) -> Vec<Vec<u8>> {
+        let config = SocketConfig::default();
+        let fragment_size = config.fragment_size_bytes();
+        let fragment_count = processed
+            .fragment_count(fragment_size, config.max_fragments())
+            .unwrap();
+        (0..fragment_count)
+            .map(|fragment_id| {
+                let mut datagram = Vec::new();
+                processed
+                    .write_fragment(fragment_id, fragment_size, &mut datagram)
+                    .unwrap();
+                datagram
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_fragmented_packet() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let payload: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+
+        let datagrams = serialize(
+            &mut sender,
+            Packet::reliable_unordered(receiver.remote_address(), payload.clone()),
+        );
+        assert_eq!(datagrams.len(), 3);
+
+        // Fragments can arrive out of order and more than once.
+        assert!(receiver.process_incoming(&datagrams[2]).unwrap().is_empty());
+        assert!(receiver.process_incoming(&datagrams[0]).unwrap().is_empty());
+        assert!(receiver.process_incoming(&datagrams[2]).unwrap().is_empty());
+
+        let packets = receiver.process_incoming(&datagrams[1]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload(), payload.as_slice());
+    }
+
+    #[test]
+    fn delivers_reliable_ordered_packets_in_order() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+
+        let datagrams: Vec<Vec<u8>> = (0..3)
+            .flat_map(|i| {
+                serialize(
+                    &mut sender,
+                    Packet::reliable_ordered(address, vec![i], None),
+                )
+            })
+            .collect();
+
+        assert!(receiver.process_incoming(&datagrams[2]).unwrap().is_empty());
+        assert!(receiver.process_incoming(&datagrams[1]).unwrap().is_empty());
+
+        let packets = receiver.process_incoming(&datagrams[0]).unwrap();
+        let payloads: Vec<&[u8]> = packets.iter().map(Packet::payload).collect();
+        assert_eq!(payloads, vec![&[0], &[1], &[2]]);
+    }
+
+    #[test]
+    fn arranges_packets_per_stream() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+
+        let stalled = serialize(
+            &mut sender,
+            Packet::reliable_ordered(address, vec![0], Some(1)),
+        );
+        let waiting = serialize(
+            &mut sender,
+            Packet::reliable_ordered(address, vec![1], Some(1)),
+        );
+        let other = serialize(
+            &mut sender,
+            Packet::reliable_ordered(address, vec![2], Some(2)),
+        );
+
+        assert!(receiver.process_incoming(&waiting[0]).unwrap().is_empty());
+
+        let packets = receiver.process_incoming(&other[0]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].stream_id(), Some(2));
+
+        let packets = receiver.process_incoming(&stalled[0]).unwrap();
+        let payloads: Vec<&[u8]> = packets.iter().map(Packet::payload).collect();
+        assert_eq!(payloads, vec![&[0], &[1]]);
+        assert_eq!(packets[0].stream_id(), Some(1));
+    }
+
+    #[test]
+    fn drops_older_sequenced_packets() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+
+        let datagrams: Vec<Vec<u8>> = (0..3)
+            .flat_map(|i| serialize(&mut sender, Packet::sequenced(address, vec![i], None)))
+            .collect();
+
+        assert_eq!(receiver.process_incoming(&datagrams[1]).unwrap().len(), 1);
+        assert!(receiver.process_incoming(&datagrams[0]).unwrap().is_empty());
+        assert!(receiver.process_incoming(&datagrams[1]).unwrap().is_empty());
+        assert_eq!(receiver.process_incoming(&datagrams[2]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn drops_older_unreliable_ordered_packets() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+
+        let datagrams: Vec<Vec<u8>> = (0..2)
+            .flat_map(|i| {
+                serialize(
+                    &mut sender,
+                    Packet::unreliable_ordered(address, vec![i], None),
+                )
+            })
+            .collect();
+
+        assert_eq!(receiver.process_incoming(&datagrams[1]).unwrap().len(), 1);
+        assert!(receiver.process_incoming(&datagrams[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_ack_ordered_packet_outside_of_window() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+        let buffer_size = SocketConfig::default().ordering_buffer_size();
+
+        let datagrams: Vec<Vec<u8>> = (0..=buffer_size)
+            .flat_map(|_| {
+                serialize(
+                    &mut sender,
+                    Packet::reliable_ordered(address, vec![1], None),
+                )
+            })
+            .collect();
+
+        // The first packet is missing, so the last one does not fit in the ordering buffer.
+        let last = datagrams.last().unwrap();
+        assert!(receiver.process_incoming(last).unwrap().is_empty());
+        assert_eq!(receiver.external_acks.last_acked(), 0);
+
+        receiver.process_incoming(&datagrams[1]).unwrap();
+        assert_eq!(receiver.external_acks.last_acked(), 1);
+    }
+
+    #[test]
+    fn does_not_ack_incomplete_packet() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let payload = vec![1; 2000];
+
+        // Make sure the receiver has acknowledged something so we can tell the difference.
+        let first = serialize(
+            &mut sender,
+            Packet::reliable_unordered(receiver.remote_address(), vec![1]),
+        );
+        receiver.process_incoming(&first[0]).unwrap();
+
+        let datagrams = serialize(
+            &mut sender,
+            Packet::reliable_unordered(receiver.remote_address(), payload),
+        );
+        receiver.process_incoming(&datagrams[0]).unwrap();
+        assert_eq!(receiver.external_acks.last_acked(), 0);
+
+        receiver.process_incoming(&datagrams[1]).unwrap();
+        assert_eq!(receiver.external_acks.last_acked(), 1);
+    }
+
+    #[test]
+    fn creates_disconnect_packet() {
+        let mut connection = create_connection();
+
+        let payload = connection.create_disconnect_packet().unwrap();
+        let header = StandardHeader::read(&mut Cursor::new(payload.as_slice())).unwrap();
+
+        assert_eq!(header.packet_type(), PacketType::Disconnect);
+        assert_eq!(header.sequence_num(), 0);
+        assert_eq!(connection.sequence_num, 1);
+    }
+
+    #[test]
+    fn heartbeat_carries_acks_without_being_surfaced() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        let datagrams = serialize(
+            &mut sender,
+            Packet::reliable_unordered(receiver.remote_address(), vec![1]),
+        );
+        receiver.process_incoming(&datagrams[0]).unwrap();
+        assert!(!sender.local_acks.is_empty());
+
+        let heartbeat = receiver.create_heartbeat_packet().unwrap();
+        let packets = sender.process_incoming(&heartbeat).unwrap();
+
+        assert!(packets.is_empty());
+        assert!(sender.local_acks.is_empty());
+    }
+
+    #[test]
+    fn heartbeat_does_not_use_sequence_number() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        let heartbeat = sender.create_heartbeat_packet().unwrap();
+        receiver.process_incoming(&heartbeat).unwrap();
+
+        assert_eq!(sender.sequence_num, 0);
+        assert_eq!(receiver.external_acks.ack_field(), 0);
+    }
+
+    #[test]
+    fn retransmits_unacked_packet_after_timeout() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        // This packet gets lost.
+        serialize(
+            &mut sender,
+            Packet::reliable_ordered(receiver.remote_address(), vec![1, 2, 3], None),
+        );
+        let now = Instant::now();
+
+        assert!(!sender.queue_retransmissions(now));
+        assert!(!sender.has_dropped_packets());
+
+        let timeout = sender.retransmission_timeout();
+        assert!(!sender.queue_retransmissions(now + timeout));
+        assert!(sender.has_dropped_packets());
+
+        let processed = sender.process_dropped_packets(now + timeout);
+        assert_eq!(processed.len(), 1);
+        let datagrams = write_datagrams(&processed[0]);
+
+        // The packet is sent with a new sequence number, but keeps its place in the stream.
+        let packets = receiver.process_incoming(&datagrams[0]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload(), &[1, 2, 3]);
+        assert_eq!(receiver.external_acks.last_acked(), 1);
+
+        // The retransmission is acknowledged like any other packet, but it is not measured since
+        // we can't tell which of the sends arrived.
+        let heartbeat = receiver.create_heartbeat_packet().unwrap();
+        sender.process_incoming(&heartbeat).unwrap();
+        assert!(sender.local_acks.is_empty());
+        assert_eq!(sender.round_trip_time(), None);
+    }
+
+    #[test]
+    fn measures_rtt_of_every_acked_packet() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        let address = receiver.remote_address();
+        for _ in 0..3 {
+            let datagrams = serialize(&mut sender, Packet::reliable_unordered(address, vec![1]));
+            receiver.process_incoming(&datagrams[0]).unwrap();
+        }
+        assert_eq!(sender.round_trip_time(), None);
+
+        let heartbeat = receiver.create_heartbeat_packet().unwrap();
+        sender.process_incoming(&heartbeat).unwrap();
+        assert!(sender.round_trip_time().is_some());
+        for sequence in 0..3 {
+            assert!(sender.congestion_data.get(sequence).is_none());
+        }
+    }
+
+    #[test]
+    fn delivers_retransmitted_packet_once() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        // The packet arrives, but the acknowledgement does not.
+        let address = receiver.remote_address();
+        let datagrams = serialize(&mut sender, Packet::reliable_unordered(address, vec![1]));
+        assert_eq!(receiver.process_incoming(&datagrams[0]).unwrap().len(), 1);
+
+        let now = Instant::now();
+        let timeout = sender.retransmission_timeout();
+        assert!(!sender.queue_retransmissions(now + timeout));
+
+        let processed = sender.process_dropped_packets(now + timeout);
+        let datagrams = write_datagrams(&processed[0]);
+
+        // The duplicate is not handed to the application again, but it is acknowledged.
+        assert!(receiver.process_incoming(&datagrams[0]).unwrap().is_empty());
+        assert_eq!(receiver.external_acks.last_acked(), 1);
+
+        let heartbeat = receiver.create_heartbeat_packet().unwrap();
+        sender.process_incoming(&heartbeat).unwrap();
+        assert!(sender.local_acks.is_empty());
+    }
+
+    #[test]
+    fn connection_breaks_after_max_retransmissions() {
+        let mut connection = create_connection();
+        let max_retransmissions = SocketConfig::default().max_retransmissions();
+        let mut now = Instant::now();
+
+        let address = connection.remote_address();
+        serialize(
+            &mut connection,
+            Packet::reliable_unordered(address, vec![1]),
+        );
+
+        for _ in 0..max_retransmissions {
+            now += Duration::from_secs(60);
+            assert!(!connection.queue_retransmissions(now));
+            connection.process_dropped_packets(now);
+        }
+
+        now += Duration::from_secs(60);
+        assert!(connection.queue_retransmissions(now));
+    }
+
+    #[test]
+    fn piggybacks_pending_acks_on_unreliable_packets() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+
+        let address = receiver.remote_address();
+        let datagrams = serialize(&mut sender, Packet::reliable_unordered(address, vec![1]));
+        receiver.process_incoming(&datagrams[0]).unwrap();
+        assert!(receiver.has_pending_acks());
+
+        let address = sender.remote_address();
+        let datagrams = serialize(&mut receiver, Packet::unreliable(address, vec![2]));
+        let header = StandardHeader::read(&mut Cursor::new(datagrams[0].as_slice())).unwrap();
+        assert!(header.has_acks());
+        assert!(!receiver.has_pending_acks());
+
+        let packets = sender.process_incoming(&datagrams[0]).unwrap();
+        assert_eq!(packets[0].payload(), &[2]);
+        assert!(sender.local_acks.is_empty());
+
+        // Once the acknowledgements are sent, unreliable packets go without them again.
+        let datagrams = serialize(&mut receiver, Packet::unreliable(address, vec![3]));
+        let header = StandardHeader::read(&mut Cursor::new(datagrams[0].as_slice())).unwrap();
+        assert!(!header.has_acks());
+    }
+
+    #[test]
+    fn sends_ack_packet_after_delay() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let ack_delay = SocketConfig::default().ack_delay();
+
+        let address = receiver.remote_address();
+        let datagrams = serialize(&mut sender, Packet::reliable_unordered(address, vec![1]));
+        receiver.process_incoming(&datagrams[0]).unwrap();
+        let now = Instant::now();
+
+        assert!(!receiver.acks_required(ack_delay, now - ack_delay));
+        assert!(receiver.acks_required(ack_delay, now + ack_delay));
+
+        let ack = receiver.create_ack_packet().unwrap();
+        assert!(!receiver.has_pending_acks());
+        let header = StandardHeader::read(&mut Cursor::new(ack.as_slice())).unwrap();
+        assert_eq!(header.packet_type(), PacketType::Ack);
+
+        assert!(sender.process_incoming(&ack).unwrap().is_empty());
+        assert!(sender.local_acks.is_empty());
+    }
+
+    #[test]
+    fn reports_acked_notified_packet() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let ticket = MessageTicket(1);
+
+        let address = receiver.remote_address();
+        let packet = Packet::unreliable_notified(address, vec![1]).with_ticket(ticket);
+        let datagrams = serialize(&mut sender, packet);
+        assert_eq!(receiver.process_incoming(&datagrams[0]).unwrap().len(), 1);
+
+        let heartbeat = receiver.create_heartbeat_packet().unwrap();
+        sender.process_incoming(&heartbeat).unwrap();
+        assert_eq!(sender.take_acked_tickets(), vec![ticket]);
+        assert!(sender.take_lost_tickets().is_empty());
+        assert!(sender.local_acks.is_empty());
+    }
+
+    #[test]
+    fn reports_lost_notified_packet_without_resending() {
+        let mut connection = create_connection();
+        let ticket = MessageTicket(1);
+
+        let address = connection.remote_address();
+        let packet = Packet::unreliable_notified(address, vec![1]).with_ticket(ticket);
+        serialize(&mut connection, packet);
+
+        let now = Instant::now() + Duration::from_secs(60);
+        assert!(!connection.queue_retransmissions(now));
+        assert!(!connection.has_dropped_packets());
+        assert_eq!(connection.take_lost_tickets(), vec![ticket]);
+        assert!(connection.local_acks.is_empty());
+    }
+
+    #[test]
+    fn paces_outgoing_packets() {
+        let config = SocketConfig::default();
+        let mut connection = create_connection();
+        let address = connection.remote_address();
+        let now = Instant::now();
+
+        for _ in 0..=config.good_send_rate() {
+            let packet = Packet::unreliable(address, vec![1]);
+            connection.queue_outgoing(packet).unwrap();
+        }
+
+        let processed = connection.process_paced_packets(now);
+        assert_eq!(processed.len(), config.good_send_rate() as usize);
+        assert!(connection.has_paced_packets());
+
+        let later = now + connection.time_until_next_send() + Duration::from_millis(1);
+        assert_eq!(connection.process_paced_packets(later).len(), 1);
+        assert!(!connection.has_paced_packets());
+    }
+
+    #[test]
+    fn bounds_paced_packets() {
+        let config = SocketConfig::builder()
+            .max_paced_packets(2)
+            .build()
+            .unwrap();
+        let mut connection = create_connection_with_config(&config);
+        let address = connection.remote_address();
+
+        // Only the most recent packet of a sequenced stream is worth sending.
+        connection
+            .queue_outgoing(Packet::sequenced(address, vec![1], None))
+            .unwrap();
+        connection
+            .queue_outgoing(Packet::sequenced(address, vec![2], None))
+            .unwrap();
+        assert_eq!(connection.paced_packets.len(), 1);
+
+        // The oldest unreliable packet makes room once the queue is full.
+        let ticket = MessageTicket(1);
+        let notified = Packet::unreliable_notified(address, vec![3]).with_ticket(ticket);
+        connection.queue_outgoing(notified).unwrap();
+        connection
+            .queue_outgoing(Packet::reliable_unordered(address, vec![4]))
+            .unwrap();
+        connection
+            .queue_outgoing(Packet::reliable_unordered(address, vec![5]))
+            .unwrap();
+        assert_eq!(connection.take_lost_tickets(), vec![ticket]);
+
+        // Nothing is left to make room for another reliable packet.
+        assert!(connection
+            .queue_outgoing(Packet::reliable_unordered(address, vec![6]))
+            .is_err());
+        connection
+            .queue_outgoing(Packet::unreliable(address, vec![7]))
+            .unwrap();
+        let payloads: Vec<&[u8]> = connection
+            .paced_packets
+            .iter()
+            .map(|packet| packet.payload())
+            .collect();
+        assert_eq!(payloads, vec![&[4][..], &[5][..]]);
+    }
+
+    #[test]
+    fn reports_quality_changes_once() {
+        let mut connection = create_connection();
+        let address = connection.remote_address();
+        assert_eq!(connection.take_quality_change(), None);
+
+        for _ in 0..5 {
+            serialize(
+                &mut connection,
+                Packet::unreliable_notified(address, vec![1]),
+            );
+        }
+        assert!(!connection.queue_retransmissions(Instant::now() + Duration::from_secs(60)));
+
+        assert_eq!(connection.take_quality_change(), Some(NetworkQuality::Bad));
+        assert_eq!(connection.take_quality_change(), None);
+    }
+
+    #[test]
+    fn reuses_payload_buffers_of_dropped_packets() {
+        let mut sender = create_connection();
+        let mut receiver = create_connection();
+        let address = receiver.remote_address();
+
+        let datagrams = serialize(&mut sender, Packet::unreliable(address, vec![1, 2, 3]));
+        let packets = receiver.process_incoming(&datagrams[0]).unwrap();
+        let buffer_address = packets[0].payload().as_ptr();
+        drop(packets);
+        assert_eq!(receiver.buffer_pool.available(), 1);
+
+        let datagrams = serialize(&mut sender, Packet::unreliable(address, vec![4, 5, 6]));
+        let packets = receiver.process_incoming(&datagrams[0]).unwrap();
+        assert_eq!(packets[0].payload(), &[4, 5, 6]);
+        assert_eq!(packets[0].payload().as_ptr(), buffer_address);
+        assert_eq!(receiver.buffer_pool.available(), 0);
+    }
+
+    #[test]
+    fn answers_mtu_probes_that_arrive_whole() {
+        let config = SocketConfig::builder()
+            .mtu_discovery_enabled(true)
+            .build()
+            .unwrap();
+        let mut sender = create_connection_with_config(&config);
+        let mut receiver = create_connection_with_config(&config);
+        let now = Instant::now();
+        sender.mark_reachable(now);
+
+        let probe = sender.create_mtu_probe(now).unwrap().unwrap();
+        assert_eq!(probe.len(), sender.mtu() as usize);
+        assert_eq!(sender.create_mtu_probe(now).unwrap(), None);
+
+        // A truncated probe doesn't prove anything.
+        assert!(receiver
+            .process_incoming(&probe[..probe.len() - 1])
+            .unwrap()
+            .is_empty());
+        assert!(receiver.create_mtu_probe_replies().unwrap().is_empty());
+
+        assert!(receiver.process_incoming(&probe).unwrap().is_empty());
+        let replies = receiver.create_mtu_probe_replies().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(receiver.external_acks.ack_field(), 0);
+
+        // The next probe tests a larger size once the first one is acknowledged.
+        assert!(sender.process_incoming(&replies[0]).unwrap().is_empty());
+        let next_probe = sender.create_mtu_probe(Instant::now()).unwrap().unwrap();
+        assert!(next_probe.len() > probe.len());
+    }
+
+    #[test]
+    fn does_not_probe_unverified_connections() {
+        let config = SocketConfig::builder()
+            .mtu_discovery_enabled(true)
+            .build()
+            .unwrap();
+        let mut sender = create_connection_with_config(&config);
+        let mut receiver = create_connection_with_config(&config);
+        let address = receiver.remote_address();
+        let now = Instant::now();
+
+        // Receiving packets doesn't prove the address they claim to come from is reachable.
+        let datagrams = serialize(&mut receiver, Packet::reliable_unordered(address, vec![1]));
+        sender.process_incoming(&datagrams[0]).unwrap();
+        assert_eq!(sender.create_mtu_probe(now).unwrap(), None);
+
+        // An acknowledgement of one of our packets does.
+        let datagrams = serialize(&mut sender, Packet::reliable_unordered(address, vec![2]));
+        receiver.process_incoming(&datagrams[0]).unwrap();
+        let datagrams = serialize(&mut receiver, Packet::unreliable(address, vec![3]));
+        sender.process_incoming(&datagrams[0]).unwrap();
+        assert!(sender.create_mtu_probe(Instant::now()).unwrap().is_some());
+    }
+
+    #[test]
+    fn splits_transfer_chunks_that_no_longer_fit() {
+        let mut connection = create_connection();
+        let address = connection.remote_address();
+        let now = Instant::now();
+
+        let chunk = SentPacket {
+            packet: Packet::reliable_unordered(address, (0..10).collect()),
+            arranging_header: None,
+            transfer_header: Some(TransferHeader::chunk(1, 20, 30)),
+            sent_at: now,
+            retries: 2,
+        };
+        let chunks = connection.split_transfer_chunk(chunk, 4);
+
+        assert_eq!(chunks.len(), 3);
+        let offsets: Vec<u64> = chunks
+            .iter()
+            .map(|chunk| chunk.transfer_header.unwrap().offset())
+            .collect();
+        assert_eq!(offsets, vec![20, 24, 28]);
+        assert_eq!(chunks[2].packet.payload(), &[8, 9]);
+        assert!(chunks.iter().all(|chunk| chunk.retries == 2));
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.transfer_header.unwrap().total_size() == 30));
+    }
+}
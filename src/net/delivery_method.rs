@@ -18,7 +18,7 @@
 /// - No way of getting dropped packet.
 /// - Duplication possible.
 /// - No fragmentation
-//
+///
 /// TCP's features can be very useful, but they also come with some overhead.
 /// This can be problematic if you only care about some of them.
 ///
@@ -38,13 +38,13 @@ pub enum DeliveryMethod {
     ///
     /// Basically just bare UDP, free to be dropped, used for very unnecessary data, great for 'general' position updates.
     UnreliableUnordered,
-    /// Unreliable. Packets can be dropped, duplicated or arrive with order.
+    /// Unreliable. Packets can be dropped, but older packets are never delivered after newer ones.
     ///
     /// **Details**
     ///
     /// | Packet Drop      | Packet Duplication  | Packet Order      | Packet Fragmentation | Packet Delivery |
     /// | :-------------:  | :-------------:     | :-------------:  | :-------------:       | :-------------: |
-    /// |      Yes        |    Yes               |      Yes          |      No              |       No        |
+    /// |      Yes        |    No               |      Yes          |      No              |       No        |
     ///
     /// Basically just bare UDP, free to be dropped, used for very unnecessary data, great for 'general' position updates but packets will be ordered.
     UnreliableOrdered,
@@ -81,9 +81,48 @@ pub enum DeliveryMethod {
     /// Toss away any packets that are older than the most recent (like a position update, you don't care about older ones),
     /// packets may be dropped, just the application may not receive older ones if a newer one came in first.
     Sequenced,
+    /// Unreliable. Packets can be dropped, duplicated or arrive without order, but the sender finds
+    /// out whether they arrived.
+    ///
+    /// *Details*
+    ///
+    /// |   Packet Drop   | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
+    /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
+    /// |       Yes       |      Yes           |      No          |      Yes             |       No        |
+    ///
+    /// Packets are acknowledged like reliable packets, but they are never sent again. When sent
+    /// with a ticket, a `SocketEvent::Acked` or `SocketEvent::Lost` tells if the packet arrived,
+    /// which is what delta compression against acknowledged snapshots needs.
+    UnreliableNotified,
 }
 
 impl DeliveryMethod {
+    /// Checks if packets with this delivery method are acknowledged and resent when dropped.
+    pub fn is_reliable(self) -> bool {
+        matches!(
+            self,
+            DeliveryMethod::ReliableUnordered | DeliveryMethod::ReliableOrdered
+        )
+    }
+
+    /// Checks if packets with this delivery method carry acknowledgements and are acknowledged by
+    /// the remote endpoint, which is the case for reliable and notified packets.
+    pub(crate) fn is_acknowledged(self) -> bool {
+        self.is_reliable() || self == DeliveryMethod::UnreliableNotified
+    }
+
+    /// Checks if packets with this delivery method carry an arranging header, which is the case
+    /// for every delivery method except unreliable unordered and unreliable notified.
+    pub(crate) fn is_arranged(self) -> bool {
+        matches!(
+            self,
+            DeliveryMethod::ReliableUnordered
+                | DeliveryMethod::UnreliableOrdered
+                | DeliveryMethod::ReliableOrdered
+                | DeliveryMethod::Sequenced
+        )
+    }
+
     /// Get integer value from `DeliveryMethod` enum.
     pub fn get_delivery_method_id(delivery_method: DeliveryMethod) -> u8 {
         delivery_method as u8
@@ -97,6 +136,7 @@ impl DeliveryMethod {
             2 => DeliveryMethod::ReliableUnordered,
             3 => DeliveryMethod::ReliableOrdered,
             4 => DeliveryMethod::Sequenced,
+            5 => DeliveryMethod::UnreliableNotified,
             _ => DeliveryMethod::UnreliableUnordered,
         }
     }
@@ -1,4 +1,7 @@
-use crate::packet::Packet;
+use crate::{
+    net::{MessageTicket, NetworkQuality, TransferId, TransferProgress},
+    packet::Packet,
+};
 use std::net::SocketAddr;
 
 /// Events which will be pushed through the event_receiver returned by RudpSocket::bind.
@@ -7,10 +10,37 @@ pub enum SocketEvent {
     /// A packet has been received from a client.
     Packet(Packet),
     /// A new client connects. Clients are uniquely identified by the ip:port combination at this layer.
+    /// This is generated the first time we send a packet to, or receive a packet from, a client.
     Connect(SocketAddr),
     /// A client disconnects. This is generated from the server-side intentionally disconnecting a client,
-    /// or it could be from the client disconnecting.
+    /// or it could be from the client disconnecting. See `LaminarSocket::disconnect`.
     Disconnect(SocketAddr),
-    /// This is generated if the server has not seen traffic from a client after a configurable amount of time.
+    /// This is generated if the server has not seen traffic from a client after a configurable amount of time,
+    /// or if a client did not acknowledge a reliable packet after it was sent again a configurable number of times.
     TimeOut(SocketAddr),
+    /// The remote endpoint acknowledged the reliable or notified packet that was sent with this
+    /// ticket, see `SocketHandle::send`.
+    Acked(MessageTicket),
+    /// The packet that was sent with this ticket will never be acknowledged, because the
+    /// connection timed out or was disconnected before it was, or because it was a notified
+    /// packet that was dropped on the way. It may or may not have arrived.
+    Lost(MessageTicket),
+    /// The network quality of a client changed. A connection turns bad as soon as its round trip
+    /// time, jitter or packet loss crosses the thresholds in `SocketConfig`, but only turns good
+    /// again after conditions have been good for a while. We send fewer packets to a client while
+    /// its network quality is bad, so this is a good moment to reduce the update rate as well.
+    QualityChanged(SocketAddr, NetworkQuality),
+    /// The next part of a large message a client is sending us, see `SocketHandle::send_transfer`.
+    /// The parts are handed over in order and only once, so they can be written to a file as they
+    /// arrive.
+    TransferData(SocketAddr, TransferId, Vec<u8>),
+    /// A transfer made progress. This is sent at most once per poll of the socket for every
+    /// transfer, in both directions.
+    TransferProgress(SocketAddr, TransferId, TransferProgress),
+    /// A transfer is done: the client acknowledged every part of the message we sent, or we
+    /// received every part of the message the client sent.
+    TransferCompleted(SocketAddr, TransferId),
+    /// A transfer was cancelled by either side, see `SocketHandle::cancel_transfer`, or the
+    /// connection with the client was lost before it was done.
+    TransferCancelled(SocketAddr, TransferId),
 }
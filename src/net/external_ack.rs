@@ -1,24 +1,40 @@
+use crate::packet::headers::{window_mask, ReliableHeader, MAX_ACK_WINDOW_SIZE};
+
 /// Third party's ack information.
 ///
 /// So what does this mean?
 ///
 /// Here we store information about the other side (virtual connection).
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ExternalAcks {
     /// the last sequence number we have received from the other side.
     last_sequence_num: u16,
-    /// We define an "ack bitfield" such that each bit corresponds to acks of the 32 sequence
-    /// numbers before "ack". So let’s say "ack" is 100. If the first bit of "ack bitfield" is set,
-    /// then the packet also includes an ack for packet 99. If the second bit is set, then packet
-    /// 98 is acked. This goes all the way down to the 32nd bit for packet 68.
-    ack_field: u32,
+    /// We define an "ack bitfield" such that each bit corresponds to acks of the sequence numbers
+    /// before "ack", as many as the window size. So let’s say "ack" is 100 and the window size is
+    /// 32. If the first bit of "ack bitfield" is set, then the packet also includes an ack for
+    /// packet 99. If the second bit is set, then packet 98 is acked. This goes all the way down to
+    /// the 32nd bit for packet 68.
+    ack_field: u128,
+    /// The number of sequence numbers before "ack" the ack field describes.
+    window_size: u8,
 }
 
 impl ExternalAcks {
+    /// Creates the ack information for a connection with an ack field describing `window_size`
+    /// packets, up to `MAX_ACK_WINDOW_SIZE`.
+    pub fn new(window_size: u8) -> Self {
+        Self {
+            last_sequence_num: 0,
+            ack_field: 0,
+            window_size: window_size.min(MAX_ACK_WINDOW_SIZE),
+        }
+    }
+
     /// Acknowledges a packet
     pub fn ack(&mut self, sequence_num: u16) {
         let pos_diff = sequence_num.wrapping_sub(self.last_sequence_num);
         let neg_diff = self.last_sequence_num.wrapping_sub(sequence_num);
+        let window_size = u16::from(self.window_size);
 
         if pos_diff == 0 {
             return;
@@ -26,14 +42,15 @@ impl ExternalAcks {
 
         // TODO: Reevaluate this logic. Something doesn't seem right...
         if pos_diff < 32000 {
-            if pos_diff <= 32 {
-                self.ack_field = ((self.ack_field << 1) | 1) << (pos_diff - 1);
+            if pos_diff <= window_size {
+                self.ack_field =
+                    (((self.ack_field << 1) | 1) << (pos_diff - 1)) & window_mask(self.window_size);
             } else {
                 self.ack_field = 0;
             }
             // If the packet is more recent, we update the remote sequence to be equal to the sequence number of the packet.
             self.last_sequence_num = sequence_num;
-        } else if neg_diff <= 32 {
+        } else if neg_diff <= window_size {
             self.ack_field |= 1 << (neg_diff - 1);
         }
     }
@@ -44,9 +61,14 @@ impl ExternalAcks {
     }
 
     /// Accessor for the ack field
-    pub fn ack_field(&self) -> u32 {
+    pub fn ack_field(&self) -> u128 {
         self.ack_field
     }
+
+    /// Creates the reliable header which tells the other side about the packets we received.
+    pub fn reliable_header(&self) -> ReliableHeader {
+        ReliableHeader::new(self.last_sequence_num, self.ack_field, self.window_size)
+    }
 }
 
 #[cfg(test)]
@@ -55,7 +77,7 @@ mod test {
 
     #[test]
     fn acking_single_packet() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
 
         assert_eq!(acks.last_sequence_num, 0);
@@ -64,7 +86,7 @@ mod test {
 
     #[test]
     fn acking_several_packets() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
         acks.ack(1);
         acks.ack(2);
@@ -75,7 +97,7 @@ mod test {
 
     #[test]
     fn acking_several_packets_out_of_order() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(1);
         acks.ack(0);
         acks.ack(2);
@@ -86,31 +108,31 @@ mod test {
 
     #[test]
     fn acking_a_nearly_full_set_of_packets() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
 
         for i in 0..32 {
             acks.ack(i);
         }
 
         assert_eq!(acks.last_sequence_num, 31);
-        assert_eq!(acks.ack_field, !0 >> 1);
+        assert_eq!(acks.ack_field, u128::from(u32::MAX >> 1));
     }
 
     #[test]
     fn acking_a_full_set_of_packets() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
 
         for i in 0..=32 {
             acks.ack(i);
         }
 
         assert_eq!(acks.last_sequence_num, 32);
-        assert_eq!(acks.ack_field, !0);
+        assert_eq!(acks.ack_field, u128::from(u32::MAX));
     }
 
     #[test]
     fn acking_to_the_edge_forward() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
         acks.ack(32);
 
@@ -120,7 +142,7 @@ mod test {
 
     #[test]
     fn acking_too_far_forward() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
         acks.ack(1);
         acks.ack(34);
@@ -131,7 +153,7 @@ mod test {
 
     #[test]
     fn acking_a_whole_buffer_too_far_forward() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
         acks.ack(60);
 
@@ -141,7 +163,7 @@ mod test {
 
     #[test]
     fn acking_too_far_backward() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(33);
         acks.ack(0);
 
@@ -151,18 +173,18 @@ mod test {
 
     #[test]
     fn acking_around_zero() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
 
         for i in 0..33_u16 {
             acks.ack(i.wrapping_sub(16));
         }
         assert_eq!(acks.last_sequence_num, 16);
-        assert_eq!(acks.ack_field, !0);
+        assert_eq!(acks.ack_field, u128::from(u32::MAX));
     }
 
     #[test]
     fn ignores_old_packets() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(40);
         acks.ack(0);
         assert_eq!(acks.last_sequence_num, 40);
@@ -171,7 +193,7 @@ mod test {
 
     #[test]
     fn ignores_really_old_packets() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(30000);
         acks.ack(0);
         assert_eq!(acks.last_sequence_num, 30000);
@@ -180,7 +202,7 @@ mod test {
 
     #[test]
     fn skips_missing_acks_correctly() {
-        let mut acks = ExternalAcks::default();
+        let mut acks = ExternalAcks::new(32);
         acks.ack(0);
         acks.ack(1);
         acks.ack(6);
@@ -196,4 +218,42 @@ mod test {
                 (1 << 5) // 0 (present)
         );
     }
+
+    #[test]
+    fn acking_a_wide_window_of_packets() {
+        let mut acks = ExternalAcks::new(128);
+
+        for i in 0..=128 {
+            acks.ack(i);
+        }
+
+        assert_eq!(acks.last_sequence_num, 128);
+        assert_eq!(acks.ack_field, u128::MAX);
+    }
+
+    #[test]
+    fn acking_to_the_edge_of_a_wide_window() {
+        let mut acks = ExternalAcks::new(64);
+        acks.ack(100);
+        acks.ack(36);
+        acks.ack(35);
+
+        assert_eq!(acks.last_sequence_num, 100);
+        assert_eq!(acks.ack_field, 1 << 63);
+
+        acks.ack(101);
+        assert_eq!(acks.ack_field, 1);
+    }
+
+    #[test]
+    fn creates_reliable_header() {
+        let mut acks = ExternalAcks::new(64);
+        acks.ack(1);
+        acks.ack(2);
+
+        let header = acks.reliable_header();
+        assert_eq!(header.last_acked(), 2);
+        assert_eq!(header.ack_field(), 1 | (1 << 1));
+        assert_eq!(header.window_size(), 64);
+    }
 }
@@ -0,0 +1,117 @@
+use crate::{errors::LaminarError, net::TransferId, packet::Packet};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+};
+
+/// Identifies a packet that was sent through `SocketHandle::send`, so the application can tell
+/// which packet a `SocketEvent::Acked` or `SocketEvent::Lost` is about.
+///
+/// Tickets are unique for the socket that handed them out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageTicket(pub(crate) u64);
+
+/// Commands that can be given to a running `LaminarSocket` through a `SocketHandle`.
+#[derive(Debug)]
+pub(crate) enum SocketCommand {
+    /// Gracefully disconnect from the given remote endpoint.
+    Disconnect(SocketAddr),
+    /// Start sending a large message to the given remote endpoint.
+    StartTransfer(SocketAddr, TransferId, Vec<u8>),
+    /// Cancel a transfer with the given remote endpoint.
+    CancelTransfer(SocketAddr, TransferId),
+    /// Flush the queued packets, disconnect from everyone and stop polling.
+    Shutdown,
+}
+
+/// A cloneable handle to control a `LaminarSocket` from any thread, even after the socket has been
+/// moved into the thread which is polling it.
+#[derive(Clone, Debug)]
+pub struct SocketHandle {
+    command_sender: mpsc::Sender<SocketCommand>,
+    packet_sender: mpsc::Sender<Packet>,
+    next_ticket: Arc<AtomicU64>,
+    next_transfer_id: Arc<AtomicU32>,
+}
+
+impl SocketHandle {
+    pub(crate) fn new(
+        command_sender: mpsc::Sender<SocketCommand>,
+        packet_sender: mpsc::Sender<Packet>,
+        next_ticket: Arc<AtomicU64>,
+        next_transfer_id: Arc<AtomicU32>,
+    ) -> Self {
+        Self {
+            command_sender,
+            packet_sender,
+            next_ticket,
+            next_transfer_id,
+        }
+    }
+
+    /// Sends a packet and returns a ticket to follow its delivery with.
+    ///
+    /// Once the remote endpoint acknowledged a reliable or notified packet, a
+    /// `SocketEvent::Acked` with this ticket is emitted. If the packet can no longer be delivered
+    /// because the connection timed out or was disconnected first, or if a notified packet was
+    /// dropped on the way, a `SocketEvent::Lost` is emitted instead. No events are emitted for
+    /// packets that are sent with any other unreliable delivery method.
+    pub fn send(&self, packet: Packet) -> io::Result<MessageTicket> {
+        let ticket = MessageTicket(self.next_ticket.fetch_add(1, Ordering::Relaxed));
+        if self.packet_sender.send(packet.with_ticket(ticket)).is_err() {
+            return Err(LaminarError::SocketClosed.into());
+        }
+        Ok(ticket)
+    }
+
+    /// Sends a message of any size to the remote endpoint with the given address, and returns the
+    /// id to follow the transfer with.
+    ///
+    /// Unlike a packet, the message isn't limited by `SocketConfig::max_packet_size_bytes`. It is
+    /// split into chunks that each fit a single datagram, which are sent reliably at the pace the
+    /// connection allows. The remote endpoint receives the message piece by piece, in order,
+    /// through `SocketEvent::TransferData` events. Both sides get `SocketEvent::TransferProgress`
+    /// events while the message is underway and a `SocketEvent::TransferCompleted` at the end.
+    ///
+    /// If handshakes are enabled, the transfer is cancelled right away unless there is a
+    /// connection with the remote endpoint.
+    pub fn send_transfer(&self, address: SocketAddr, payload: Vec<u8>) -> io::Result<TransferId> {
+        let id = TransferId::outgoing(self.next_transfer_id.fetch_add(1, Ordering::Relaxed));
+        self.send_command(SocketCommand::StartTransfer(address, id, payload))?;
+        Ok(id)
+    }
+
+    /// Cancels a transfer with the remote endpoint with the given address, which can be a message
+    /// we are sending or receiving. Both sides get a `SocketEvent::TransferCancelled`. Nothing
+    /// happens if the transfer is already done.
+    pub fn cancel_transfer(&self, address: SocketAddr, id: TransferId) -> io::Result<()> {
+        self.send_command(SocketCommand::CancelTransfer(address, id))
+    }
+
+    /// Gracefully disconnects from the remote endpoint with the given address.
+    ///
+    /// The socket will let the remote endpoint know we are leaving, forget about the connection
+    /// and emit a `SocketEvent::Disconnect`. Nothing happens if there is no connection with this
+    /// address.
+    pub fn disconnect(&self, address: SocketAddr) -> io::Result<()> {
+        self.send_command(SocketCommand::Disconnect(address))
+    }
+
+    /// Shuts the socket down.
+    ///
+    /// The socket sends the packets that are still queued, gracefully disconnects from every
+    /// remote endpoint and then stops polling, which makes `LaminarSocket::start_polling` return.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.send_command(SocketCommand::Shutdown)
+    }
+
+    fn send_command(&self, command: SocketCommand) -> io::Result<()> {
+        self.command_sender
+            .send(command)
+            .map_err(|_| LaminarError::SocketClosed.into())
+    }
+}
@@ -0,0 +1,488 @@
+use crate::{
+    config::SocketConfig,
+    net::DeliveryMethod,
+    packet::{
+        headers::{ChallengeHeader, HeaderReader, HeaderWriter, StandardHeader},
+        Packet, PacketType,
+    },
+};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash, Hasher},
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Keeps track of the connection handshakes of a socket.
+///
+/// The handshake goes as follows:
+/// 1. The client sends a `ConnectionRequest` and holds on to the packets the application wants
+///    to send until the handshake is done.
+/// 2. The server answers with a `ConnectionChallenge` containing a token which is signed with a
+///    secret only the server knows. The server does not remember anything about the client yet.
+/// 3. The client echoes the token in a `ConnectionResponse`.
+/// 4. The server verifies the signature and expiry time of the token. If it is valid, the
+///    connection is created and a `ConnectionAccepted` is sent, otherwise `ConnectionDenied`.
+///
+/// Because the server only creates a connection after the client has proven that it can receive
+/// packets on its address, spoofed packets can't make the server allocate connections. Both
+/// endpoints keep the challenge of the handshake, which authenticates their disconnect packets.
+pub struct Handshaker {
+    secret: RandomState,
+    created_at: Instant,
+    resend_interval: Duration,
+    timeout: Duration,
+    max_queued_packets: usize,
+    pending: HashMap<SocketAddr, PendingHandshake>,
+}
+
+/// A handshake we started as a client and which has not been accepted yet.
+struct PendingHandshake {
+    challenge: Option<ChallengeHeader>,
+    queued_packets: Vec<Packet>,
+    started_at: Instant,
+    last_sent_at: Instant,
+}
+
+impl PendingHandshake {
+    /// The packet we should (re)send to make progress with this handshake.
+    fn next_packet(&self) -> HandshakePacket {
+        match self.challenge {
+            Some(challenge) => HandshakePacket::new(PacketType::ConnectionResponse, challenge),
+            None => HandshakePacket::new(PacketType::ConnectionRequest, ChallengeHeader::default()),
+        }
+    }
+}
+
+/// A packet that is exchanged during the connection handshake.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HandshakePacket {
+    packet_type: PacketType,
+    challenge: ChallengeHeader,
+}
+
+impl HandshakePacket {
+    /// Creates a new handshake packet of the given type.
+    pub fn new(packet_type: PacketType, challenge: ChallengeHeader) -> Self {
+        Self {
+            packet_type,
+            challenge,
+        }
+    }
+
+    /// Serializes this packet so it can be sent on the socket.
+    pub fn serialize(&self) -> io::Result<Vec<u8>> {
+        let header = StandardHeader::new(DeliveryMethod::UnreliableUnordered, self.packet_type, 0);
+        let mut buffer = Vec::with_capacity(header.size() + self.challenge.size());
+        header.write(&mut buffer)?;
+        self.challenge.write(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+impl Handshaker {
+    /// Creates a new handshaker with a fresh secret to sign challenges with.
+    pub fn new(config: &SocketConfig) -> Self {
+        Self {
+            secret: RandomState::new(),
+            created_at: Instant::now(),
+            resend_interval: config.handshake_resend_interval(),
+            timeout: config.handshake_timeout(),
+            // The packets go to the paced packets of the connection once it is accepted, which
+            // can't hold more than that either.
+            max_queued_packets: config.max_paced_packets(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Holds on to a packet for an endpoint we are not connected to yet.
+    ///
+    /// Returns the `ConnectionRequest` to send if this started a new handshake. The packet is
+    /// handed back if `max_paced_packets` packets are waiting for the handshake already.
+    pub fn queue_packet(
+        &mut self,
+        packet: Packet,
+        now: Instant,
+    ) -> Result<Option<HandshakePacket>, Packet> {
+        let address = packet.address();
+        if let Some(pending) = self.pending.get_mut(&address) {
+            if pending.queued_packets.len() >= self.max_queued_packets {
+                return Err(packet);
+            }
+            pending.queued_packets.push(packet);
+            return Ok(None);
+        }
+
+        let pending = PendingHandshake {
+            challenge: None,
+            queued_packets: vec![packet],
+            started_at: now,
+            last_sent_at: now,
+        };
+        let request = pending.next_packet();
+        self.pending.insert(address, pending);
+        Ok(Some(request))
+    }
+
+    /// Creates a challenge for the endpoint with the given address.
+    ///
+    /// Nothing is stored, the challenge can be verified using only its own contents.
+    pub fn create_challenge(&self, address: &SocketAddr, now: Instant) -> ChallengeHeader {
+        let expires_at = self.millis_since_creation(now) + self.timeout.as_millis() as u64;
+        ChallengeHeader::new(expires_at, self.sign(address, expires_at))
+    }
+
+    /// Checks whether the challenge was handed out to the given address and has not expired yet.
+    pub fn verify_challenge(
+        &self,
+        address: &SocketAddr,
+        challenge: ChallengeHeader,
+        now: Instant,
+    ) -> bool {
+        challenge.mac() == self.sign(address, challenge.expires_at())
+            && challenge.expires_at() >= self.millis_since_creation(now)
+    }
+
+    /// Handles a challenge of a server we are connecting to.
+    ///
+    /// Returns the `ConnectionResponse` to send, or `None` if we did not ask to connect.
+    pub fn handle_challenge(
+        &mut self,
+        address: &SocketAddr,
+        challenge: ChallengeHeader,
+        now: Instant,
+    ) -> Option<HandshakePacket> {
+        let pending = self.pending.get_mut(address)?;
+        pending.challenge = Some(challenge);
+        pending.last_sent_at = now;
+        Some(pending.next_packet())
+    }
+
+    /// Handles the acceptance of our handshake by a server.
+    ///
+    /// Returns the packets that were held back during the handshake, or `None` if we are not
+    /// waiting for this server or the challenge does not match the one we answered.
+    pub fn handle_accepted(
+        &mut self,
+        address: &SocketAddr,
+        challenge: ChallengeHeader,
+    ) -> Option<Vec<Packet>> {
+        if !self.is_answering(address, challenge) {
+            return None;
+        }
+
+        self.pending
+            .remove(address)
+            .map(|pending| pending.queued_packets)
+    }
+
+    /// Handles the denial of our response by a server, which happens if the challenge expired.
+    ///
+    /// Returns the `ConnectionRequest` to start over with, or `None` if we are not waiting for
+    /// this server or the challenge does not match the one we answered.
+    pub fn handle_denied(
+        &mut self,
+        address: &SocketAddr,
+        challenge: ChallengeHeader,
+        now: Instant,
+    ) -> Option<HandshakePacket> {
+        if !self.is_answering(address, challenge) {
+            return None;
+        }
+
+        let pending = self.pending.get_mut(address)?;
+        pending.challenge = None;
+        pending.last_sent_at = now;
+        Some(pending.next_packet())
+    }
+
+    /// Returns the handshake packets that have to be sent again because they were not answered
+    /// in time, together with the addresses of the handshakes that took too long and were given
+    /// up. The packets that were held back for those handshakes are returned with them, they will
+    /// never be sent.
+    #[allow(clippy::type_complexity)]
+    pub fn update(
+        &mut self,
+        now: Instant,
+    ) -> (
+        Vec<(SocketAddr, HandshakePacket)>,
+        Vec<(SocketAddr, Vec<Packet>)>,
+    ) {
+        let timeout = self.timeout;
+        let timed_out_addresses: Vec<SocketAddr> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.started_at) >= timeout)
+            .map(|(address, _)| *address)
+            .collect();
+
+        let timed_out = timed_out_addresses
+            .into_iter()
+            .map(|address| {
+                let pending = self.pending.remove(&address).unwrap();
+                (address, pending.queued_packets)
+            })
+            .collect();
+
+        let resend_interval = self.resend_interval;
+        let resends = self
+            .pending
+            .iter_mut()
+            .filter(|(_, pending)| now.duration_since(pending.last_sent_at) >= resend_interval)
+            .map(|(address, pending)| {
+                pending.last_sent_at = now;
+                (*address, pending.next_packet())
+            })
+            .collect();
+
+        (resends, timed_out)
+    }
+
+    /// Gives up every handshake that is still in progress and returns the packets that were held
+    /// back for them.
+    pub fn abort(&mut self) -> Vec<Packet> {
+        self.pending
+            .drain()
+            .flat_map(|(_, pending)| pending.queued_packets)
+            .collect()
+    }
+
+    /// Checks whether the challenge is the one we answered. Nothing matches before we answered a
+    /// challenge, so a spoofed packet can't finish or restart a handshake.
+    fn is_answering(&self, address: &SocketAddr, challenge: ChallengeHeader) -> bool {
+        self.pending
+            .get(address)
+            .is_some_and(|pending| pending.challenge == Some(challenge))
+    }
+
+    fn sign(&self, address: &SocketAddr, expires_at: u64) -> u64 {
+        let mut hasher = self.secret.build_hasher();
+        address.hash(&mut hasher);
+        expires_at.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn millis_since_creation(&self, now: Instant) -> u64 {
+        now.duration_since(self.created_at).as_millis() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HandshakePacket, Handshaker};
+    use crate::{
+        config::SocketConfig,
+        packet::{
+            headers::{ChallengeHeader, HeaderReader, StandardHeader},
+            PacketType,
+        },
+        Packet,
+    };
+    use std::{io::Cursor, net::SocketAddr, time::Instant};
+
+    fn handshaker() -> Handshaker {
+        Handshaker::new(&SocketConfig::default())
+    }
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:10000".parse().unwrap()
+    }
+
+    #[test]
+    fn completes_handshake() {
+        let mut client = handshaker();
+        let server = handshaker();
+        let now = Instant::now();
+
+        let request = client
+            .queue_packet(Packet::unreliable(address(), vec![1]), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.packet_type, PacketType::ConnectionRequest);
+        // Packets queued while the handshake is running don't start a new one.
+        assert!(client
+            .queue_packet(Packet::unreliable(address(), vec![2]), now)
+            .unwrap()
+            .is_none());
+
+        let challenge = server.create_challenge(&address(), now);
+        let response = client.handle_challenge(&address(), challenge, now).unwrap();
+        assert_eq!(
+            response,
+            HandshakePacket::new(PacketType::ConnectionResponse, challenge)
+        );
+
+        assert!(server.verify_challenge(&address(), response.challenge, now));
+
+        let packets = client.handle_accepted(&address(), challenge).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].payload(), &[1]);
+        assert_eq!(packets[1].payload(), &[2]);
+    }
+
+    #[test]
+    fn rejects_challenge_of_other_address() {
+        let server = handshaker();
+        let now = Instant::now();
+        let other_address = "127.0.0.1:10001".parse().unwrap();
+
+        let challenge = server.create_challenge(&address(), now);
+
+        assert!(!server.verify_challenge(&other_address, challenge, now));
+    }
+
+    #[test]
+    fn rejects_forged_challenge() {
+        let server = handshaker();
+        let now = Instant::now();
+
+        let challenge = server.create_challenge(&address(), now);
+        let forged = ChallengeHeader::new(challenge.expires_at() + 1000, challenge.mac());
+
+        assert!(!server.verify_challenge(&address(), forged, now));
+        assert!(!handshaker().verify_challenge(&address(), challenge, now));
+    }
+
+    #[test]
+    fn rejects_expired_challenge() {
+        let config = SocketConfig::default();
+        let server = handshaker();
+        let now = Instant::now();
+
+        let challenge = server.create_challenge(&address(), now);
+
+        assert!(!server.verify_challenge(
+            &address(),
+            challenge,
+            now + config.handshake_timeout() * 2
+        ));
+    }
+
+    #[test]
+    fn ignores_unsolicited_packets() {
+        let mut client = handshaker();
+        let now = Instant::now();
+        let challenge = handshaker().create_challenge(&address(), now);
+
+        assert!(client
+            .handle_challenge(&address(), challenge, now)
+            .is_none());
+        assert!(client.handle_accepted(&address(), challenge).is_none());
+        assert!(client.handle_denied(&address(), challenge, now).is_none());
+    }
+
+    #[test]
+    fn ignores_acceptance_of_other_challenge() {
+        let mut client = handshaker();
+        let server = handshaker();
+        let now = Instant::now();
+
+        client
+            .queue_packet(Packet::unreliable(address(), vec![1]), now)
+            .unwrap();
+        // We haven't answered a challenge yet, so nothing can be accepted.
+        assert!(client
+            .handle_accepted(&address(), ChallengeHeader::default())
+            .is_none());
+
+        let challenge = server.create_challenge(&address(), now);
+        client.handle_challenge(&address(), challenge, now);
+
+        assert!(client
+            .handle_accepted(&address(), ChallengeHeader::default())
+            .is_none());
+        assert!(client.handle_accepted(&address(), challenge).is_some());
+    }
+
+    #[test]
+    fn restarts_handshake_when_denied() {
+        let mut client = handshaker();
+        let server = handshaker();
+        let now = Instant::now();
+
+        client
+            .queue_packet(Packet::unreliable(address(), vec![1]), now)
+            .unwrap();
+        let challenge = server.create_challenge(&address(), now);
+        client.handle_challenge(&address(), challenge, now);
+
+        let request = client.handle_denied(&address(), challenge, now).unwrap();
+        assert_eq!(request.packet_type, PacketType::ConnectionRequest);
+    }
+
+    #[test]
+    fn resends_and_times_out_handshakes() {
+        let config = SocketConfig::default();
+        let mut client = handshaker();
+        let now = Instant::now();
+
+        client
+            .queue_packet(Packet::unreliable(address(), vec![1]), now)
+            .unwrap();
+
+        let (resends, timed_out) = client.update(now);
+        assert!(resends.is_empty());
+        assert!(timed_out.is_empty());
+
+        let (resends, timed_out) = client.update(now + config.handshake_resend_interval());
+        assert_eq!(resends.len(), 1);
+        assert_eq!(resends[0].1.packet_type, PacketType::ConnectionRequest);
+        assert!(timed_out.is_empty());
+
+        let (resends, timed_out) = client.update(now + config.handshake_timeout());
+        assert!(resends.is_empty());
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].0, address());
+        assert_eq!(timed_out[0].1, vec![Packet::unreliable(address(), vec![1])]);
+        assert!(client.pending.is_empty());
+    }
+
+    #[test]
+    fn aborts_handshakes() {
+        let mut client = handshaker();
+        let now = Instant::now();
+
+        client
+            .queue_packet(Packet::unreliable(address(), vec![1]), now)
+            .unwrap();
+        client
+            .queue_packet(Packet::unreliable(address(), vec![2]), now)
+            .unwrap();
+
+        assert_eq!(client.abort().len(), 2);
+        assert!(client.pending.is_empty());
+    }
+
+    #[test]
+    fn limits_packets_queued_for_handshake() {
+        let config = SocketConfig::builder()
+            .max_paced_packets(2)
+            .build()
+            .unwrap();
+        let mut client = Handshaker::new(&config);
+        let now = Instant::now();
+
+        for payload in 0..2 {
+            assert!(client
+                .queue_packet(Packet::unreliable(address(), vec![payload]), now)
+                .is_ok());
+        }
+        let packet = Packet::unreliable(address(), vec![2]);
+        assert_eq!(client.queue_packet(packet.clone(), now), Err(packet));
+        assert_eq!(client.abort().len(), 2);
+    }
+
+    #[test]
+    fn serializes_handshake_packet() {
+        let challenge = ChallengeHeader::new(1, 2);
+        let payload = HandshakePacket::new(PacketType::ConnectionChallenge, challenge)
+            .serialize()
+            .unwrap();
+
+        let mut cursor = Cursor::new(payload.as_slice());
+        let header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(header.packet_type(), PacketType::ConnectionChallenge);
+        assert_eq!(ChallengeHeader::read(&mut cursor).unwrap(), challenge);
+    }
+}
@@ -1,14 +1,58 @@
-use std::collections::HashMap;
+use crate::{
+    packet::headers::{ArrangingHeader, TransferHeader},
+    Packet,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// The retransmission timeout stops doubling after this many retries.
+const MAX_BACKOFF_EXPONENT: u8 = 6;
 
 /// Packets waiting for an ack
 ///
-/// Holds up to 32 packets waiting for ack
+/// Holds the packets waiting for ack, a packet is declared dropped once it falls outside of the
+/// ack window of the acknowledgements we get from the other side.
 ///
 /// Additionally, holds packets "forward" of the current ack packet
 #[derive(Debug, Default)]
 pub struct LocalAckRecord {
     // packets waiting for acknowledgement.
-    packets: HashMap<u16, Box<[u8]>>,
+    packets: HashMap<u16, SentPacket>,
+}
+
+/// A packet waiting for an ack, together with everything needed to send it again.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SentPacket {
+    /// The packet as it was handed to us by the application.
+    pub packet: Packet,
+    /// The position of the packet within its ordering or sequencing stream, which has to stay the
+    /// same when the packet is sent again.
+    pub arranging_header: Option<ArrangingHeader>,
+    /// The transfer and position within it of a chunk of a large message.
+    pub transfer_header: Option<TransferHeader>,
+    /// The last time this packet was sent.
+    pub sent_at: Instant,
+    /// The number of times this packet has been sent again.
+    pub retries: u8,
+}
+
+impl SentPacket {
+    /// Creates a packet that is sent for the first time at `sent_at`.
+    pub fn new(
+        packet: Packet,
+        arranging_header: Option<ArrangingHeader>,
+        sent_at: Instant,
+    ) -> Self {
+        Self {
+            packet,
+            arranging_header,
+            transfer_header: None,
+            sent_at,
+            retries: 0,
+        }
+    }
 }
 
 impl LocalAckRecord {
@@ -23,13 +67,21 @@ impl LocalAckRecord {
     }
 
     /// Adds a packet to the queue awaiting for an acknowledgement.
-    pub fn enqueue(&mut self, seq: u16, payload: &[u8]) {
-        self.packets.insert(seq, Box::from(payload));
+    pub(crate) fn enqueue(&mut self, seq: u16, packet: SentPacket) {
+        self.packets.insert(seq, packet);
     }
 
-    /// Finds and removes acked packets, returning dropped packets
-    #[allow(unused_parens)]
-    pub fn ack(&mut self, seq: u16, seq_field: u32) -> Vec<(u16, Box<[u8]>)> {
+    /// Finds and removes acked and dropped packets, returning both of them in that order
+    ///
+    /// `seq_field` describes the `window_size` packets before `seq`, any older packets we are still
+    /// waiting for are considered dropped.
+    #[allow(unused_parens, clippy::type_complexity)]
+    pub(crate) fn ack(
+        &mut self,
+        seq: u16,
+        seq_field: u128,
+        window_size: u8,
+    ) -> (Vec<(u16, SentPacket)>, Vec<(u16, SentPacket)>) {
         let mut dropped_packets = Vec::new();
         let mut acked_packets = Vec::new();
 
@@ -37,7 +89,7 @@ impl LocalAckRecord {
             let diff = seq.wrapping_sub(*key);
             if diff == 0 {
                 acked_packets.push(*key);
-            } else if diff <= 32 {
+            } else if diff <= u16::from(window_size) {
                 let field_acked = (seq_field & (1 << (diff - 1)) != 0);
                 if field_acked {
                     acked_packets.push(*key);
@@ -47,11 +99,43 @@ impl LocalAckRecord {
             }
         }
 
-        for seq_number in &acked_packets {
-            self.packets.remove(seq_number);
-        }
+        (
+            self.remove_all(acked_packets),
+            self.remove_all(dropped_packets),
+        )
+    }
+
+    /// Iterates over the packets that are waiting for an acknowledgement.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &SentPacket> {
+        self.packets.values()
+    }
+
+    /// Finds and removes the packets that have not been acknowledged within their retransmission
+    /// timeout, returning them so they can be sent again.
+    ///
+    /// The timeout starts at `retransmission_timeout` and doubles with every retry of a packet,
+    /// so we back off when the remote endpoint doesn't respond.
+    pub(crate) fn retransmissions(
+        &mut self,
+        now: Instant,
+        retransmission_timeout: Duration,
+    ) -> Vec<(u16, SentPacket)> {
+        let timed_out: Vec<u16> = self
+            .packets
+            .iter()
+            .filter(|(_, packet)| {
+                let backoff = 1 << u32::from(packet.retries.min(MAX_BACKOFF_EXPONENT));
+                now.duration_since(packet.sent_at) >= retransmission_timeout * backoff
+            })
+            .map(|(seq, _)| *seq)
+            .collect();
 
-        dropped_packets
+        self.remove_all(timed_out)
+    }
+
+    /// Removes the packets with the given sequence numbers and returns them.
+    fn remove_all(&mut self, sequences: Vec<u16>) -> Vec<(u16, SentPacket)> {
+        sequences
             .into_iter()
             .map(|seq| (seq, self.packets.remove(&seq).unwrap()))
             .collect()
@@ -60,13 +144,28 @@ impl LocalAckRecord {
 
 #[cfg(test)]
 mod test {
-    use super::LocalAckRecord;
+    use super::{LocalAckRecord, SentPacket};
+    use crate::Packet;
+    use std::time::{Duration, Instant};
+
+    fn sent_packet(sent_at: Instant) -> SentPacket {
+        let address = "127.0.0.1:10000".parse().unwrap();
+        SentPacket::new(
+            Packet::reliable_unordered(address, Vec::new()),
+            None,
+            sent_at,
+        )
+    }
+
+    fn sequences(packets: Vec<(u16, SentPacket)>) -> Vec<u16> {
+        packets.into_iter().map(|(seq, _)| seq).collect()
+    }
 
     #[test]
     fn acking_single_packet() {
         let mut record = LocalAckRecord::default();
-        record.enqueue(0, &Vec::new());
-        let dropped = record.ack(0, 0);
+        record.enqueue(0, sent_packet(Instant::now()));
+        let (_, dropped) = record.ack(0, 0, 32);
         assert_eq!(dropped.len(), 0);
         assert!(record.is_empty());
     }
@@ -74,10 +173,10 @@ mod test {
     #[test]
     fn acking_several_packets() {
         let mut record = LocalAckRecord::default();
-        record.enqueue(0, &Vec::new());
-        record.enqueue(1, &Vec::new());
-        record.enqueue(2, &Vec::new());
-        let dropped = record.ack(2, 1 | (1 << 1));
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(1, sent_packet(Instant::now()));
+        record.enqueue(2, sent_packet(Instant::now()));
+        let (_, dropped) = record.ack(2, 1 | (1 << 1), 32);
         assert_eq!(dropped.len(), 0);
         assert!(record.is_empty());
     }
@@ -87,10 +186,10 @@ mod test {
         let mut record = LocalAckRecord::default();
 
         for i in 0..33 {
-            record.enqueue(i, &Vec::new())
+            record.enqueue(i, sent_packet(Instant::now()))
         }
 
-        let dropped = record.ack(32, !0);
+        let (_, dropped) = record.ack(32, !0, 32);
 
         assert_eq!(dropped.len(), 0);
         assert!(record.is_empty());
@@ -101,12 +200,12 @@ mod test {
         let mut record = LocalAckRecord::default();
 
         for i in 0..33 {
-            record.enqueue(i, &Vec::new());
+            record.enqueue(i, sent_packet(Instant::now()));
         }
 
-        let dropped = record.ack(33, !0);
+        let (_, dropped) = record.ack(33, !0, 32);
 
-        assert_eq!(dropped, vec![(0, Vec::new().into_boxed_slice())]);
+        assert_eq!(sequences(dropped), vec![0]);
         assert!(record.is_empty());
     }
 
@@ -115,10 +214,10 @@ mod test {
         let mut record = LocalAckRecord::default();
 
         for i in 0..33_u16 {
-            record.enqueue(i.wrapping_sub(16), &Vec::new());
+            record.enqueue(i.wrapping_sub(16), sent_packet(Instant::now()));
         }
 
-        let dropped = record.ack(16, !0);
+        let (_, dropped) = record.ack(16, !0, 32);
 
         assert_eq!(dropped.len(), 0);
         assert!(record.is_empty());
@@ -127,12 +226,12 @@ mod test {
     #[test]
     fn not_dropping_new_packets() {
         let mut record = LocalAckRecord::default();
-        record.enqueue(0, &Vec::new());
-        record.enqueue(1, &Vec::new());
-        record.enqueue(2, &Vec::new());
-        record.enqueue(5, &Vec::new());
-        record.enqueue(30000, &Vec::new());
-        let dropped = record.ack(1, 1);
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(1, sent_packet(Instant::now()));
+        record.enqueue(2, sent_packet(Instant::now()));
+        record.enqueue(5, sent_packet(Instant::now()));
+        record.enqueue(30000, sent_packet(Instant::now()));
+        let (_, dropped) = record.ack(1, 1, 32);
         assert_eq!(dropped.len(), 0);
         assert_eq!(record.len(), 3);
     }
@@ -140,21 +239,105 @@ mod test {
     #[test]
     fn drops_old_packets() {
         let mut record = LocalAckRecord::default();
-        record.enqueue(0, &Vec::new());
-        record.enqueue(40, &Vec::new());
-        let dropped = record.ack(40, 0);
-        assert_eq!(dropped, vec![(0, Vec::new().into_boxed_slice())]);
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(40, sent_packet(Instant::now()));
+        let (_, dropped) = record.ack(40, 0, 32);
+        assert_eq!(sequences(dropped), vec![0]);
         assert!(record.is_empty());
     }
 
     #[test]
     fn drops_really_old_packets() {
         let mut record = LocalAckRecord::default();
-        record.enqueue(50000, &Vec::new());
-        record.enqueue(0, &Vec::new());
-        record.enqueue(1, &Vec::new());
-        let dropped = record.ack(1, 1);
-        assert_eq!(dropped, vec![(50000, Vec::new().into_boxed_slice())]);
+        record.enqueue(50000, sent_packet(Instant::now()));
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(1, sent_packet(Instant::now()));
+        let (_, dropped) = record.ack(1, 1, 32);
+        assert_eq!(sequences(dropped), vec![50000]);
+        assert!(record.is_empty());
+    }
+
+    #[test]
+    fn retransmits_after_timeout() {
+        let mut record = LocalAckRecord::default();
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        record.enqueue(0, sent_packet(now));
+
+        assert!(record.retransmissions(now, timeout).is_empty());
+
+        let retransmissions = record.retransmissions(now + timeout, timeout);
+        assert_eq!(sequences(retransmissions), vec![0]);
+        assert!(record.is_empty());
+    }
+
+    #[test]
+    fn backs_off_between_retransmissions() {
+        let mut record = LocalAckRecord::default();
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        let mut packet = sent_packet(now);
+        packet.retries = 2;
+        record.enqueue(0, packet);
+
+        assert!(record
+            .retransmissions(now + timeout * 3, timeout)
+            .is_empty());
+
+        let retransmissions = record.retransmissions(now + timeout * 4, timeout);
+        assert_eq!(sequences(retransmissions), vec![0]);
+    }
+
+    #[test]
+    fn does_not_retransmit_acked_packets() {
+        let mut record = LocalAckRecord::default();
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        record.enqueue(0, sent_packet(now));
+        record.ack(0, 0, 32);
+
+        assert!(record.retransmissions(now + timeout, timeout).is_empty());
+    }
+
+    #[test]
+    fn acking_a_wide_window_of_packets() {
+        let mut record = LocalAckRecord::default();
+
+        for i in 0..=128 {
+            record.enqueue(i, sent_packet(Instant::now()));
+        }
+
+        let (_, dropped) = record.ack(128, !0, 128);
+
+        assert_eq!(dropped.len(), 0);
         assert!(record.is_empty());
     }
+
+    #[test]
+    fn drops_packets_outside_of_the_window() {
+        let mut record = LocalAckRecord::default();
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(36, sent_packet(Instant::now()));
+
+        let (_, dropped) = record.ack(100, 0, 64);
+
+        assert_eq!(sequences(dropped), vec![0]);
+        assert_eq!(record.len(), 1);
+    }
+
+    #[test]
+    fn returns_acked_packets() {
+        let mut record = LocalAckRecord::default();
+        record.enqueue(0, sent_packet(Instant::now()));
+        record.enqueue(1, sent_packet(Instant::now()));
+        record.enqueue(2, sent_packet(Instant::now()));
+
+        let (acked, dropped) = record.ack(2, 1 << 1, 32);
+
+        let mut acked = sequences(acked);
+        acked.sort();
+        assert_eq!(acked, vec![0, 2]);
+        assert!(dropped.is_empty());
+        assert_eq!(record.iter().count(), 1);
+    }
 }
@@ -0,0 +1,261 @@
+use crate::{
+    config::SocketConfig,
+    errors::FragmentError,
+    packet::headers::FragmentHeader,
+    sequence_buffer::{ReassemblyData, SequenceBuffer, MAX_FRAGMENTS_DEFAULT},
+};
+use std::time::{Duration, Instant};
+
+/// Collects the fragments of incoming packets and reassembles them into their original payload.
+///
+/// Fragments are grouped by the sequence number of the packet they belong to. They may arrive in
+/// any order and more than once; a payload is only returned once every fragment has been received.
+/// Packets whose fragments do not all arrive within `fragment_reassembly_timeout` are discarded,
+/// either when a fragment for their slot shows up or by `remove_expired`.
+pub struct FragmentReassembler {
+    reassembly_data: SequenceBuffer<ReassemblyData>,
+    max_fragments: u8,
+    timeout: Duration,
+}
+
+impl FragmentReassembler {
+    /// Creates a new reassembler from the fragment settings in the given config.
+    pub fn new(config: &SocketConfig) -> Self {
+        Self {
+            reassembly_data: SequenceBuffer::with_capacity(
+                config.fragment_reassembly_buffer_size() as usize,
+            ),
+            max_fragments: config.max_fragments(),
+            timeout: config.fragment_reassembly_timeout(),
+        }
+    }
+
+    /// Handles a single fragment of the packet with the given sequence number.
+    ///
+    /// Returns `Ok(Some(payload))` if this fragment completed the packet and `Ok(None)` if we are
+    /// still waiting for other fragments or if this fragment was a duplicate.
+    pub fn handle_fragment(
+        &mut self,
+        sequence: u16,
+        fragment_header: FragmentHeader,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, FragmentError> {
+        let fragment_count = fragment_header.fragment_count();
+
+        if fragment_count == 0 || fragment_header.id() >= fragment_count {
+            return Err(FragmentError::InvalidFragmentHeader);
+        }
+
+        if fragment_count > self.max_fragments || u16::from(fragment_count) > MAX_FRAGMENTS_DEFAULT
+        {
+            return Err(FragmentError::ExceededMaxFragments);
+        }
+
+        // A stale entry is left over from an earlier packet with this sequence number, which says
+        // nothing about the fragments of this one.
+        let is_stale = match self.reassembly_data.get(sequence) {
+            Some(entry) if !is_expired(entry, self.timeout, now) => {
+                if entry.num_fragments_total != fragment_count {
+                    return Err(FragmentError::InvalidFragmentHeader);
+                }
+                false
+            }
+            _ => true,
+        };
+
+        let entry = if is_stale {
+            self.reassembly_data
+                .insert(ReassemblyData::new(fragment_count, now), sequence)
+        } else {
+            self.reassembly_data
+                .get_mut(sequence)
+                .expect("We just checked that this entry exists.")
+        };
+
+        if !entry.insert_fragment(fragment_header.id(), payload) || !entry.is_complete() {
+            return Ok(None);
+        }
+
+        let payload = entry.assemble();
+        self.reassembly_data.remove(sequence);
+
+        Ok(Some(payload))
+    }
+
+    /// Discards the packets whose fragments did not all arrive in time, so their fragments are
+    /// not held on to until another packet takes their slot.
+    pub fn remove_expired(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.reassembly_data
+            .retain(|entry| !is_expired(entry, timeout, now));
+    }
+}
+
+/// Checks if the fragments of a packet did not all arrive within the timeout.
+fn is_expired(entry: &ReassemblyData, timeout: Duration, now: Instant) -> bool {
+    now.duration_since(entry.created_at) > timeout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FragmentReassembler;
+    use crate::{config::SocketConfig, errors::FragmentError, packet::headers::FragmentHeader};
+    use std::time::Instant;
+
+    fn reassembler() -> FragmentReassembler {
+        FragmentReassembler::new(&SocketConfig::default())
+    }
+
+    #[test]
+    fn reassembles_fragments_in_order() {
+        let mut reassembler = reassembler();
+
+        assert_eq!(
+            reassembler
+                .handle_fragment(1, FragmentHeader::new(0, 3), &[1, 2], Instant::now())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            reassembler
+                .handle_fragment(1, FragmentHeader::new(1, 3), &[3, 4], Instant::now())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            reassembler
+                .handle_fragment(1, FragmentHeader::new(2, 3), &[5], Instant::now())
+                .unwrap(),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+    }
+
+    #[test]
+    fn reassembles_fragments_out_of_order() {
+        let mut reassembler = reassembler();
+
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(2, 3), &[5], Instant::now())
+            .unwrap();
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 3), &[1, 2], Instant::now())
+            .unwrap();
+        let payload = reassembler
+            .handle_fragment(1, FragmentHeader::new(1, 3), &[3, 4], Instant::now())
+            .unwrap();
+
+        assert_eq!(payload, Some(vec![1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn ignores_duplicate_fragments() {
+        let mut reassembler = reassembler();
+
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 2), &[1], Instant::now())
+            .unwrap();
+        let duplicate = reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 2), &[1], Instant::now())
+            .unwrap();
+        let payload = reassembler
+            .handle_fragment(1, FragmentHeader::new(1, 2), &[2], Instant::now())
+            .unwrap();
+
+        assert_eq!(duplicate, None);
+        assert_eq!(payload, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn keeps_packets_with_different_sequences_apart() {
+        let mut reassembler = reassembler();
+
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 2), &[1], Instant::now())
+            .unwrap();
+        reassembler
+            .handle_fragment(2, FragmentHeader::new(0, 2), &[3], Instant::now())
+            .unwrap();
+        let second = reassembler
+            .handle_fragment(2, FragmentHeader::new(1, 2), &[4], Instant::now())
+            .unwrap();
+        let first = reassembler
+            .handle_fragment(1, FragmentHeader::new(1, 2), &[2], Instant::now())
+            .unwrap();
+
+        assert_eq!(second, Some(vec![3, 4]));
+        assert_eq!(first, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn newer_packet_overwrites_stale_slot() {
+        let mut reassembler = reassembler();
+        let buffer_size = SocketConfig::default().fragment_reassembly_buffer_size();
+
+        reassembler
+            .handle_fragment(0, FragmentHeader::new(0, 2), &[1], Instant::now())
+            .unwrap();
+        // This sequence maps onto the same slot and replaces the incomplete packet.
+        reassembler
+            .handle_fragment(buffer_size, FragmentHeader::new(0, 2), &[3], Instant::now())
+            .unwrap();
+        let stale = reassembler
+            .handle_fragment(0, FragmentHeader::new(1, 2), &[2], Instant::now())
+            .unwrap();
+
+        assert_eq!(stale, None);
+    }
+
+    #[test]
+    fn replaces_stale_packet_with_other_fragment_count() {
+        let mut reassembler = reassembler();
+        let timeout = SocketConfig::default().fragment_reassembly_timeout();
+        let now = Instant::now();
+
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 3), &[1], now)
+            .unwrap();
+        // The sequence number came around again for a packet with fewer fragments.
+        let payload = reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 1), &[2], now + timeout * 2)
+            .unwrap();
+
+        assert_eq!(payload, Some(vec![2]));
+    }
+
+    #[test]
+    fn removes_expired_packets() {
+        let mut reassembler = reassembler();
+        let timeout = SocketConfig::default().fragment_reassembly_timeout();
+        let now = Instant::now();
+
+        reassembler
+            .handle_fragment(1, FragmentHeader::new(0, 2), &[1], now)
+            .unwrap();
+        reassembler.remove_expired(now);
+        assert!(reassembler.reassembly_data.exists(1));
+
+        reassembler.remove_expired(now + timeout * 2);
+        assert!(!reassembler.reassembly_data.exists(1));
+    }
+
+    #[test]
+    fn rejects_invalid_fragment_ids() {
+        let mut reassembler = reassembler();
+
+        match reassembler.handle_fragment(1, FragmentHeader::new(3, 3), &[1], Instant::now()) {
+            Err(FragmentError::InvalidFragmentHeader) => {}
+            _ => panic!("Expected an invalid fragment header error."),
+        }
+    }
+
+    #[test]
+    fn rejects_too_many_fragments() {
+        let mut reassembler = reassembler();
+
+        match reassembler.handle_fragment(1, FragmentHeader::new(0, 17), &[1], Instant::now()) {
+            Err(FragmentError::ExceededMaxFragments) => {}
+            _ => panic!("Expected an exceeded max fragments error."),
+        }
+    }
+}
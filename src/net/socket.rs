@@ -1,27 +1,55 @@
 use crate::{
     config::SocketConfig,
-    errors::LaminarError,
-    net::{connection::ActiveConnections, events::SocketEvent},
-    packet::Packet,
+    errors::{LaminarError, PacketError},
+    net::{
+        connection::{
+            ActiveConnections, ConnectionStats, RoundTripTime, TransferId, VirtualConnection,
+        },
+        events::SocketEvent,
+        handle::{MessageTicket, SocketCommand, SocketHandle},
+        handshake::{HandshakePacket, Handshaker},
+    },
+    packet::{
+        headers::{ChallengeHeader, HeaderReader, StandardHeader},
+        Packet, PacketType, ProcessedPacket,
+    },
+    protocol_version,
 };
+use log::error;
 use mio::{Evented, Events, Poll, PollOpt, Ready, Token};
 use std::{
-    self, io, mem,
+    self, io,
     net::{SocketAddr, ToSocketAddrs},
-    sync::mpsc,
+    sync::{
+        atomic::{AtomicU32, AtomicU64},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
 };
-use log::error;
 
 const SOCKET: Token = Token(0);
+/// How long a shutdown blocks on the socket at most before checking if packets have to be sent
+/// again.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 /// A reliable UDP socket implementation with configurable reliability and ordering guarantees.
 pub struct LaminarSocket {
     socket: mio::net::UdpSocket,
+    poll: Poll,
+    events: Events,
+    is_shut_down: bool,
     config: SocketConfig,
     connections: ActiveConnections,
+    handshaker: Handshaker,
     receive_buffer: Vec<u8>,
+    send_buffer: Vec<u8>,
     event_sender: mpsc::Sender<SocketEvent>,
+    packet_sender: mpsc::Sender<Packet>,
     packet_receiver: mpsc::Receiver<Packet>,
+    next_ticket: Arc<AtomicU64>,
+    next_transfer_id: Arc<AtomicU32>,
+    command_sender: mpsc::Sender<SocketCommand>,
+    command_receiver: mpsc::Receiver<SocketCommand>,
 }
 
 impl LaminarSocket {
@@ -34,144 +62,850 @@ impl LaminarSocket {
     ) -> io::Result<(Self, mpsc::Sender<Packet>, mpsc::Receiver<SocketEvent>)> {
         let socket = std::net::UdpSocket::bind(addresses)?;
         let socket = mio::net::UdpSocket::from_socket(socket)?;
-        Ok(Self::new(socket, config))
+        Self::new(socket, config)
     }
 
     /// Entry point to the run loop. This should run in a spawned thread since calls to `poll.poll`
     /// are blocking.
+    ///
+    /// The loop keeps running until the socket is shut down, see `shutdown`.
     pub fn start_polling(&mut self) -> io::Result<()> {
-        let poll = Poll::new()?;
+        // Nothing but a shutdown should break out of this loop!
+        while !self.is_shut_down {
+            self.poll_once(Instant::now(), self.config.socket_polling_timeout());
+        }
+        Ok(())
+    }
 
-        poll.register(self, SOCKET, Ready::readable(), PollOpt::edge())?;
+    /// Runs a single iteration of the run loop without blocking: everything that was received is
+    /// processed, queued packets and commands are handled and timers are checked against `now`.
+    ///
+    /// This can be called from a game loop instead of running `start_polling` in its own thread.
+    pub fn manual_poll(&mut self, now: Instant) {
+        self.poll_once(now, Some(Duration::from_millis(0)));
+    }
 
-        let mut events = Events::with_capacity(self.config.socket_event_buffer_size());
-        let events_ref = &mut events;
-        // Packet receiver MUST only be used in this method.
-        let packet_receiver = mem::replace(&mut self.packet_receiver, mpsc::channel().1);
-        // Nothing should break out of this loop!
-        loop {
-            self.handle_idle_clients();
-            if let Err(e) = poll.poll(events_ref, self.config.socket_polling_timeout()) {
+    /// Shuts the socket down.
+    ///
+    /// All packets that are waiting in the packet channel or for the congestion control are sent.
+    /// We keep receiving and sending reliable packets again until everything we sent is
+    /// acknowledged, or the `shutdown_timeout` config has passed, after which every connection
+    /// is gracefully disconnected (see `disconnect`). `start_polling` returns once the socket has
+    /// been shut down. Handshakes that are still in progress are given up, and so are transfers
+    /// that are not done.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.send_queued_packets();
+        self.flush_paced_packets();
+        for packet in self.handshaker.abort() {
+            self.send_lost_event(packet.ticket());
+        }
+        self.wait_for_acks(Instant::now() + self.config.shutdown_timeout());
+
+        for address in self.connections.addresses() {
+            self.disconnect(address)?;
+        }
+
+        self.is_shut_down = true;
+        Ok(())
+    }
+
+    /// Returns a `SocketHandle` which can be used to control this socket once it has been moved
+    /// into the polling thread.
+    pub fn handle(&self) -> SocketHandle {
+        SocketHandle::new(
+            self.command_sender.clone(),
+            self.packet_sender.clone(),
+            self.next_ticket.clone(),
+            self.next_transfer_id.clone(),
+        )
+    }
+
+    /// Gracefully disconnects from the remote endpoint with the given address.
+    ///
+    /// A disconnect packet is sent to the remote endpoint so it can drop the connection as well.
+    /// The connection is removed and a `SocketEvent::Disconnect` is sent to the `event_sender`
+    /// channel. Nothing happens if there is no connection with this address.
+    pub fn disconnect(&mut self, address: SocketAddr) -> io::Result<()> {
+        if let Some(mut connection) = self.remove_connection(&address) {
+            let payload = connection.create_disconnect_packet()?;
+            let result = self.socket.send_to(&payload, &address);
+            self.send_event(SocketEvent::Disconnect(address));
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Keeps receiving, acknowledging and sending reliable packets again until every packet we
+    /// sent is acknowledged, or until the deadline.
+    fn wait_for_acks(&mut self, deadline: Instant) {
+        while self.connections.has_unacked_packets() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            self.handle_retransmissions(now);
+            self.send_pending_acks(now);
+            let timeout = (deadline - now).min(SHUTDOWN_POLL_INTERVAL);
+            if let Err(e) = self.poll.poll(&mut self.events, Some(timeout)) {
                 error!("Error polling the socket: {:?}", e);
             }
-            if let Err(e) = self.process_events(events_ref) {
+            if let Err(e) = self.process_events() {
                 error!("Error processing events: {:?}", e);
             }
-            // XXX: I'm fairly certain this isn't exactly safe. I'll likely need to add some
-            // handling for when the socket is blocked on send. Worth some more research.
-            // Alternatively, I'm sure the Tokio single threaded runtime does handle this for us
-            // so maybe it's work switching to that while providing the same interface?
-            for packet in packet_receiver.try_iter() {
-                if let Err(e) = self.send_to(packet) {
-                    error!("Error sending packet: {:?}", e);
+        }
+    }
+
+    /// A single iteration of the run loop, which blocks for at most `timeout` while polling the
+    /// socket for events.
+    fn poll_once(&mut self, now: Instant, timeout: Option<Duration>) {
+        self.handle_idle_clients(now);
+        self.remove_expired_fragments(now);
+        self.handle_pending_handshakes(now);
+        self.handle_retransmissions(now);
+        self.send_heartbeats(now);
+        self.send_pending_acks(now);
+        self.send_paced_packets(now);
+        self.send_transfer_chunks(now);
+        self.send_mtu_probes(now);
+
+        // Don't sleep past the moment pending acknowledgements or paced packets have to be sent.
+        let mut timeout = timeout;
+        if self.connections.has_pending_acks() {
+            let ack_delay = self.config.ack_delay();
+            timeout = Some(timeout.map_or(ack_delay, |timeout| timeout.min(ack_delay)));
+        }
+        if let Some(next_send) = self.connections.time_until_next_send() {
+            timeout = Some(timeout.map_or(next_send, |timeout| timeout.min(next_send)));
+        }
+        if let Err(e) = self.poll.poll(&mut self.events, timeout) {
+            error!("Error polling the socket: {:?}", e);
+        }
+        if let Err(e) = self.process_events() {
+            error!("Error processing events: {:?}", e);
+        }
+        self.send_queued_packets();
+        self.send_quality_changes();
+        self.handle_commands();
+        self.send_transfer_events();
+    }
+
+    /// Sends a `SocketEvent::QualityChanged` event for every connection whose network quality
+    /// changed since the last time we checked.
+    fn send_quality_changes(&mut self) {
+        let events: Vec<SocketEvent> = self
+            .connections
+            .connections_mut()
+            .filter_map(|connection| {
+                let quality = connection.take_quality_change()?;
+                Some(SocketEvent::QualityChanged(
+                    connection.remote_address(),
+                    quality,
+                ))
+            })
+            .collect();
+        for event in events {
+            self.send_event(event);
+        }
+    }
+
+    /// Sends all of the packets that are waiting in the packet channel.
+    fn send_queued_packets(&mut self) {
+        // XXX: I'm fairly certain this isn't exactly safe. I'll likely need to add some
+        // handling for when the socket is blocked on send. Worth some more research.
+        // Alternatively, I'm sure the Tokio single threaded runtime does handle this for us
+        // so maybe it's work switching to that while providing the same interface?
+        while let Ok(packet) = self.packet_receiver.try_recv() {
+            if let Err(e) = self.send_to(packet) {
+                error!("Error sending packet: {:?}", e);
+            }
+        }
+    }
+
+    /// Executes the commands which were given through a `SocketHandle`.
+    fn handle_commands(&mut self) {
+        while let Ok(command) = self.command_receiver.try_recv() {
+            match command {
+                SocketCommand::Disconnect(address) => {
+                    if let Err(e) = self.disconnect(address) {
+                        error!("Error disconnecting from {}: {:?}", address, e);
+                    }
+                }
+                SocketCommand::StartTransfer(address, id, payload) => {
+                    self.start_transfer(address, id, payload)
+                }
+                SocketCommand::CancelTransfer(address, id) => {
+                    if let Err(e) = self.cancel_transfer(address, id) {
+                        error!("Error cancelling transfer with {}: {:?}", address, e);
+                    }
+                }
+                SocketCommand::Shutdown => {
+                    if let Err(e) = self.shutdown() {
+                        error!("Error shutting down: {:?}", e);
+                    }
+                    // Nothing should be done anymore once the socket has been shut down.
+                    break;
                 }
             }
         }
     }
 
+    /// Starts sending a large message to the remote endpoint with the given address, see
+    /// `SocketHandle::send_transfer`.
+    ///
+    /// A `SocketEvent::Connect` is sent if this creates the connection. If handshakes are enabled
+    /// and there is no connection with the remote endpoint, a `SocketEvent::TransferCancelled` is
+    /// sent instead.
+    fn start_transfer(&mut self, address: SocketAddr, id: TransferId, payload: Vec<u8>) {
+        if self.config.handshake_enabled() && !self.connections.contains(&address) {
+            self.send_event(SocketEvent::TransferCancelled(address, id));
+            return;
+        }
+
+        let (connection, is_new) = self
+            .connections
+            .get_or_insert_connection(&address, &self.config);
+        connection.start_transfer(id.id(), payload);
+        if is_new {
+            self.send_event(SocketEvent::Connect(address));
+        }
+    }
+
+    /// Cancels a transfer with the remote endpoint with the given address, see
+    /// `SocketHandle::cancel_transfer`.
+    fn cancel_transfer(&mut self, address: SocketAddr, id: TransferId) -> io::Result<()> {
+        let connection = match self.connections.get_connection_mut(&address) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+
+        if let Some(processed) = connection.cancel_transfer(id) {
+            send_processed_packet(
+                &self.socket,
+                &self.config,
+                &mut self.send_buffer,
+                connection,
+                processed,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sends a `SocketEvent` for everything that happened to the transfers of every connection
+    /// since the last time we checked.
+    fn send_transfer_events(&mut self) {
+        let events: Vec<SocketEvent> = self
+            .connections
+            .connections_mut()
+            .flat_map(|connection| connection.take_transfer_events())
+            .collect();
+        for event in events {
+            self.send_event(event);
+        }
+    }
+
     /// Iterate through all of the idle connections based on `idle_connection_timeout` config and
     /// remove them from the active connections. For each connection removed, we will send a
     /// `SocketEvent::TimeOut` event to the `event_sender` channel.
-    fn handle_idle_clients(&mut self) {
+    fn handle_idle_clients(&mut self, now: Instant) {
         let idle_addresses = self
             .connections
-            .idle_connections(self.config.idle_connection_timeout());
+            .idle_connections(self.config.idle_connection_timeout(), now);
 
         for address in idle_addresses {
-            self.connections.remove_connection(&address);
-            self.event_sender.send(SocketEvent::TimeOut(address));
+            self.remove_connection(&address);
+            self.send_event(SocketEvent::TimeOut(address));
+        }
+    }
+
+    /// Discards the fragments of packets that did not all arrive in time, for every connection.
+    fn remove_expired_fragments(&mut self, now: Instant) {
+        for connection in self.connections.connections_mut() {
+            connection.remove_expired_fragments(now);
+        }
+    }
+
+    /// Sends the reliable packets that were not acknowledged in time again and sends a
+    /// `SocketEvent::Lost` for the notified packets that were not. Connections that don't
+    /// acknowledge a packet after `max_retransmissions` attempts are removed, and for each of them
+    /// we will send a `SocketEvent::TimeOut` event to the `event_sender` channel.
+    fn handle_retransmissions(&mut self, now: Instant) {
+        let socket = &self.socket;
+        let config = &self.config;
+        let send_buffer = &mut self.send_buffer;
+        let mut lost_tickets = Vec::new();
+        let mut broken_addresses = Vec::new();
+        for connection in self.connections.connections_mut() {
+            let is_broken = connection.queue_retransmissions(now);
+            lost_tickets.extend(connection.take_lost_tickets());
+
+            if is_broken {
+                broken_addresses.push(connection.remote_address());
+                continue;
+            }
+
+            for processed in connection.process_dropped_packets(now) {
+                if let Err(e) =
+                    send_processed_packet(socket, config, send_buffer, connection, processed)
+                {
+                    error!("Error resending packet: {:?}", e);
+                }
+            }
+        }
+
+        for ticket in lost_tickets {
+            self.send_event(SocketEvent::Lost(ticket));
+        }
+        for address in broken_addresses {
+            self.remove_connection(&address);
+            self.send_event(SocketEvent::TimeOut(address));
+        }
+    }
+
+    /// Sends a heartbeat to every connection we haven't sent anything to within the
+    /// `heartbeat_interval` config.
+    fn send_heartbeats(&mut self, now: Instant) {
+        let heartbeat_interval = match self.config.heartbeat_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let socket = &self.socket;
+        for connection in self
+            .connections
+            .heartbeat_required_connections(heartbeat_interval, now)
+        {
+            let result = connection
+                .create_heartbeat_packet()
+                .and_then(|payload| send_datagram(socket, connection, &payload));
+            if let Err(e) = result {
+                error!("Error sending heartbeat: {:?}", e);
+            }
+        }
+    }
+
+    /// Sends an ack packet to every connection whose acknowledgements have been waiting for an
+    /// outgoing packet for longer than the `ack_delay` config.
+    fn send_pending_acks(&mut self, now: Instant) {
+        let socket = &self.socket;
+        for connection in self
+            .connections
+            .ack_required_connections(self.config.ack_delay(), now)
+        {
+            let result = connection
+                .create_ack_packet()
+                .and_then(|payload| send_datagram(socket, connection, &payload));
+            if let Err(e) = result {
+                error!("Error sending ack packet: {:?}", e);
+            }
+        }
+    }
+
+    /// Sends a probe to every connection whose path MTU discovery is due for one, see
+    /// `MtuDiscovery`.
+    fn send_mtu_probes(&mut self, now: Instant) {
+        let socket = &self.socket;
+        for connection in self.connections.connections_mut() {
+            let result = connection
+                .create_mtu_probe(now)
+                .and_then(|probe| match probe {
+                    Some(payload) => send_datagram(socket, connection, &payload),
+                    None => Ok(0),
+                });
+            if let Err(e) = result {
+                error!("Error sending MTU probe: {:?}", e);
+            }
+        }
+    }
+
+    /// Sends the packets that were held back by the congestion control of their connection, as far
+    /// as the current send rate allows.
+    fn send_paced_packets(&mut self, now: Instant) {
+        let socket = &self.socket;
+        let config = &self.config;
+        let send_buffer = &mut self.send_buffer;
+        for connection in self.connections.connections_mut() {
+            if !connection.has_paced_packets() {
+                continue;
+            }
+
+            for processed in connection.process_paced_packets(now) {
+                if let Err(e) =
+                    send_processed_packet(socket, config, send_buffer, connection, processed)
+                {
+                    error!("Error sending packet: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Sends the chunks of the transfers of every connection, as far as the windows of the
+    /// transfers and the current send rate allow.
+    fn send_transfer_chunks(&mut self, now: Instant) {
+        let socket = &self.socket;
+        let config = &self.config;
+        let send_buffer = &mut self.send_buffer;
+        for connection in self.connections.connections_mut() {
+            for processed in connection.process_transfer_chunks(now) {
+                if let Err(e) =
+                    send_processed_packet(socket, config, send_buffer, connection, processed)
+                {
+                    error!("Error sending transfer chunk: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Sends all of the packets that were held back by the congestion control of their connection,
+    /// regardless of the current send rate.
+    fn flush_paced_packets(&mut self) {
+        let socket = &self.socket;
+        let config = &self.config;
+        let send_buffer = &mut self.send_buffer;
+        for connection in self.connections.connections_mut() {
+            for processed in connection.flush_paced_packets() {
+                if let Err(e) =
+                    send_processed_packet(socket, config, send_buffer, connection, processed)
+                {
+                    error!("Error sending packet: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Sends the handshake packets that were not answered in time again. For each handshake that
+    /// took too long, we will send a `SocketEvent::TimeOut` event to the `event_sender` channel.
+    fn handle_pending_handshakes(&mut self, now: Instant) {
+        let (resends, timed_out) = self.handshaker.update(now);
+
+        for (address, packet) in resends {
+            if let Err(e) = self.send_handshake_packet(address, packet) {
+                error!("Error sending handshake packet: {:?}", e);
+            }
+        }
+
+        for (address, packets) in timed_out {
+            for packet in packets {
+                self.send_lost_event(packet.ticket());
+            }
+            self.send_event(SocketEvent::TimeOut(address));
+        }
+    }
+
+    /// Removes the connection with the given address. For each packet that was sent with a
+    /// ticket and is still waiting for an acknowledgement, we will send a `SocketEvent::Lost`
+    /// event to the `event_sender` channel, and for each transfer that isn't done a
+    /// `SocketEvent::TransferCancelled`.
+    fn remove_connection(&mut self, address: &SocketAddr) -> Option<VirtualConnection> {
+        let (_, mut connection) = self.connections.remove_connection(address)?;
+        for ticket in connection.unacked_tickets() {
+            self.send_event(SocketEvent::Lost(ticket));
+        }
+        for event in connection.take_transfer_events() {
+            self.send_event(event);
+        }
+        for id in connection.transfer_ids() {
+            self.send_event(SocketEvent::TransferCancelled(*address, id));
+        }
+        Some(connection)
+    }
+
+    /// Sends a `SocketEvent::Lost` event for the ticket of a packet that will never be sent, if it
+    /// has one.
+    fn send_lost_event(&self, ticket: Option<MessageTicket>) {
+        if let Some(ticket) = ticket {
+            self.send_event(SocketEvent::Lost(ticket));
+        }
+    }
+
+    /// Pushes a `SocketEvent` to the `event_sender` channel.
+    fn send_event(&self, event: SocketEvent) {
+        if let Err(e) = self.event_sender.send(event) {
+            error!("Error sending socket event: {:?}", e);
         }
     }
 
     /// Process events received from the mio socket.
-    fn process_events(&mut self, events: &mut Events) -> io::Result<()> {
-        for event in events.iter() {
+    fn process_events(&mut self) -> io::Result<()> {
+        let mut is_readable = false;
+        for event in self.events.iter() {
             match event.token() {
-                SOCKET => {
-                    if event.readiness().is_readable() {
-                        loop {
-                            match self.receive_from() {
-                                Ok(Some(packet)) => {
-                                    self.event_sender.send(SocketEvent::Packet(packet));
-                                }
-                                Ok(None) => continue,
-                                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
-                                Err(e) => error!("{:?}", e)
-                            };
+                SOCKET => is_readable |= event.readiness().is_readable(),
+                _ => unreachable!(),
+            }
+        }
+
+        if is_readable {
+            loop {
+                match self.receive_from() {
+                    Ok(packets) => {
+                        for packet in packets {
+                            self.send_event(SocketEvent::Packet(packet));
                         }
                     }
-                }
-                _ => unreachable!(),
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => error!("{:?}", e),
+                };
             }
         }
         Ok(())
     }
 
+    /// Get the number of packets per second we currently send at most to the remote endpoint with
+    /// the given address, or `None` if there is no connection with this address.
+    ///
+    /// The send rate goes down when the connection suffers from a high round trip time or packet
+    /// loss, see `SocketConfig::bad_send_rate`.
+    pub fn send_rate(&self, address: &SocketAddr) -> Option<u32> {
+        self.connections
+            .get_connection(address)
+            .map(|connection| connection.send_rate())
+    }
+
+    /// Get the round trip time measurements of the connection with the given address, which can be
+    /// used to show its ping and jitter. Returns `None` if there is no connection with this address
+    /// or none of the packets we sent to it have been acknowledged yet.
+    pub fn round_trip_time(&self, address: &SocketAddr) -> Option<RoundTripTime> {
+        self.connections
+            .get_connection(address)
+            .and_then(|connection| connection.round_trip_time())
+    }
+
+    /// Get the traffic statistics of the connection with the given address, which can be used to
+    /// monitor the health of the connection. Returns `None` if there is no connection with this
+    /// address.
+    pub fn connection_stats(&self, address: &SocketAddr) -> Option<ConnectionStats> {
+        self.connections
+            .get_connection(address)
+            .map(|connection| connection.stats(Instant::now()))
+    }
+
+    /// Get the size of the largest datagram we currently send to the connection with the given
+    /// address, or `None` if there is no connection with this address.
+    ///
+    /// This starts out at `SocketConfig::fragment_size_bytes` plus the headers and is adjusted
+    /// to the path to the connection if `SocketConfig::mtu_discovery_enabled` is set, which
+    /// changes the size of the fragments packets are split into.
+    pub fn mtu(&self, address: &SocketAddr) -> Option<u16> {
+        self.connections
+            .get_connection(address)
+            .map(|connection| connection.mtu())
+    }
+
     /// Returns the socket address that this socket was created from.
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.socket.local_addr()
     }
 
     /// Serializes and sends a `Packet` on the socket. On success, returns the number of bytes written.
+    ///
+    /// A `SocketEvent::Connect` is sent if this is the first packet for the remote endpoint. If
+    /// handshakes are enabled, packets for unknown endpoints are held back until the handshake
+    /// with that endpoint is done. Packets are held back as well when the send rate of the
+    /// connection has been used up, those are sent by `send_paced_packets`. A packet that is
+    /// refused is reported with a `SocketEvent::Lost` if it has a ticket.
     fn send_to(&mut self, packet: Packet) -> io::Result<usize> {
+        let address = packet.address();
+        if self.config.handshake_enabled() && !self.connections.contains(&address) {
+            return match self.handshaker.queue_packet(packet, Instant::now()) {
+                Ok(Some(request)) => self.send_handshake_packet(address, request),
+                Ok(None) => Ok(0),
+                Err(packet) => {
+                    self.send_lost_event(packet.ticket());
+                    Err(PacketError::SendQueueFull.into())
+                }
+            };
+        }
+
+        let (_, is_new) = self
+            .connections
+            .get_or_insert_connection(&address, &self.config);
+        if is_new {
+            self.send_event(SocketEvent::Connect(address));
+        }
         let connection = self
             .connections
-            .get_or_insert_connection(&packet.address(), &self.config);
-        let mut processed = connection.process_outgoing(packet)?;
+            .get_connection_mut(&address)
+            .expect("The connection exists, we just made sure of it.");
+
         let mut bytes_written = 0;
 
-        // TODO: Is this where we want to send dropped packets?
+        // Packets that were dropped on the way are sent again first, since they are older.
         if connection.has_dropped_packets() {
-            for payload in connection.drain_dropped_packets() {
-                bytes_written += self.socket.send_to(&payload, &processed.address())?;
+            for processed in connection.process_dropped_packets(Instant::now()) {
+                bytes_written += send_processed_packet(
+                    &self.socket,
+                    &self.config,
+                    &mut self.send_buffer,
+                    connection,
+                    processed,
+                )?;
             }
         }
 
-        let address = processed.address();
-        for fragment in processed.fragments(
-            self.config.fragment_size_bytes(),
-            self.config.max_fragments(),
-        )? {
-            bytes_written += self.socket.send_to(fragment, &address)?;
+        // Packets that are refused never get acknowledged, so their tickets are lost right away.
+        let ticket = packet.ticket();
+        if let Err(e) = connection.queue_outgoing(packet) {
+            self.send_lost_event(ticket);
+            return Err(e);
+        }
+        for processed in connection.process_paced_packets(Instant::now()) {
+            bytes_written += send_processed_packet(
+                &self.socket,
+                &self.config,
+                &mut self.send_buffer,
+                connection,
+                processed,
+            )?;
         }
 
         Ok(bytes_written)
     }
 
-    /// Receives a single message from the socket. On success, returns the packet containing origin and data.
-    fn receive_from(&mut self) -> io::Result<Option<Packet>> {
+    /// Receives a single message from the socket. On success, returns the packets containing
+    /// origin and data that are ready to be handed to the application.
+    ///
+    /// A `SocketEvent::Connect` is sent if this is the first message from the remote endpoint. A
+    /// disconnect packet removes the connection and sends a `SocketEvent::Disconnect` instead. If
+    /// the connection was created by a handshake, disconnect packets that don't carry its
+    /// challenge are ignored, so others can't disconnect the remote endpoint by spoofing its
+    /// address.
+    ///
+    /// If handshakes are enabled, messages from endpoints that did not complete the handshake are
+    /// dropped.
+    fn receive_from(&mut self) -> io::Result<Vec<Packet>> {
         let (recv_len, address) = self.socket.recv_from(&mut self.receive_buffer)?;
-        if recv_len <= 0 {
+        if recv_len == 0 {
             return Err(LaminarError::ReceivedDataTooShort.into());
         }
 
         let received_payload = &self.receive_buffer[..recv_len];
+        let mut cursor = io::Cursor::new(received_payload);
+        let header = StandardHeader::read(&mut cursor)?;
+        if !protocol_version::valid_version(header.protocol_version()) {
+            return Err(LaminarError::ProtocolVersionMismatch.into());
+        }
+
+        if header.packet_type().is_handshake() {
+            let challenge = ChallengeHeader::read(&mut cursor)?;
+            self.handle_handshake_packet(address, header.packet_type(), challenge)?;
+            return Ok(Vec::new());
+        }
+
+        if header.packet_type() == PacketType::Disconnect {
+            let is_authentic = match self.connections.get_connection(&address) {
+                Some(connection) => connection.is_authentic_disconnect(&mut cursor),
+                None => false,
+            };
+            if is_authentic && self.remove_connection(&address).is_some() {
+                self.send_event(SocketEvent::Disconnect(address));
+            }
+            return Ok(Vec::new());
+        }
+
+        if self.config.handshake_enabled() && !self.connections.contains(&address) {
+            return Ok(Vec::new());
+        }
+
+        let (_, is_new) = self
+            .connections
+            .get_or_insert_connection(&address, &self.config);
+        if is_new {
+            self.send_event(SocketEvent::Connect(address));
+        }
         let connection = self
+            .connections
+            .get_connection_mut(&address)
+            .expect("The connection exists, we just made sure of it.");
+
+        let packets = connection.process_incoming(received_payload);
+        // Failing to answer a probe only keeps the other side from using a larger MTU, it shouldn't
+        // keep us from handling what we received.
+        match connection.create_mtu_probe_replies() {
+            Ok(replies) => {
+                for reply in replies {
+                    if let Err(e) = send_datagram(&self.socket, connection, &reply) {
+                        error!("Error sending MTU probe reply: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => error!("Error creating MTU probe replies: {:?}", e),
+        }
+        let acked = connection
+            .take_acked_tickets()
+            .into_iter()
+            .map(SocketEvent::Acked);
+        let lost = connection
+            .take_lost_tickets()
+            .into_iter()
+            .map(SocketEvent::Lost);
+        let events: Vec<SocketEvent> = acked.chain(lost).collect();
+        for event in events {
+            self.send_event(event);
+        }
+        packets
+    }
+
+    /// Handles a packet of the connection handshake. See `Handshaker` for how the handshake works.
+    fn handle_handshake_packet(
+        &mut self,
+        address: SocketAddr,
+        packet_type: PacketType,
+        challenge: ChallengeHeader,
+    ) -> io::Result<()> {
+        let now = Instant::now();
+        match packet_type {
+            PacketType::ConnectionRequest => {
+                if self.config.handshake_enabled() {
+                    let challenge = self.handshaker.create_challenge(&address, now);
+                    let packet = HandshakePacket::new(PacketType::ConnectionChallenge, challenge);
+                    self.send_handshake_packet(address, packet)?;
+                } else {
+                    self.accept_connection(address, challenge)?;
+                }
+            }
+            PacketType::ConnectionResponse => {
+                if self.handshaker.verify_challenge(&address, challenge, now) {
+                    self.accept_connection(address, challenge)?;
+                    if let Some(connection) = self.connections.get_connection_mut(&address) {
+                        connection.complete_handshake(challenge, now);
+                    }
+                } else {
+                    let packet = HandshakePacket::new(PacketType::ConnectionDenied, challenge);
+                    self.send_handshake_packet(address, packet)?;
+                }
+            }
+            PacketType::ConnectionChallenge => {
+                if let Some(response) = self.handshaker.handle_challenge(&address, challenge, now) {
+                    self.send_handshake_packet(address, response)?;
+                }
+            }
+            PacketType::ConnectionAccepted => {
+                if let Some(packets) = self.handshaker.handle_accepted(&address, challenge) {
+                    let (connection, is_new) = self
+                        .connections
+                        .get_or_insert_connection(&address, &self.config);
+                    connection.complete_handshake(challenge, now);
+                    if is_new {
+                        self.send_event(SocketEvent::Connect(address));
+                    }
+                    for packet in packets {
+                        if let Err(e) = self.send_to(packet) {
+                            error!("Error sending packet: {:?}", e);
+                        }
+                    }
+                }
+            }
+            PacketType::ConnectionDenied => {
+                if let Some(request) = self.handshaker.handle_denied(&address, challenge, now) {
+                    self.send_handshake_packet(address, request)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Creates the connection for an endpoint that completed the handshake, if it doesn't exist
+    /// yet, and lets the endpoint know it is connected.
+    fn accept_connection(
+        &mut self,
+        address: SocketAddr,
+        challenge: ChallengeHeader,
+    ) -> io::Result<()> {
+        let (_, is_new) = self
             .connections
             .get_or_insert_connection(&address, &self.config);
-        connection.process_incoming(received_payload)
+        if is_new {
+            self.send_event(SocketEvent::Connect(address));
+        }
+
+        let packet = HandshakePacket::new(PacketType::ConnectionAccepted, challenge);
+        self.send_handshake_packet(address, packet)?;
+        Ok(())
+    }
+
+    /// Serializes and sends a handshake packet on the socket.
+    fn send_handshake_packet(
+        &mut self,
+        address: SocketAddr,
+        packet: HandshakePacket,
+    ) -> io::Result<usize> {
+        self.socket.send_to(&packet.serialize()?, &address)
     }
 
     fn new(
         socket: mio::net::UdpSocket,
         config: SocketConfig,
-    ) -> (Self, mpsc::Sender<Packet>, mpsc::Receiver<SocketEvent>) {
+    ) -> io::Result<(Self, mpsc::Sender<Packet>, mpsc::Receiver<SocketEvent>)> {
+        let poll = Poll::new()?;
+        poll.register(&socket, SOCKET, Ready::readable(), PollOpt::edge())?;
+        let events = Events::with_capacity(config.socket_event_buffer_size());
+
         let (event_sender, event_receiver) = mpsc::channel();
         let (packet_sender, packet_receiver) = mpsc::channel();
+        let (command_sender, command_receiver) = mpsc::channel();
         let buffer_size = config.receive_buffer_size_bytes();
-        (
+        let send_buffer_size = config.max_mtu() as usize;
+        let handshaker = Handshaker::new(&config);
+        let connections = ActiveConnections::new(&config);
+        Ok((
             Self {
                 socket,
+                poll,
+                events,
+                is_shut_down: false,
                 config,
-                connections: ActiveConnections::new(),
+                connections,
+                handshaker,
                 receive_buffer: vec![0; buffer_size],
+                send_buffer: Vec::with_capacity(send_buffer_size),
                 event_sender,
+                packet_sender: packet_sender.clone(),
                 packet_receiver,
+                next_ticket: Arc::new(AtomicU64::new(0)),
+                next_transfer_id: Arc::new(AtomicU32::new(0)),
+                command_sender,
+                command_receiver,
             },
             packet_sender,
             event_receiver,
-        )
+        ))
+    }
+}
+
+/// Sends every fragment of a processed packet on the socket. Each fragment is written into
+/// `send_buffer` right before it is sent, so sending doesn't allocate once the buffer has grown to
+/// the size of a datagram. On success, returns the number of bytes written.
+fn send_processed_packet(
+    socket: &mio::net::UdpSocket,
+    config: &SocketConfig,
+    send_buffer: &mut Vec<u8>,
+    connection: &mut VirtualConnection,
+    processed: ProcessedPacket,
+) -> io::Result<usize> {
+    let address = processed.address();
+    let fragment_size =
+        processed.fit_fragment_size(connection.fragment_size(), config.max_fragments());
+    let mut bytes_written = 0;
+    for fragment_id in 0..processed.fragment_count(fragment_size, config.max_fragments())? {
+        processed.write_fragment(fragment_id, fragment_size, send_buffer)?;
+        let fragment_bytes = socket.send_to(send_buffer, &address)?;
+        connection.record_sent(fragment_bytes, Instant::now());
+        bytes_written += fragment_bytes;
     }
+    Ok(bytes_written)
+}
+
+/// Sends a single datagram to a connection and records it in the statistics of the connection.
+fn send_datagram(
+    socket: &mio::net::UdpSocket,
+    connection: &mut VirtualConnection,
+    payload: &[u8],
+) -> io::Result<usize> {
+    let bytes_written = socket.send_to(payload, &connection.remote_address())?;
+    connection.record_sent(bytes_written, Instant::now());
+    Ok(bytes_written)
 }
 
 impl Evented for LaminarSocket {
@@ -199,3 +933,469 @@ impl Evented for LaminarSocket {
         self.socket.deregister(poll)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LaminarSocket;
+    use crate::{
+        config::SocketConfig,
+        net::{connection::VirtualConnection, handshake::HandshakePacket, SocketEvent},
+        packet::{
+            headers::{ChallengeHeader, HeaderReader, StandardHeader},
+            BufferPool, PacketType,
+        },
+        Packet,
+    };
+    use std::{
+        io::Cursor,
+        net::UdpSocket,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn start_socket() -> (
+        LaminarSocket,
+        mpsc::Sender<Packet>,
+        mpsc::Receiver<SocketEvent>,
+    ) {
+        LaminarSocket::bind("127.0.0.1:0", SocketConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn emits_connect_and_disconnect_events() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, client_packets, client_events) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+        let client_handle = client.handle();
+        thread::spawn(move || server.start_polling());
+        thread::spawn(move || client.start_polling());
+
+        client_packets
+            .send(Packet::unreliable(server_address, vec![1, 2, 3]))
+            .unwrap();
+
+        match client_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Connect(address) => assert_eq!(address, server_address),
+            event => panic!("Expected a connect event, got {:?}", event),
+        }
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Connect(address) => assert_eq!(address, client_address),
+            event => panic!("Expected a connect event, got {:?}", event),
+        }
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Packet(packet) => assert_eq!(packet.payload(), &[1, 2, 3]),
+            event => panic!("Expected a packet event, got {:?}", event),
+        }
+
+        client_handle.disconnect(server_address).unwrap();
+
+        match client_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Disconnect(address) => assert_eq!(address, server_address),
+            event => panic!("Expected a disconnect event, got {:?}", event),
+        }
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Disconnect(address) => assert_eq!(address, client_address),
+            event => panic!("Expected a disconnect event, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn shutdown_flushes_packets_and_disconnects() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, client_packets, _) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+        let client_handle = client.handle();
+        thread::spawn(move || server.start_polling());
+        let client_thread = thread::spawn(move || client.start_polling());
+
+        client_packets
+            .send(Packet::unreliable(server_address, vec![1]))
+            .unwrap();
+        client_handle.shutdown().unwrap();
+        client_thread.join().unwrap().unwrap();
+
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Connect(address) => assert_eq!(address, client_address),
+            event => panic!("Expected a connect event, got {:?}", event),
+        }
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Packet(packet) => assert_eq!(packet.payload(), &[1]),
+            event => panic!("Expected a packet event, got {:?}", event),
+        }
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Disconnect(address) => assert_eq!(address, client_address),
+            event => panic!("Expected a disconnect event, got {:?}", event),
+        }
+        assert!(client_handle.shutdown().is_err());
+    }
+
+    #[test]
+    fn shutdown_waits_for_reliable_packets_to_be_acked() {
+        let (mut server, _, server_events) = start_socket();
+        let config = SocketConfig::builder()
+            .shutdown_timeout(TIMEOUT)
+            .build()
+            .unwrap();
+        let (mut client, client_packets, _) = LaminarSocket::bind("127.0.0.1:0", config).unwrap();
+        let server_address = server.local_addr().unwrap();
+
+        client_packets
+            .send(Packet::reliable_unordered(server_address, vec![1]))
+            .unwrap();
+        client.manual_poll(Instant::now());
+
+        // The server only answers after a while, which the shutdown has to wait for.
+        let delay = Duration::from_millis(200);
+        thread::spawn(move || {
+            thread::sleep(delay);
+            server.start_polling()
+        });
+        let started_at = Instant::now();
+        client.shutdown().unwrap();
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= delay);
+        assert!(elapsed < TIMEOUT);
+
+        assert!(matches!(
+            server_events.recv_timeout(TIMEOUT),
+            Ok(SocketEvent::Connect(_))
+        ));
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Packet(packet) => assert_eq!(packet.payload(), &[1]),
+            event => panic!("Expected a packet event, got {:?}", event),
+        }
+        assert!(matches!(
+            server_events.recv_timeout(TIMEOUT),
+            Ok(SocketEvent::Disconnect(_))
+        ));
+    }
+
+    #[test]
+    fn retransmits_unacked_reliable_packet_with_headers() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let peer_address = peer.local_addr().unwrap();
+        let (mut client, client_packets, _) = start_socket();
+        let client_address = client.local_addr().unwrap();
+
+        client_packets
+            .send(Packet::reliable_unordered(peer_address, vec![1, 2, 3]))
+            .unwrap();
+        let now = Instant::now();
+        client.manual_poll(now);
+
+        // The peer never acknowledges the packet, as if it got lost.
+        let mut buffer = [0; 1500];
+        peer.recv(&mut buffer).unwrap();
+
+        // The packet that is sent again can be read like any other.
+        client.manual_poll(now + Duration::from_secs(1));
+        let length = peer.recv(&mut buffer).unwrap();
+        let config = SocketConfig::default();
+        let buffer_pool = BufferPool::new(
+            config.payload_pool_size(),
+            config.receive_buffer_size_bytes(),
+        );
+        let mut connection = VirtualConnection::new(client_address, &config, buffer_pool);
+        let packets = connection.process_incoming(&buffer[..length]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn manual_poll_does_not_block() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, client_packets, _) = start_socket();
+        let server_address = server.local_addr().unwrap();
+
+        client_packets
+            .send(Packet::unreliable(server_address, vec![1]))
+            .unwrap();
+        client.manual_poll(Instant::now());
+
+        let started_at = Instant::now();
+        while started_at.elapsed() < TIMEOUT {
+            server.manual_poll(Instant::now());
+            if let Ok(SocketEvent::Packet(packet)) = server_events.try_recv() {
+                assert_eq!(packet.payload(), &[1]);
+                return;
+            }
+        }
+        panic!("The server did not receive the packet.");
+    }
+
+    #[test]
+    fn records_connection_stats() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, client_packets, _) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+        assert_eq!(client.connection_stats(&server_address), None);
+
+        client_packets
+            .send(Packet::unreliable(server_address, vec![1, 2, 3]))
+            .unwrap();
+        client.manual_poll(Instant::now());
+
+        let started_at = Instant::now();
+        while started_at.elapsed() < TIMEOUT {
+            server.manual_poll(Instant::now());
+            if let Ok(SocketEvent::Packet(_)) = server_events.try_recv() {
+                let sent = client.connection_stats(&server_address).unwrap();
+                let received = server.connection_stats(&client_address).unwrap();
+                assert_eq!(sent.packets_sent(), 1);
+                assert_eq!(received.packets_received(), 1);
+                assert_eq!(sent.bytes_sent(), received.bytes_received());
+                assert!(received.receive_bandwidth() > 0.0);
+                return;
+            }
+        }
+        panic!("The server did not receive the packet.");
+    }
+
+    #[test]
+    fn emits_acked_event_for_ticket() {
+        let (mut server, _, _) = start_socket();
+        let (mut client, _, client_events) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_handle = client.handle();
+        thread::spawn(move || server.start_polling());
+        thread::spawn(move || client.start_polling());
+
+        let ticket = client_handle
+            .send(Packet::reliable_unordered(server_address, vec![1]))
+            .unwrap();
+
+        // The server acknowledges the packet with its next heartbeat.
+        loop {
+            match client_events.recv_timeout(TIMEOUT).unwrap() {
+                SocketEvent::Acked(acked) => {
+                    assert_eq!(acked, ticket);
+                    break;
+                }
+                SocketEvent::Connect(_) => {}
+                event => panic!("Expected an acked event, got {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn emits_lost_event_for_unacked_ticket() {
+        let (server, _, _) = start_socket();
+        let (mut client, _, client_events) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_handle = client.handle();
+
+        let ticket = client_handle
+            .send(Packet::reliable_unordered(server_address, vec![1]))
+            .unwrap();
+        client.manual_poll(Instant::now());
+        client.disconnect(server_address).unwrap();
+
+        let events: Vec<SocketEvent> = client_events.try_iter().collect();
+        match events.as_slice() {
+            [SocketEvent::Connect(_), SocketEvent::Lost(lost), SocketEvent::Disconnect(_)] => {
+                assert_eq!(*lost, ticket)
+            }
+            events => panic!("Expected a lost event, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn emits_lost_event_for_packet_refused_by_full_queue() {
+        let config = SocketConfig::builder()
+            .good_send_rate(1)
+            .bad_send_rate(1)
+            .max_paced_packets(1)
+            .build()
+            .unwrap();
+        let (server, _, _) = start_socket();
+        let (mut client, _, client_events) = LaminarSocket::bind("127.0.0.1:0", config).unwrap();
+        let server_address = server.local_addr().unwrap();
+        let client_handle = client.handle();
+
+        // The first packet uses up the send rate and the second one fills the queue.
+        let tickets: Vec<_> = (0..3)
+            .map(|i| {
+                client_handle
+                    .send(Packet::reliable_unordered(server_address, vec![i]))
+                    .unwrap()
+            })
+            .collect();
+        client.manual_poll(Instant::now());
+
+        let events: Vec<SocketEvent> = client_events.try_iter().collect();
+        match events.as_slice() {
+            [SocketEvent::Connect(_), SocketEvent::Lost(lost)] => assert_eq!(*lost, tickets[2]),
+            events => panic!("Expected a lost event, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn transfers_message_larger_than_a_packet() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, _, client_events) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+        let client_handle = client.handle();
+        thread::spawn(move || server.start_polling());
+        thread::spawn(move || client.start_polling());
+
+        let payload: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        assert!(payload.len() > SocketConfig::default().max_packet_size_bytes());
+        let id = client_handle
+            .send_transfer(server_address, payload.clone())
+            .unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            match server_events.recv_timeout(TIMEOUT).unwrap() {
+                SocketEvent::TransferData(address, _, data) => {
+                    assert_eq!(address, client_address);
+                    received.extend(data);
+                }
+                SocketEvent::TransferProgress(_, _, progress) => {
+                    assert_eq!(progress.transferred_bytes(), received.len() as u64);
+                    assert_eq!(progress.total_bytes(), payload.len() as u64);
+                }
+                SocketEvent::TransferCompleted(_, incoming) => {
+                    assert_eq!(incoming.id(), id.id());
+                    assert!(!incoming.is_outgoing());
+                    break;
+                }
+                SocketEvent::Connect(_) => {}
+                event => panic!("Expected a transfer event, got {:?}", event),
+            }
+        }
+        assert_eq!(received, payload);
+
+        loop {
+            match client_events.recv_timeout(TIMEOUT).unwrap() {
+                SocketEvent::TransferCompleted(address, completed) => {
+                    assert_eq!(address, server_address);
+                    assert_eq!(completed, id);
+                    break;
+                }
+                SocketEvent::Connect(_) | SocketEvent::TransferProgress(..) => {}
+                event => panic!("Expected a completed event, got {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn receiver_cancels_transfer() {
+        let (mut server, _, server_events) = start_socket();
+        let (mut client, _, client_events) = start_socket();
+        let server_address = server.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+        let server_handle = server.handle();
+        let client_handle = client.handle();
+        thread::spawn(move || server.start_polling());
+        thread::spawn(move || client.start_polling());
+
+        let id = client_handle
+            .send_transfer(server_address, vec![0; 1_000_000])
+            .unwrap();
+
+        loop {
+            match server_events.recv_timeout(TIMEOUT).unwrap() {
+                SocketEvent::TransferData(_, incoming, _) => {
+                    server_handle
+                        .cancel_transfer(client_address, incoming)
+                        .unwrap();
+                }
+                SocketEvent::TransferCancelled(_, incoming) => {
+                    assert_eq!(incoming.id(), id.id());
+                    break;
+                }
+                SocketEvent::Connect(_) | SocketEvent::TransferProgress(..) => {}
+                event => panic!("Expected a cancelled event, got {:?}", event),
+            }
+        }
+
+        loop {
+            match client_events.recv_timeout(TIMEOUT).unwrap() {
+                SocketEvent::TransferCancelled(_, cancelled) => {
+                    assert_eq!(cancelled, id);
+                    break;
+                }
+                SocketEvent::Connect(_) | SocketEvent::TransferProgress(..) => {}
+                event => panic!("Expected a cancelled event, got {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_forged_disconnect_of_handshaked_peer() {
+        let config = SocketConfig::builder()
+            .handshake_enabled(true)
+            .build()
+            .unwrap();
+        let (mut server, _, server_events) =
+            LaminarSocket::bind("127.0.0.1:0", config.clone()).unwrap();
+        let server_address = server.local_addr().unwrap();
+        thread::spawn(move || server.start_polling());
+
+        // The client does the handshake by hand, so it can send whatever it wants afterwards.
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut buffer = [0; 1500];
+        let request =
+            HandshakePacket::new(PacketType::ConnectionRequest, ChallengeHeader::default());
+        client
+            .send_to(&request.serialize().unwrap(), server_address)
+            .unwrap();
+        let length = client.recv(&mut buffer).unwrap();
+        let mut cursor = Cursor::new(&buffer[..length]);
+        StandardHeader::read(&mut cursor).unwrap();
+        let challenge = ChallengeHeader::read(&mut cursor).unwrap();
+        let response = HandshakePacket::new(PacketType::ConnectionResponse, challenge);
+        client
+            .send_to(&response.serialize().unwrap(), server_address)
+            .unwrap();
+        client.recv(&mut buffer).unwrap();
+
+        let buffer_pool = BufferPool::new(
+            config.payload_pool_size(),
+            config.receive_buffer_size_bytes(),
+        );
+        let mut connection = VirtualConnection::new(server_address, &config, buffer_pool);
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Connect(_) => {}
+            event => panic!("Expected a connect event, got {:?}", event),
+        }
+
+        // A disconnect packet without the challenge is what anyone could send on our behalf.
+        let mut forged = connection.create_disconnect_packet().unwrap();
+        client.send_to(&forged, server_address).unwrap();
+        forged.extend_from_slice(&[0; 16]);
+        client.send_to(&forged, server_address).unwrap();
+
+        // The connection is still there, so the server hands us the next packet.
+        let processed = connection
+            .process_outgoing(Packet::unreliable(server_address, vec![1]))
+            .unwrap();
+        let mut datagram = Vec::new();
+        processed
+            .write_fragment(0, config.fragment_size_bytes(), &mut datagram)
+            .unwrap();
+        client.send_to(&datagram, server_address).unwrap();
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Packet(packet) => assert_eq!(packet.payload(), &[1]),
+            event => panic!("Expected a packet event, got {:?}", event),
+        }
+
+        connection.complete_handshake(challenge, Instant::now());
+        let disconnect = connection.create_disconnect_packet().unwrap();
+        client.send_to(&disconnect, server_address).unwrap();
+        match server_events.recv_timeout(TIMEOUT).unwrap() {
+            SocketEvent::Disconnect(_) => {}
+            event => panic!("Expected a disconnect event, got {:?}", event),
+        }
+    }
+}
@@ -1,13 +1,62 @@
+mod buffer_pool;
 /// Contains code dealing with Packet headers
 pub mod headers;
 mod packet_type;
 mod processed;
 
+pub use self::buffer_pool::{BufferPool, PooledBuffer};
 pub use self::packet_type::PacketType;
 pub use self::processed::ProcessedPacket;
 
-use crate::net::DeliveryMethod;
-use std::net::SocketAddr;
+use crate::net::{DeliveryMethod, MessageTicket};
+use std::{fmt, net::SocketAddr, ops::Deref, sync::Arc};
+
+/// The raw payload of a packet.
+#[derive(Clone)]
+pub enum Payload {
+    /// A payload handed to us by the application. It is shared, so the copy of a packet that is
+    /// kept around to be sent again until it is acknowledged doesn't copy the payload as well.
+    Shared(Arc<Vec<u8>>),
+    /// A received payload, whose buffer goes back to its pool once the packet is dropped.
+    Pooled(PooledBuffer),
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Payload::Shared(payload) => payload,
+            Payload::Pooled(payload) => payload,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(payload: Vec<u8>) -> Self {
+        Payload::Shared(Arc::new(payload))
+    }
+}
+
+impl From<PooledBuffer> for Payload {
+    fn from(payload: PooledBuffer) -> Self {
+        Payload::Pooled(payload)
+    }
+}
+
+impl PartialEq for Payload {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Payload {}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 /// This is a user friendly packet containing the payload and the endpoint from
@@ -16,9 +65,13 @@ pub struct Packet {
     /// the endpoint from where it came or where to send to.
     address: SocketAddr,
     /// the raw payload of the packet.
-    payload: Box<[u8]>,
+    payload: Payload,
     /// defines on how the packet will be delivered.
     delivery_method: DeliveryMethod,
+    /// the stream on which ordered and sequenced packets are arranged.
+    stream_id: Option<u8>,
+    /// the ticket of a packet that was sent through `SocketHandle::send`.
+    ticket: Option<MessageTicket>,
 }
 
 impl Packet {
@@ -32,10 +85,32 @@ impl Packet {
     ///
     /// Basically just bare UDP, free to be dropped, used for very unnecessary data, great for 'general' position updates.
     pub fn unreliable(address: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet::new(address, payload, DeliveryMethod::UnreliableUnordered, None)
+    }
+
+    /// Unreliable. Packets can be dropped, but older packets are never delivered after newer ones.
+    ///
+    /// **Details**
+    ///
+    /// | Packet Drop      | Packet Duplication  | Packet Order      | Packet Fragmentation | Packet Delivery |
+    /// | :-------------:  | :-------------:     | :-------------:  | :-------------:       | :-------------: |
+    /// |      Yes        |    No               |      Yes          |      No              |       No        |
+    ///
+    /// Basically just bare UDP, free to be dropped, used for very unnecessary data, great for 'general' position updates but packets will be ordered.
+    ///
+    /// Packets are arranged per stream, so packets on one stream will never hold back or cause
+    /// the dropping of packets on another stream. If no `stream_id` is given, the packet is sent
+    /// on the default stream (255).
+    pub fn unreliable_ordered(
+        address: SocketAddr,
+        payload: Vec<u8>,
+        stream_id: Option<u8>,
+    ) -> Packet {
         Packet::new(
             address,
-            payload.into_boxed_slice(),
-            DeliveryMethod::UnreliableUnordered,
+            payload,
+            DeliveryMethod::UnreliableOrdered,
+            stream_id,
         )
     }
 
@@ -50,26 +125,87 @@ impl Packet {
     /// Basically this is almost TCP like without ordering of packets.
     /// Receive every packet and immediately give to application, order does not matter.
     pub fn reliable_unordered(address: SocketAddr, payload: Vec<u8>) -> Packet {
-        Packet::new(
-            address,
-            payload.into_boxed_slice(),
-            DeliveryMethod::ReliableUnordered,
-        )
+        Packet::new(address, payload, DeliveryMethod::ReliableUnordered, None)
+    }
+
+    /// Reliable. All packets will be sent and received, with order.
+    ///
+    /// *Details*
+    ///
+    /// |   Packet Drop   | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
+    /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
+    /// |       No        |      No            |      Yes         |      Yes             |       Yes       |
+    ///
+    /// Basically this is almost has all features TCP has.
+    /// Receive every packet (file downloading for example) in order (any missing keeps the later ones buffered until they are received).
+    ///
+    /// Packets are arranged per stream, so packets on one stream will never hold back or cause
+    /// the dropping of packets on another stream. If no `stream_id` is given, the packet is sent
+    /// on the default stream (255).
+    pub fn reliable_ordered(
+        address: SocketAddr,
+        payload: Vec<u8>,
+        stream_id: Option<u8>,
+    ) -> Packet {
+        Packet::new(address, payload, DeliveryMethod::ReliableOrdered, stream_id)
+    }
+
+    /// Unreliable. Packets can be dropped, but never duplicated and arrive in order.
+    ///
+    /// *Details*
+    ///
+    /// |   Packet Drop   | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
+    /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
+    /// |       Yes       |      No            |      Yes         |      Yes             |       No        |
+    ///
+    /// Toss away any packets that are older than the most recent (like a position update, you don't care about older ones),
+    /// packets may be dropped, just the application may not receive older ones if a newer one came in first.
+    ///
+    /// Packets are arranged per stream, so packets on one stream will never hold back or cause
+    /// the dropping of packets on another stream. If no `stream_id` is given, the packet is sent
+    /// on the default stream (255).
+    pub fn sequenced(address: SocketAddr, payload: Vec<u8>, stream_id: Option<u8>) -> Packet {
+        Packet::new(address, payload, DeliveryMethod::Sequenced, stream_id)
+    }
+
+    /// Unreliable. Packets can be dropped, duplicated or arrive without order, but the sender finds
+    /// out whether they arrived.
+    ///
+    /// *Details*
+    ///
+    /// |   Packet Drop   | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
+    /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
+    /// |       Yes       |      Yes           |      No          |      Yes             |       No        |
+    ///
+    /// The packet is never sent again, but if it is sent through `SocketHandle::send` the returned
+    /// ticket is reported back with a `SocketEvent::Acked` or a `SocketEvent::Lost`. Great for
+    /// snapshots that are delta compressed against the last snapshot the other side received.
+    pub fn unreliable_notified(address: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet::new(address, payload, DeliveryMethod::UnreliableNotified, None)
     }
 
     /// Create an new packet by passing the receiver, data and how this packet should be delivered.
     pub(crate) fn new(
         address: SocketAddr,
-        payload: Box<[u8]>,
+        payload: impl Into<Payload>,
         delivery_method: DeliveryMethod,
+        stream_id: Option<u8>,
     ) -> Self {
         Packet {
             address,
-            payload,
+            payload: payload.into(),
             delivery_method,
+            stream_id,
+            ticket: None,
         }
     }
 
+    /// Attaches the ticket the application uses to follow the delivery of this packet.
+    pub(crate) fn with_ticket(mut self, ticket: MessageTicket) -> Self {
+        self.ticket = Some(ticket);
+        self
+    }
+
     /// Get the payload (raw data) of this packet.
     pub fn payload(&self) -> &[u8] {
         &self.payload
@@ -84,4 +220,18 @@ impl Packet {
     pub fn delivery_method(&self) -> DeliveryMethod {
         self.delivery_method
     }
+
+    /// Get the id of the stream this packet is arranged on.
+    ///
+    /// This is `None` for delivery methods that do not order or sequence packets.
+    pub fn stream_id(&self) -> Option<u8> {
+        self.stream_id
+    }
+
+    /// Get the ticket that was handed out when this packet was sent through `SocketHandle::send`.
+    ///
+    /// This is `None` for packets that were sent without a ticket and for received packets.
+    pub fn ticket(&self) -> Option<MessageTicket> {
+        self.ticket
+    }
 }
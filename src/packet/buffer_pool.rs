@@ -0,0 +1,163 @@
+use std::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// A pool of buffers for the payloads of received packets.
+///
+/// Packets are handed to the application on another thread, so a buffer is returned to the pool
+/// when the packet that holds it is dropped, wherever that happens. Once enough buffers are in
+/// circulation, receiving a packet doesn't allocate anymore.
+///
+/// Only buffers of up to `max_buffer_size` bytes are kept, so the large buffers of reassembled
+/// packets don't stay around once those packets are dropped.
+///
+/// Cloning the pool is cheap, every clone hands out the same buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+    max_buffer_size: usize,
+}
+
+impl BufferPool {
+    /// Creates an empty pool that keeps at most `max_buffers` buffers of at most
+    /// `max_buffer_size` bytes around.
+    pub fn new(max_buffers: usize, max_buffer_size: usize) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_buffers,
+            max_buffer_size,
+        }
+    }
+
+    /// Takes an empty buffer out of the pool, or allocates a new one if the pool is empty.
+    pub fn take(&self) -> PooledBuffer {
+        let buffer = self
+            .buffers
+            .lock()
+            .ok()
+            .and_then(|mut buffers| buffers.pop())
+            .unwrap_or_default();
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Adds a buffer that already holds the payload of a packet to the buffers of this pool, so
+    /// it is kept once the packet is dropped, unless it is larger than the buffers of this pool.
+    pub fn adopt(&self, buffer: Vec<u8>) -> PooledBuffer {
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Get the number of buffers waiting in the pool to be reused.
+    pub fn available(&self) -> usize {
+        self.buffers.lock().map_or(0, |buffers| buffers.len())
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > self.max_buffer_size {
+            return;
+        }
+
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_buffers {
+                buffer.clear();
+                buffers.push(buffer);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("available", &self.available())
+            .field("max_buffers", &self.max_buffers)
+            .field("max_buffer_size", &self.max_buffer_size)
+            .finish()
+    }
+}
+
+/// A buffer that was taken out of a `BufferPool`, which goes back to the pool once it's dropped.
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Clone for PooledBuffer {
+    fn clone(&self) -> Self {
+        let mut clone = self.pool.take();
+        clone.extend_from_slice(&self.buffer);
+        clone
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give_back(mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+
+    #[test]
+    fn reuses_dropped_buffers() {
+        let pool = BufferPool::new(4, 16);
+
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[1, 2, 3]);
+        let address = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pool.available(), 1);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), address);
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn keeps_at_most_max_buffers() {
+        let pool = BufferPool::new(2, 16);
+
+        let buffers: Vec<_> = (0..3).map(|_| pool.adopt(vec![1])).collect();
+        drop(buffers);
+
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn drops_buffers_larger_than_max_buffer_size() {
+        let pool = BufferPool::new(2, 16);
+
+        drop(pool.adopt(vec![1; 17]));
+        assert_eq!(pool.available(), 0);
+
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[1; 16]);
+        drop(buffer);
+        assert_eq!(pool.available(), 1);
+    }
+}
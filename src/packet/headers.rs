@@ -1,12 +1,16 @@
+mod arranging;
+mod challenge;
 mod fragment;
-mod heart_beat;
 mod reliable;
 mod standard;
+mod transfer;
 
+pub use self::arranging::ArrangingHeader;
+pub use self::challenge::ChallengeHeader;
 pub use self::fragment::FragmentHeader;
-pub use self::heart_beat::HeartBeatHeader;
-pub use self::reliable::{ReliableHeader, HEADER_SIZE as RELIABLE_HEADER_SIZE};
+pub use self::reliable::{window_mask, ReliableHeader, MAX_ACK_WINDOW_SIZE};
 pub use self::standard::{StandardHeader, HEADER_SIZE as STANDARD_HEADER_SIZE};
+pub use self::transfer::{TransferAction, TransferHeader};
 
 use std::io;
 
@@ -28,6 +32,22 @@ pub trait HeaderReader {
     fn size(&self) -> usize;
 }
 
+/// Get the largest number of header bytes that can precede the payload in a single datagram.
+pub fn max_header_size() -> usize {
+    *STANDARD_HEADER_SIZE
+        + FragmentHeader::default().size()
+        + ReliableHeader::new(0, 0, MAX_ACK_WINDOW_SIZE).size()
+        + ArrangingHeader::default().size()
+}
+
+/// Get the number of header bytes that precede the data in a chunk of a transfer. Chunks are never
+/// fragmented or arranged, but always carry acknowledgements.
+pub fn transfer_header_size() -> usize {
+    *STANDARD_HEADER_SIZE
+        + ReliableHeader::new(0, 0, MAX_ACK_WINDOW_SIZE).size()
+        + TransferHeader::default().size()
+}
+
 /// Small helper method to statically calculate the written size of a header struct
 fn calc_header_size<T: Default + HeaderWriter>() -> usize {
     let mut buffer: Vec<u8> = Vec::new();
@@ -0,0 +1,89 @@
+use super::{calc_header_size, HeaderReader, HeaderWriter};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use std::io;
+
+lazy_static! {
+    pub static ref HEADER_SIZE: usize = calc_header_size::<ArrangingHeader>();
+}
+
+/// This header provides the information needed to arrange packets (ordering or sequencing) on the
+/// receiving side.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArrangingHeader {
+    // The position of the packet within its ordering or sequencing stream.
+    arranging_id: u16,
+    // The stream this packet is arranged on.
+    stream_id: u8,
+}
+
+impl ArrangingHeader {
+    /// Create new arranging header with the given arranging id and stream id.
+    pub fn new(arranging_id: u16, stream_id: u8) -> Self {
+        Self {
+            arranging_id,
+            stream_id,
+        }
+    }
+
+    /// Get the position of the packet within its stream.
+    #[inline]
+    pub fn arranging_id(&self) -> u16 {
+        self.arranging_id
+    }
+
+    /// Get the id of the stream this packet is arranged on.
+    #[inline]
+    pub fn stream_id(&self) -> u8 {
+        self.stream_id
+    }
+}
+
+impl HeaderWriter for ArrangingHeader {
+    fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.write_u16::<BigEndian>(self.arranging_id)?;
+        buffer.write_u8(self.stream_id)?;
+        Ok(())
+    }
+}
+
+impl HeaderReader for ArrangingHeader {
+    type Header = io::Result<Self>;
+
+    fn read(rdr: &mut io::Cursor<&[u8]>) -> Self::Header {
+        let arranging_id = rdr.read_u16::<BigEndian>()?;
+        let stream_id = rdr.read_u8()?;
+
+        Ok(Self::new(arranging_id, stream_id))
+    }
+
+    /// Get the size of this header.
+    fn size(&self) -> usize {
+        *HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArrangingHeader, HeaderReader, HeaderWriter};
+    use std::io::Cursor;
+
+    #[test]
+    pub fn serialize_deserialize_arranging_header_test() {
+        let arranging_header = ArrangingHeader::new(1234, 5);
+        let mut buffer = Vec::with_capacity(arranging_header.size());
+
+        arranging_header.write(&mut buffer).unwrap();
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let deserialized = ArrangingHeader::read(&mut cursor).unwrap();
+
+        assert_eq!(deserialized.arranging_id(), 1234);
+        assert_eq!(deserialized.stream_id(), 5);
+    }
+
+    #[test]
+    pub fn header_size_test() {
+        assert_eq!(ArrangingHeader::default().size(), 3);
+    }
+}
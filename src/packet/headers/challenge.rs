@@ -0,0 +1,89 @@
+use super::{calc_header_size, HeaderReader, HeaderWriter};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use std::io;
+
+lazy_static! {
+    pub static ref HEADER_SIZE: usize = calc_header_size::<ChallengeHeader>();
+}
+
+/// This header carries the challenge token of a connection handshake.
+///
+/// The token is handed out by the server and has to be echoed by the client before a connection
+/// is created. Every handshake packet carries this header, so a connection request is just as
+/// large as the challenge that answers it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChallengeHeader {
+    // The moment (in milliseconds on the clock of the server) after which the token is rejected.
+    expires_at: u64,
+    // The signature of the server over the remote address and the expiry time.
+    mac: u64,
+}
+
+impl ChallengeHeader {
+    /// Create new challenge header with the given expiry time and signature.
+    pub fn new(expires_at: u64, mac: u64) -> Self {
+        Self { expires_at, mac }
+    }
+
+    /// Get the moment after which this challenge is no longer accepted.
+    #[inline]
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    /// Get the signature of this challenge.
+    #[inline]
+    pub fn mac(&self) -> u64 {
+        self.mac
+    }
+}
+
+impl HeaderWriter for ChallengeHeader {
+    fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.write_u64::<BigEndian>(self.expires_at)?;
+        buffer.write_u64::<BigEndian>(self.mac)?;
+        Ok(())
+    }
+}
+
+impl HeaderReader for ChallengeHeader {
+    type Header = io::Result<Self>;
+
+    fn read(rdr: &mut io::Cursor<&[u8]>) -> Self::Header {
+        let expires_at = rdr.read_u64::<BigEndian>()?;
+        let mac = rdr.read_u64::<BigEndian>()?;
+
+        Ok(Self::new(expires_at, mac))
+    }
+
+    /// Get the size of this header.
+    fn size(&self) -> usize {
+        *HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChallengeHeader, HeaderReader, HeaderWriter};
+    use std::io::Cursor;
+
+    #[test]
+    pub fn serialize_deserialize_challenge_header_test() {
+        let challenge_header = ChallengeHeader::new(1234, 0xDEAD_BEEF);
+        let mut buffer = Vec::with_capacity(challenge_header.size());
+
+        challenge_header.write(&mut buffer).unwrap();
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let deserialized = ChallengeHeader::read(&mut cursor).unwrap();
+
+        assert_eq!(deserialized.expires_at(), 1234);
+        assert_eq!(deserialized.mac(), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    pub fn header_size_test() {
+        assert_eq!(ChallengeHeader::default().size(), 16);
+    }
+}
@@ -8,7 +8,7 @@ lazy_static! {
 }
 
 /// This header represents a fragmented packet header.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub struct FragmentHeader {
     id: u8,
     num_fragments: u8,
@@ -31,15 +31,6 @@ impl FragmentHeader {
     }
 }
 
-impl Default for FragmentHeader {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            num_fragments: 0,
-        }
-    }
-}
-
 impl HeaderWriter for FragmentHeader {
     fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         buffer.write_u8(self.id)?;
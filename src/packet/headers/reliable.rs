@@ -1,26 +1,34 @@
-use super::{calc_header_size, HeaderReader, HeaderWriter};
+use super::{HeaderReader, HeaderWriter};
+use crate::errors::PacketError;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use lazy_static::lazy_static;
 use std::io;
 
-lazy_static! {
-    pub static ref HEADER_SIZE: usize = calc_header_size::<ReliableHeader>();
-}
+/// The largest number of packets, before the last acknowledged one, an ack field can describe.
+pub const MAX_ACK_WINDOW_SIZE: u8 = 128;
 
 /// This header provides reliability information to the packet.
+///
+/// The ack field is written with a length prefix, so both sides of a connection can use a
+/// different ack window size.
 #[derive(Copy, Clone, Debug)]
 pub struct ReliableHeader {
     // This is the last acknowledged sequence number.
     last_acked: u16,
-    // This is a bitfield of all last 32 acknowledged packets.
-    ack_field: u32,
+    // This is a bitfield of the acknowledged packets before the last acknowledged one.
+    ack_field: u128,
+    // This is the number of packets the ack field describes, which is a multiple of 8.
+    window_size: u8,
 }
 
 impl ReliableHeader {
-    pub fn new(last_acked: u16, ack_field: u32) -> Self {
+    /// Creates a header with an ack field describing `window_size` packets. The window size is
+    /// rounded up to a multiple of 8 and bits outside of the window are ignored.
+    pub fn new(last_acked: u16, ack_field: u128, window_size: u8) -> Self {
+        let window_size = window_bytes(window_size) * 8;
         Self {
             last_acked,
-            ack_field,
+            ack_field: ack_field & window_mask(window_size),
+            window_size,
         }
     }
 
@@ -30,23 +38,25 @@ impl ReliableHeader {
         self.last_acked
     }
 
-    /// Get bit field of all last 32 acknowledged packets
+    /// Get bit field of the acknowledged packets before the last acknowledged one.
     #[inline]
-    pub fn ack_field(&self) -> u32 {
+    pub fn ack_field(&self) -> u128 {
         self.ack_field
     }
-}
 
-impl Default for ReliableHeader {
-    fn default() -> Self {
-        Self::new(0, 0)
+    /// Get the number of packets the ack field describes.
+    #[inline]
+    pub fn window_size(&self) -> u8 {
+        self.window_size
     }
 }
 
 impl HeaderWriter for ReliableHeader {
     fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let length = window_bytes(self.window_size);
         buffer.write_u16::<BigEndian>(self.last_acked)?;
-        buffer.write_u32::<BigEndian>(self.ack_field)?;
+        buffer.write_u8(length)?;
+        buffer.write_uint128::<BigEndian>(self.ack_field, length as usize)?;
         Ok(())
     }
 }
@@ -56,13 +66,31 @@ impl HeaderReader for ReliableHeader {
 
     fn read(rdr: &mut io::Cursor<&[u8]>) -> Self::Header {
         let last_acked = rdr.read_u16::<BigEndian>()?;
-        let ack_field = rdr.read_u32::<BigEndian>()?;
+        let length = rdr.read_u8()?;
+        if length == 0 || length > window_bytes(MAX_ACK_WINDOW_SIZE) {
+            return Err(PacketError::InvalidAckField.into());
+        }
+        let ack_field = rdr.read_uint128::<BigEndian>(length as usize)?;
 
-        Ok(Self::new(last_acked, ack_field))
+        Ok(Self::new(last_acked, ack_field, length * 8))
     }
 
     fn size(&self) -> usize {
-        *HEADER_SIZE
+        3 + window_bytes(self.window_size) as usize
+    }
+}
+
+/// Get the number of bytes needed to write an ack field for the given window size.
+fn window_bytes(window_size: u8) -> u8 {
+    window_size.clamp(8, MAX_ACK_WINDOW_SIZE).div_ceil(8)
+}
+
+/// Get a mask of the bits in an ack field that fall within the given window size.
+pub fn window_mask(window_size: u8) -> u128 {
+    if window_size >= MAX_ACK_WINDOW_SIZE {
+        u128::MAX
+    } else {
+        (1 << window_size) - 1
     }
 }
 
@@ -73,7 +101,7 @@ mod tests {
 
     #[test]
     pub fn serialize_deserialize_reliable_header_test() {
-        let packet_header = ReliableHeader::new(1, 5421);
+        let packet_header = ReliableHeader::new(1, 5421, 32);
         let mut buffer = Vec::with_capacity(packet_header.size() + 1);
 
         let _ = packet_header.write(&mut buffer);
@@ -84,13 +112,42 @@ mod tests {
             Ok(packet_deserialized) => {
                 assert_eq!(packet_deserialized.last_acked(), 1);
                 assert_eq!(packet_deserialized.ack_field(), 5421);
+                assert_eq!(packet_deserialized.window_size(), 32);
             }
             Err(e) => println!("{:?}", e),
         }
     }
 
+    #[test]
+    pub fn serialize_deserialize_wide_ack_field() {
+        let ack_field = (1 << 127) | (1 << 64) | 1;
+        let packet_header = ReliableHeader::new(1, ack_field, 128);
+        let mut buffer = Vec::new();
+        packet_header.write(&mut buffer).unwrap();
+
+        let packet_deserialized = ReliableHeader::read(&mut Cursor::new(&buffer[..])).unwrap();
+        assert_eq!(packet_deserialized.ack_field(), ack_field);
+        assert_eq!(packet_deserialized.window_size(), 128);
+    }
+
+    #[test]
+    pub fn rounds_window_size_up_to_whole_bytes() {
+        let packet_header = ReliableHeader::new(1, !0, 60);
+
+        assert_eq!(packet_header.window_size(), 64);
+        assert_eq!(packet_header.ack_field(), u128::from(u64::MAX));
+    }
+
+    #[test]
+    pub fn rejects_oversized_ack_field() {
+        let buffer = [0, 1, 17];
+
+        assert!(ReliableHeader::read(&mut Cursor::new(&buffer[..])).is_err());
+    }
+
     #[test]
     pub fn header_size_test() {
-        assert_eq!(ReliableHeader::default().size(), 6);
+        assert_eq!(ReliableHeader::new(0, 0, 32).size(), 7);
+        assert_eq!(ReliableHeader::new(0, 0, 128).size(), 19);
     }
 }
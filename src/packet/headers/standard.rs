@@ -8,6 +8,9 @@ lazy_static! {
     pub static ref HEADER_SIZE: usize = calc_header_size::<StandardHeader>();
 }
 
+/// The bit of the delivery method byte that tells whether a `ReliableHeader` follows.
+const ACKS_FLAG: u8 = 0x80;
+
 /// This header will be included in each packet, and contains some basic information.
 #[derive(Copy, Clone, Debug)]
 pub struct StandardHeader {
@@ -19,6 +22,8 @@ pub struct StandardHeader {
     delivery_method: DeliveryMethod,
     /// This is the sequence number. This is used for both reliability and fragmentation
     sequence_num: u16,
+    /// specifies whether this packet carries acknowledgements in a `ReliableHeader`.
+    has_acks: bool,
 }
 
 impl StandardHeader {
@@ -33,9 +38,16 @@ impl StandardHeader {
             packet_type,
             delivery_method,
             sequence_num,
+            has_acks: false,
         }
     }
 
+    /// Marks whether a `ReliableHeader` with acknowledgements follows this header.
+    pub fn with_acks(mut self, has_acks: bool) -> Self {
+        self.has_acks = has_acks;
+        self
+    }
+
     #[inline]
     pub fn protocol_version(&self) -> u32 {
         self.protocol_version
@@ -55,6 +67,11 @@ impl StandardHeader {
     pub fn sequence_num(&self) -> u16 {
         self.sequence_num
     }
+
+    #[inline]
+    pub fn has_acks(&self) -> bool {
+        self.has_acks
+    }
 }
 
 impl Default for StandardHeader {
@@ -67,7 +84,9 @@ impl HeaderWriter for StandardHeader {
     fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         buffer.write_u32::<BigEndian>(self.protocol_version)?;
         buffer.write_u8(PacketType::get_id(self.packet_type))?;
-        buffer.write_u8(DeliveryMethod::get_delivery_method_id(self.delivery_method))?;
+        let acks_flag = if self.has_acks { ACKS_FLAG } else { 0 };
+        buffer
+            .write_u8(DeliveryMethod::get_delivery_method_id(self.delivery_method) | acks_flag)?;
         buffer.write_u16::<BigEndian>(self.sequence_num)?;
         Ok(())
     }
@@ -85,8 +104,11 @@ impl HeaderReader for StandardHeader {
         let header = Self {
             protocol_version,
             packet_type: PacketType::get_packet_type(packet_id),
-            delivery_method: DeliveryMethod::get_delivery_method_from_id(delivery_method_id),
+            delivery_method: DeliveryMethod::get_delivery_method_from_id(
+                delivery_method_id & !ACKS_FLAG,
+            ),
             sequence_num,
+            has_acks: delivery_method_id & ACKS_FLAG != 0,
         };
 
         Ok(header)
@@ -123,6 +145,20 @@ mod tests {
             packet_header.delivery_method,
             DeliveryMethod::UnreliableUnordered
         );
+        assert!(!packet_header.has_acks);
+    }
+
+    #[test]
+    pub fn serializes_deserialize_acks_flag() {
+        let packet_header =
+            StandardHeader::new(DeliveryMethod::Sequenced, PacketType::Fragment, 7).with_acks(true);
+        let mut buffer = Vec::new();
+        packet_header.write(&mut buffer).unwrap();
+
+        let packet_header = StandardHeader::read(&mut Cursor::new(buffer.as_slice())).unwrap();
+        assert_eq!(packet_header.delivery_method, DeliveryMethod::Sequenced);
+        assert_eq!(packet_header.sequence_num, 7);
+        assert!(packet_header.has_acks);
     }
 
     #[test]
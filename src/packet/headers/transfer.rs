@@ -0,0 +1,160 @@
+use super::{calc_header_size, HeaderReader, HeaderWriter};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use std::io;
+
+lazy_static! {
+    pub static ref HEADER_SIZE: usize = calc_header_size::<TransferHeader>();
+}
+
+/// What a transfer packet tells the other side.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TransferAction {
+    /// The packet carries the chunk of the message that starts at `offset`.
+    #[default]
+    Chunk = 0,
+    /// The sender of the message gives up on it.
+    SenderCancel = 1,
+    /// The receiver of the message doesn't want the rest of it.
+    ReceiverCancel = 2,
+}
+
+/// This header is the wider counterpart of the `FragmentHeader`, used for messages that are too
+/// large to be sent as a single packet. Chunks are addressed by their byte offset instead of a
+/// fragment id, so a message can be of any size and the chunk size can follow the MTU.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferHeader {
+    // The id the sender gave the transfer, unique within the connection.
+    transfer_id: u32,
+    action: TransferAction,
+    // The position of the chunk within the message.
+    offset: u64,
+    // The size of the whole message.
+    total_size: u64,
+}
+
+impl TransferHeader {
+    /// Create the header of the chunk at `offset` of a message of `total_size` bytes.
+    pub fn chunk(transfer_id: u32, offset: u64, total_size: u64) -> Self {
+        Self {
+            transfer_id,
+            action: TransferAction::Chunk,
+            offset,
+            total_size,
+        }
+    }
+
+    /// Create the header of a packet that cancels the transfer with the given id.
+    pub fn cancel(transfer_id: u32, action: TransferAction) -> Self {
+        Self {
+            transfer_id,
+            action,
+            offset: 0,
+            total_size: 0,
+        }
+    }
+
+    /// Get the id the sender gave the transfer.
+    #[inline]
+    pub fn transfer_id(&self) -> u32 {
+        self.transfer_id
+    }
+
+    /// Get what this packet tells the other side.
+    #[inline]
+    pub fn action(&self) -> TransferAction {
+        self.action
+    }
+
+    /// Get the position of the chunk within the message.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get the size of the whole message.
+    #[inline]
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+}
+
+impl HeaderWriter for TransferHeader {
+    fn write(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.write_u32::<BigEndian>(self.transfer_id)?;
+        buffer.write_u8(self.action as u8)?;
+        buffer.write_u64::<BigEndian>(self.offset)?;
+        buffer.write_u64::<BigEndian>(self.total_size)?;
+        Ok(())
+    }
+}
+
+impl HeaderReader for TransferHeader {
+    type Header = io::Result<Self>;
+
+    fn read(rdr: &mut io::Cursor<&[u8]>) -> Self::Header {
+        let transfer_id = rdr.read_u32::<BigEndian>()?;
+        let action = match rdr.read_u8()? {
+            0 => TransferAction::Chunk,
+            1 => TransferAction::SenderCancel,
+            2 => TransferAction::ReceiverCancel,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unknown transfer action.",
+                ))
+            }
+        };
+        let offset = rdr.read_u64::<BigEndian>()?;
+        let total_size = rdr.read_u64::<BigEndian>()?;
+
+        Ok(Self {
+            transfer_id,
+            action,
+            offset,
+            total_size,
+        })
+    }
+
+    /// Get the size of this header.
+    fn size(&self) -> usize {
+        *HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderReader, HeaderWriter, TransferAction, TransferHeader};
+    use std::io::Cursor;
+
+    #[test]
+    pub fn serialize_deserialize_transfer_header_test() {
+        let transfer_header = TransferHeader::chunk(7, 5_000_000_000, 6_000_000_000);
+        let mut buffer = Vec::with_capacity(transfer_header.size());
+
+        transfer_header.write(&mut buffer).unwrap();
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let deserialized = TransferHeader::read(&mut cursor).unwrap();
+
+        assert_eq!(deserialized, transfer_header);
+        assert_eq!(deserialized.action(), TransferAction::Chunk);
+        assert_eq!(deserialized.offset(), 5_000_000_000);
+    }
+
+    #[test]
+    pub fn rejects_unknown_action() {
+        let mut buffer = Vec::new();
+        TransferHeader::cancel(7, TransferAction::ReceiverCancel)
+            .write(&mut buffer)
+            .unwrap();
+        buffer[4] = 3;
+
+        assert!(TransferHeader::read(&mut Cursor::new(buffer.as_slice())).is_err());
+    }
+
+    #[test]
+    pub fn header_size_test() {
+        assert_eq!(TransferHeader::default().size(), 21);
+    }
+}
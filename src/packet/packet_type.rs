@@ -1,4 +1,3 @@
-
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 /// Id to identify an certain packet type.
 pub enum PacketType {
@@ -10,6 +9,24 @@ pub enum PacketType {
     HeartBeat = 2,
     /// Special packet that disconnects
     Disconnect = 3,
+    /// Handshake packet with which a client asks to connect
+    ConnectionRequest = 4,
+    /// Handshake packet with which a server challenges a client that asked to connect
+    ConnectionChallenge = 5,
+    /// Handshake packet with which a client answers the challenge of a server
+    ConnectionResponse = 6,
+    /// Handshake packet with which a server lets a client know the connection was created
+    ConnectionAccepted = 7,
+    /// Handshake packet with which a server lets a client know its response was not valid
+    ConnectionDenied = 8,
+    /// Special packet that only carries acknowledgements
+    Ack = 9,
+    /// Special packet, padded to a certain size, to find out if datagrams of that size arrive
+    MtuProbe = 10,
+    /// Special packet that lets the sender of a probe know it arrived
+    MtuProbeAck = 11,
+    /// Packet that is part of a large message transfer, see `TransferHeader`
+    Transfer = 12,
     /// Unknown packet type
     Unknown = 255,
 }
@@ -27,7 +44,28 @@ impl PacketType {
             1 => PacketType::Fragment,
             2 => PacketType::HeartBeat,
             3 => PacketType::Disconnect,
+            4 => PacketType::ConnectionRequest,
+            5 => PacketType::ConnectionChallenge,
+            6 => PacketType::ConnectionResponse,
+            7 => PacketType::ConnectionAccepted,
+            8 => PacketType::ConnectionDenied,
+            9 => PacketType::Ack,
+            10 => PacketType::MtuProbe,
+            11 => PacketType::MtuProbeAck,
+            12 => PacketType::Transfer,
             _ => PacketType::Unknown,
         }
     }
+
+    /// Returns whether this packet type is part of the connection handshake.
+    pub fn is_handshake(self) -> bool {
+        matches!(
+            self,
+            PacketType::ConnectionRequest
+                | PacketType::ConnectionChallenge
+                | PacketType::ConnectionResponse
+                | PacketType::ConnectionAccepted
+                | PacketType::ConnectionDenied
+        )
+    }
 }
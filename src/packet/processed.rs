@@ -1,132 +1,164 @@
 use crate::{
     errors::FragmentError,
     packet::headers::{
-        FragmentHeader, HeaderReader, HeaderWriter, ReliableHeader, StandardHeader
+        ArrangingHeader, FragmentHeader, HeaderWriter, ReliableHeader, StandardHeader,
+        TransferHeader,
     },
     packet::{Packet, PacketType},
 };
-use std::{
-    io::{self, Write},
-    net::SocketAddr,
-};
+use std::{io, net::SocketAddr};
 
-/// Wrapper struct to hold the fully serialized packet (includes header data)
+/// A packet together with the headers it needs to be delivered, which is written out as one or
+/// more datagrams right before it is sent.
+///
+/// Nothing is serialized up front: every datagram is written into a buffer supplied by the
+/// caller, so the socket can reuse a single buffer for everything it sends.
 pub struct ProcessedPacket {
     sequence_num: u16,
     packet: Packet,
     reliability: Option<ReliableHeader>,
-    // This will be used by the fragments function. There is likely a more efficient way to handle
-    // fragments.
-    serialized_fragments: Vec<Vec<u8>>,
+    arranging: Option<ArrangingHeader>,
+    transfer: Option<TransferHeader>,
 }
 
 impl ProcessedPacket {
-    pub fn new(sequence_num: u16, packet: Packet, reliability: Option<ReliableHeader>) -> Self {
+    pub fn new(
+        sequence_num: u16,
+        packet: Packet,
+        reliability: Option<ReliableHeader>,
+        arranging: Option<ArrangingHeader>,
+    ) -> Self {
         Self {
             sequence_num,
             packet,
             reliability,
-            serialized_fragments: Vec::new(),
+            arranging,
+            transfer: None,
         }
     }
 
+    /// Marks this packet as part of a large message transfer. Transfer packets are sized to fit a
+    /// single datagram, so they are never fragmented.
+    pub fn with_transfer_header(mut self, transfer: TransferHeader) -> Self {
+        self.transfer = Some(transfer);
+        self
+    }
+
     /// Get the endpoint from this packet.
     pub fn address(&self) -> SocketAddr {
         self.packet.address
     }
 
-    /// Returns an iterator yielding payload fragments
-    pub fn fragments(
-        &mut self,
-        fragment_size: u16,
-        max_fragments: u8,
-    ) -> io::Result<impl Iterator<Item = &[u8]>> {
-        let payload_length = self.packet.payload.len();
-        let num_fragments = total_fragments_needed(payload_length, fragment_size) as u8; /* safe cast max_fragments is u8 */
+    /// Returns the fragment size to send this packet with: `fragment_size`, unless the payload
+    /// doesn't fit in `max_fragments` fragments of that size. That happens to packets that were
+    /// accepted before the path MTU went down, which are split into as few fragments as allowed
+    /// instead. Those fragments are larger than the path MTU, but they still arrive on paths where
+    /// the IP layer fragments them.
+    pub fn fit_fragment_size(&self, fragment_size: u16, max_fragments: u8) -> u16 {
+        if self.transfer.is_some() || max_fragments == 0 {
+            return fragment_size;
+        }
 
-        if num_fragments > max_fragments {
+        let min_fragment_size = self
+            .packet
+            .payload
+            .len()
+            .div_ceil(max_fragments as usize)
+            .min(u16::MAX as usize) as u16;
+        fragment_size.max(min_fragment_size)
+    }
+
+    /// Returns the number of datagrams this packet is sent in when each of them carries at most
+    /// `fragment_size` bytes of the payload, or an error if that takes more than `max_fragments`.
+    pub fn fragment_count(&self, fragment_size: u16, max_fragments: u8) -> io::Result<u8> {
+        if self.transfer.is_some() {
+            return Ok(1);
+        }
+
+        let num_fragments = total_fragments_needed(self.packet.payload.len(), fragment_size);
+        if num_fragments > max_fragments as usize {
             return Err(FragmentError::ExceededMaxFragments.into());
         }
+        // safe cast, the number of fragments is at most max_fragments which is an u8
+        Ok((num_fragments as u8).max(1))
+    }
 
-        if num_fragments <= 1 {
-            self.serialize_unfragmented()?;
+    /// Writes the datagram with the given index, see `fragment_count`, into `buffer`. The buffer
+    /// is cleared first, but keeps its capacity so it can be reused for the next datagram.
+    pub fn write_fragment(
+        &self,
+        fragment_id: u8,
+        fragment_size: u16,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        buffer.clear();
+
+        let num_fragments = total_fragments_needed(self.packet.payload.len(), fragment_size);
+        if num_fragments <= 1 || self.transfer.is_some() {
+            self.write_unfragmented(buffer)
         } else {
-            self.serialize_fragmented(num_fragments, fragment_size)?;
+            self.write_fragmented(fragment_id, num_fragments as u8, fragment_size, buffer)
         }
-
-        Ok(self
-            .serialized_fragments
-            .iter()
-            .map(|fragment| fragment.as_slice()))
     }
 
-    fn serialize_unfragmented(&mut self) -> io::Result<()> {
-        // Calculate the buffer size
-        let standard_header = StandardHeader::new(
-            self.packet.delivery_method,
-            PacketType::Packet,
-            self.sequence_num,
-        );
-
-        let mut buffer_size = standard_header.size();
-        buffer_size += if let Some(reliability_header) = self.reliability {
-            reliability_header.size()
+    fn write_unfragmented(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let packet_type = if self.transfer.is_some() {
+            PacketType::Transfer
         } else {
-            0
+            PacketType::Packet
         };
-        buffer_size += self.packet.payload.len();
+        let standard_header =
+            StandardHeader::new(self.packet.delivery_method, packet_type, self.sequence_num)
+                .with_acks(self.reliability.is_some());
 
-        // Create the buffer and write out the header info plus the payload
-        let mut buffer = Vec::with_capacity(buffer_size);
-        standard_header.write(&mut buffer)?;
+        // Write out the header info plus the payload
+        standard_header.write(buffer)?;
         if let Some(reliability_header) = self.reliability {
-            reliability_header.write(&mut buffer)?;
+            reliability_header.write(buffer)?;
         }
-        buffer.extend(self.packet.payload.iter());
-
-        self.serialized_fragments.push(buffer);
+        if let Some(arranging_header) = self.arranging {
+            arranging_header.write(buffer)?;
+        }
+        if let Some(transfer_header) = self.transfer {
+            transfer_header.write(buffer)?;
+        }
+        buffer.extend_from_slice(&self.packet.payload);
         Ok(())
     }
 
-    fn serialize_fragmented(&mut self, num_fragments: u8, fragment_size: u16) -> io::Result<()> {
+    fn write_fragmented(
+        &self,
+        fragment_id: u8,
+        num_fragments: u8,
+        fragment_size: u16,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
         let standard_header = StandardHeader::new(
             self.packet.delivery_method,
             PacketType::Fragment,
             self.sequence_num,
-        );
-
-        for fragment_id in 0..num_fragments {
-            let fragment_header = FragmentHeader::new(fragment_id, num_fragments);
-            // Calculate the buffer size
-            let mut buffer_size = standard_header.size();
-            buffer_size += fragment_header.size();
-            buffer_size += if let Some(reliability_header) = self.reliability {
-                reliability_header.size()
-            } else {
-                0
-            };
-            buffer_size += fragment_size as usize;
-
-            // Create the buffer and write out the header info plus the payload
-            let mut buffer = Vec::with_capacity(buffer_size);
-            standard_header.write(&mut buffer)?;
-            fragment_header.write(&mut buffer)?;
-            if let Some(reliability_header) = self.reliability {
-                reliability_header.write(&mut buffer)?;
-            }
-            // get start end pos in buffer
-            let start_fragment_pos = (u16::from(fragment_id) * fragment_size) as usize;
-            let mut end_fragment_pos = ((u16::from(fragment_id) + 1) * fragment_size) as usize;
-            // If remaining buffer fits int one packet just set the end position to the length of the packet payload.
-            let payload_length = self.packet.payload.len();
-            if end_fragment_pos > payload_length {
-                end_fragment_pos = payload_length;
-            }
-            let fragment_data = &self.packet.payload[start_fragment_pos..end_fragment_pos];
-            buffer.write_all(fragment_data)?;
-            self.serialized_fragments.push(buffer);
-        }
+        )
+        .with_acks(self.reliability.is_some());
+        let fragment_header = FragmentHeader::new(fragment_id, num_fragments);
 
+        // Write out the header info plus the payload
+        standard_header.write(buffer)?;
+        fragment_header.write(buffer)?;
+        if let Some(reliability_header) = self.reliability {
+            reliability_header.write(buffer)?;
+        }
+        if let Some(arranging_header) = self.arranging {
+            arranging_header.write(buffer)?;
+        }
+        // get start end pos in buffer
+        let start_fragment_pos = fragment_id as usize * fragment_size as usize;
+        let mut end_fragment_pos = (fragment_id as usize + 1) * fragment_size as usize;
+        // If remaining buffer fits int one packet just set the end position to the length of the packet payload.
+        let payload_length = self.packet.payload.len();
+        if end_fragment_pos > payload_length {
+            end_fragment_pos = payload_length;
+        }
+        buffer.extend_from_slice(&self.packet.payload[start_fragment_pos..end_fragment_pos]);
         Ok(())
     }
 }
@@ -136,6 +168,7 @@ impl ProcessedPacket {
 /// For example we have two numbers:
 /// - number 1 = 4000;
 /// - number 2 = 1024;
+///
 /// If you do it the easy way the answer will be 4000/1024 = 3.90625.
 /// But since we care about how how many whole times the number fits in we need the result 4.
 ///
@@ -160,23 +193,23 @@ impl ProcessedPacket {
 ///
 /// So for 4000 bytes we need 4 fragments
 /// [fragment: 1024] [fragment: 1024] [fragment: 1024] [fragment: 928]
-fn total_fragments_needed(payload_length: usize, fragment_size: u16) -> u16 {
-    let payload_length = payload_length as u16;
-    let remainder = if payload_length % fragment_size > 0 {
+fn total_fragments_needed(payload_length: usize, fragment_size: u16) -> usize {
+    let fragment_size = fragment_size as usize;
+    let remainder = if !payload_length.is_multiple_of(fragment_size) {
         1
     } else {
         0
     };
-    ((payload_length / fragment_size) + remainder)
+    (payload_length / fragment_size) + remainder
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        total_fragments_needed, FragmentHeader, HeaderReader, ProcessedPacket, ReliableHeader,
-        StandardHeader,
+        total_fragments_needed, ArrangingHeader, FragmentHeader, PacketType, ProcessedPacket,
+        ReliableHeader, StandardHeader, TransferHeader,
     };
-    use crate::Packet;
+    use crate::{packet::headers::HeaderReader, Packet};
     use std::io::{Cursor, Read};
     use std::net::SocketAddr;
 
@@ -184,19 +217,38 @@ mod tests {
         let address: SocketAddr = "127.0.0.1:9000".parse().unwrap();
         let packet = Packet::unreliable(address, payload);
         let sequence_num = 0;
-        ProcessedPacket::new(sequence_num, packet, reliability)
+        ProcessedPacket::new(sequence_num, packet, reliability, None)
+    }
+
+    fn serialize(
+        processed: &ProcessedPacket,
+        fragment_size: u16,
+        max_fragments: u8,
+    ) -> Vec<Vec<u8>> {
+        let fragment_count = processed
+            .fragment_count(fragment_size, max_fragments)
+            .unwrap();
+        (0..fragment_count)
+            .map(|fragment_id| {
+                let mut buffer = Vec::new();
+                processed
+                    .write_fragment(fragment_id, fragment_size, &mut buffer)
+                    .unwrap();
+                buffer
+            })
+            .collect()
     }
 
     #[test]
     pub fn test_processed_no_fragmentation_no_reliability() {
         let payload = "hello!".as_bytes().to_owned();
-        let mut processed = create_processed(payload.clone(), None);
+        let processed = create_processed(payload.clone(), None);
 
-        let serialized: Vec<&[u8]> = processed.fragments(1024, 10).unwrap().collect();
+        let serialized = serialize(&processed, 1024, 10);
 
         assert_eq!(serialized.len(), 1);
 
-        let message = serialized[0];
+        let message = serialized[0].as_slice();
 
         let mut cursor = Cursor::new(message);
 
@@ -213,20 +265,21 @@ mod tests {
     #[test]
     pub fn test_processed_no_fragmentation_with_reliability() {
         let payload = "hello!".as_bytes().to_owned();
-        let reliable = ReliableHeader::new(1, 5421);
-        let mut processed = create_processed(payload.clone(), Some(reliable));
+        let reliable = ReliableHeader::new(1, 5421, 32);
+        let processed = create_processed(payload.clone(), Some(reliable));
 
-        let serialized: Vec<&[u8]> = processed.fragments(1024, 10).unwrap().collect();
+        let serialized = serialize(&processed, 1024, 10);
 
         assert_eq!(serialized.len(), 1);
 
-        let message = serialized[0];
+        let message = serialized[0].as_slice();
 
         let mut cursor = Cursor::new(message);
 
         // message must have standard header
         let standard_header = StandardHeader::read(&mut cursor).unwrap();
         assert_eq!(standard_header.sequence_num(), 0);
+        assert!(standard_header.has_acks());
 
         // message must have a reliability header
         let reliable_header = ReliableHeader::read(&mut cursor).unwrap();
@@ -242,14 +295,14 @@ mod tests {
     #[test]
     pub fn test_processed_fragmentation_no_reliability() {
         let payload = "hello world!".as_bytes().to_owned();
-        let mut processed = create_processed(payload.clone(), None);
+        let processed = create_processed(payload.clone(), None);
 
-        let serialized: Vec<&[u8]> = processed.fragments(5, 10).unwrap().collect();
+        let serialized = serialize(&processed, 5, 10);
 
         assert_eq!(serialized.len(), 3);
 
         for (index, packet) in serialized.iter().enumerate() {
-            let mut cursor = Cursor::new(*packet);
+            let mut cursor = Cursor::new(packet.as_slice());
             // message must have standard header
             let standard_header = StandardHeader::read(&mut cursor).unwrap();
             assert_eq!(standard_header.sequence_num(), 0);
@@ -269,15 +322,15 @@ mod tests {
     #[test]
     pub fn test_processed_fragmentation_and_reliability() {
         let payload = "hello world!".as_bytes().to_owned();
-        let reliable = ReliableHeader::new(1, 5421);
-        let mut processed = create_processed(payload.clone(), Some(reliable));
+        let reliable = ReliableHeader::new(1, 5421, 32);
+        let processed = create_processed(payload.clone(), Some(reliable));
 
-        let serialized: Vec<&[u8]> = processed.fragments(5, 10).unwrap().collect();
+        let serialized = serialize(&processed, 5, 10);
 
         assert_eq!(serialized.len(), 3);
 
         for (index, packet) in serialized.iter().enumerate() {
-            let mut cursor = Cursor::new(*packet);
+            let mut cursor = Cursor::new(packet.as_slice());
             // message must have standard header
             let standard_header = StandardHeader::read(&mut cursor).unwrap();
             assert_eq!(standard_header.sequence_num(), 0);
@@ -299,6 +352,91 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_processed_fragmentation_with_reliability_and_arranging() {
+        let payload = "hello world!".as_bytes().to_owned();
+        let address: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let packet = Packet::reliable_ordered(address, payload.clone(), Some(1));
+        let reliable = ReliableHeader::new(1, 5421, 32);
+        let arranging = ArrangingHeader::new(7, 1);
+        let processed = ProcessedPacket::new(0, packet, Some(reliable), Some(arranging));
+
+        let serialized = serialize(&processed, 5, 10);
+
+        assert_eq!(serialized.len(), 3);
+
+        for packet in serialized.iter() {
+            let mut cursor = Cursor::new(packet.as_slice());
+            StandardHeader::read(&mut cursor).unwrap();
+            FragmentHeader::read(&mut cursor).unwrap();
+            ReliableHeader::read(&mut cursor).unwrap();
+
+            // message must have an arranging header after the reliability header
+            let arranging_header = ArrangingHeader::read(&mut cursor).unwrap();
+            assert_eq!(arranging_header.arranging_id(), 7);
+        }
+    }
+
+    #[test]
+    pub fn test_processed_transfer_chunk_is_never_fragmented() {
+        let payload = "hello world!".as_bytes().to_owned();
+        let address: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let packet = Packet::reliable_unordered(address, payload.clone());
+        let reliable = ReliableHeader::new(1, 5421, 32);
+        let transfer = TransferHeader::chunk(3, 1200, 5000);
+        let processed =
+            ProcessedPacket::new(0, packet, Some(reliable), None).with_transfer_header(transfer);
+
+        let serialized = serialize(&processed, 5, 1);
+
+        assert_eq!(serialized.len(), 1);
+
+        let mut cursor = Cursor::new(serialized[0].as_slice());
+        let standard_header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(standard_header.packet_type(), PacketType::Transfer);
+        ReliableHeader::read(&mut cursor).unwrap();
+        assert_eq!(TransferHeader::read(&mut cursor).unwrap(), transfer);
+
+        let mut deserialized_message = Vec::new();
+        cursor.read_to_end(&mut deserialized_message).unwrap();
+        assert_eq!(payload, deserialized_message);
+    }
+
+    #[test]
+    pub fn test_processed_reuses_buffer_for_every_fragment() {
+        let processed = create_processed(vec![7; 3000], None);
+        assert_eq!(processed.fragment_count(1024, 2).ok(), None);
+        assert_eq!(processed.fragment_count(1024, 3).unwrap(), 3);
+
+        let mut buffer = Vec::with_capacity(2048);
+        let buffer_address = buffer.as_ptr();
+        for fragment_id in 0..3 {
+            processed
+                .write_fragment(fragment_id, 1024, &mut buffer)
+                .unwrap();
+
+            let mut cursor = Cursor::new(buffer.as_slice());
+            StandardHeader::read(&mut cursor).unwrap();
+            let fragment_header = FragmentHeader::read(&mut cursor).unwrap();
+            assert_eq!(fragment_header.id(), fragment_id);
+            assert!(buffer[cursor.position() as usize..]
+                .iter()
+                .all(|&byte| byte == 7));
+        }
+        assert_eq!(buffer.as_ptr(), buffer_address);
+    }
+
+    #[test]
+    pub fn test_processed_fits_fragment_size_to_max_fragments() {
+        let processed = create_processed(vec![7; 3000], None);
+
+        assert_eq!(processed.fit_fragment_size(1024, 3), 1024);
+        // The path MTU went down after this packet was accepted.
+        let fragment_size = processed.fit_fragment_size(500, 3);
+        assert_eq!(fragment_size, 1000);
+        assert_eq!(processed.fragment_count(fragment_size, 3).unwrap(), 3);
+    }
+
     #[test]
     pub fn total_fragments_needed_test() {
         let fragment_number = total_fragments_needed(4000, 1024);
@@ -3,5 +3,5 @@ mod reassembly_data;
 mod sequence_buffer_structure;
 
 pub use self::congestion_data::CongestionData;
-pub use self::reassembly_data::ReassemblyData;
+pub use self::reassembly_data::{ReassemblyData, MAX_FRAGMENTS_DEFAULT};
 pub use self::sequence_buffer_structure::SequenceBuffer;
@@ -1,36 +1,70 @@
+use std::time::Instant;
+
 /// Default max number of fragments to size
 pub const MAX_FRAGMENTS_DEFAULT: u16 = 16;
 
 #[derive(Clone)]
 /// This contains the information required to reassemble fragments.
 pub struct ReassemblyData {
-    pub sequence: u16,
     pub num_fragments_received: u8,
     pub num_fragments_total: u8,
-    pub buffer: Vec<u8>,
+    /// The payload of each fragment, indexed by fragment id.
+    pub fragments: Vec<Vec<u8>>,
     pub fragments_received: [bool; MAX_FRAGMENTS_DEFAULT as usize],
+    /// The time at which the first fragment of this packet arrived.
+    pub created_at: Instant,
 }
 
 impl ReassemblyData {
-    pub fn new(sequence: u16, num_fragments_total: u8, prealloc: usize) -> Self {
+    pub fn new(num_fragments_total: u8, created_at: Instant) -> Self {
         Self {
-            sequence,
             num_fragments_received: 0,
             num_fragments_total,
-            buffer: Vec::with_capacity(prealloc),
+            fragments: vec![Vec::new(); num_fragments_total as usize],
             fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
+            created_at,
+        }
+    }
+
+    /// Stores the payload of the fragment with the given id.
+    ///
+    /// Returns `false` if this fragment was already received, in which case the payload is ignored.
+    pub fn insert_fragment(&mut self, id: u8, payload: &[u8]) -> bool {
+        let index = id as usize;
+        if self.fragments_received[index] {
+            return false;
+        }
+
+        self.fragments[index].extend_from_slice(payload);
+        self.fragments_received[index] = true;
+        self.num_fragments_received += 1;
+        true
+    }
+
+    /// Checks if all fragments of this packet have been received.
+    pub fn is_complete(&self) -> bool {
+        self.num_fragments_received == self.num_fragments_total
+    }
+
+    /// Joins the received fragments, in order, into the original payload.
+    pub fn assemble(&mut self) -> Vec<u8> {
+        let length = self.fragments.iter().map(Vec::len).sum();
+        let mut payload = Vec::with_capacity(length);
+        for fragment in self.fragments.drain(..) {
+            payload.extend(fragment);
         }
+        payload
     }
 }
 
 impl Default for ReassemblyData {
     fn default() -> Self {
         Self {
-            sequence: 0,
             num_fragments_received: 0,
             num_fragments_total: 0,
-            buffer: Vec::with_capacity(1024),
+            fragments: Vec::new(),
             fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
+            created_at: Instant::now(),
         }
     }
 }
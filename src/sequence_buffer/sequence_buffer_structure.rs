@@ -6,7 +6,8 @@ where
     T: Default + Clone + Send + Sync,
 {
     entries: Vec<T>,
-    entry_sequences: Vec<u16>,
+    // `None` marks an empty slot, every sequence number can be stored.
+    entry_sequences: Vec<Option<u16>>,
 }
 
 impl<T> SequenceBuffer<T>
@@ -19,7 +20,7 @@ where
         let mut entry_sequences = Vec::with_capacity(size);
 
         entries.resize(size, T::default());
-        entry_sequences.resize(size, 0xFFFF);
+        entry_sequences.resize(size, None);
 
         SequenceBuffer {
             entries,
@@ -27,11 +28,22 @@ where
         }
     }
 
+    /// Get entry from collection by sequence number.
+    pub fn get(&self, sequence: u16) -> Option<&T> {
+        let index = self.index(sequence);
+
+        if self.entry_sequences[index] != Some(sequence) {
+            return None;
+        }
+
+        Some(&self.entries[index])
+    }
+
     /// Get mutable entry from collection by sequence number.
     pub fn get_mut(&mut self, sequence: u16) -> Option<&mut T> {
         let index = self.index(sequence);
 
-        if self.entry_sequences[index] != sequence {
+        if self.entry_sequences[index] != Some(sequence) {
             return None;
         }
 
@@ -43,7 +55,7 @@ where
         let index = self.index(sequence);
 
         self.entries[index] = data;
-        self.entry_sequences[index] = sequence;
+        self.entry_sequences[index] = Some(sequence);
 
         &mut self.entries[index]
     }
@@ -53,13 +65,24 @@ where
         // TODO: validity check
         let index = self.index(sequence);
         self.entries[index] = T::default();
-        self.entry_sequences[index] = 0xFFFF;
+        self.entry_sequences[index] = None;
+    }
+
+    /// Removes every entry for which `keep` returns `false`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        for (entry, sequence) in self.entries.iter_mut().zip(&mut self.entry_sequences) {
+            if sequence.is_some() && !keep(entry) {
+                *entry = T::default();
+                *sequence = None;
+            }
+        }
     }
 
     /// checks if an certain entry exists.
+    #[cfg(test)]
     pub fn exists(&self, sequence: u16) -> bool {
         let index = self.index(sequence);
-        if self.entry_sequences[index] != sequence {
+        if self.entry_sequences[index] != Some(sequence) {
             return false;
         }
 
@@ -100,6 +123,25 @@ mod tests {
         assert!(!fragment_buffer.exists(1));
     }
 
+    #[test]
+    fn highest_sequence_is_not_an_empty_slot_test() {
+        let mut fragment_buffer: SequenceBuffer<DataStub> = SequenceBuffer::with_capacity(2);
+        assert!(!fragment_buffer.exists(0xFFFF));
+        fragment_buffer.insert(DataStub, 0xFFFF);
+        fragment_buffer.remove(0xFFFF);
+        assert!(fragment_buffer.get(0xFFFF).is_none());
+    }
+
+    #[test]
+    fn retain_fragment_buffer_test() {
+        let mut fragment_buffer = SequenceBuffer::with_capacity(4);
+        fragment_buffer.insert(1u8, 1);
+        fragment_buffer.insert(2u8, 2);
+        fragment_buffer.retain(|entry| *entry != 1);
+        assert!(!fragment_buffer.exists(1));
+        assert!(fragment_buffer.exists(2));
+    }
+
     #[test]
     fn fragment_buffer_len_test() {
         let mut fragment_buffer = SequenceBuffer::with_capacity(2);